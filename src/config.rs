@@ -548,6 +548,8 @@ pub struct HtmlConfig {
     pub playground: Playground,
     /// Code settings.
     pub code: Code,
+    /// Markdown rendering settings.
+    pub markdown: Markdown,
     /// Print settings.
     pub print: Print,
     /// Don't render section labels.
@@ -584,6 +586,9 @@ pub struct HtmlConfig {
     /// The mapping from old pages to new pages/URLs to use when generating
     /// redirects.
     pub redirect: HashMap<String, String>,
+    /// Describe each chapter with the beginning of its first paragraph
+    /// instead of the book description. Default: `false`.
+    pub page_descriptions: bool,
 }
 
 impl Default for HtmlConfig {
@@ -602,6 +607,7 @@ impl Default for HtmlConfig {
             fold: Fold::default(),
             playground: Playground::default(),
             code: Code::default(),
+            markdown: Markdown::default(),
             print: Print::default(),
             no_section_label: false,
             search: None,
@@ -613,6 +619,7 @@ impl Default for HtmlConfig {
             cname: None,
             live_reload_endpoint: None,
             redirect: HashMap::new(),
+            page_descriptions: false,
         }
     }
 }
@@ -641,6 +648,11 @@ pub struct Print {
     pub enable: bool,
     /// Insert page breaks between chapters. Default: `true`.
     pub page_break: bool,
+    /// The markup used for the page breaks. Default: `inline`.
+    pub page_break_style: PageBreakStyle,
+    /// List the footnotes of all chapters at the end of the book. Default:
+    /// `false`.
+    pub endnotes: bool,
 }
 
 impl Default for Print {
@@ -648,10 +660,22 @@ impl Default for Print {
         Self {
             enable: true,
             page_break: true,
+            page_break_style: PageBreakStyle::Inline,
+            endnotes: false,
         }
     }
 }
 
+/// The markup of the page breaks between chapters on the print page.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PageBreakStyle {
+    /// A `<div>` with inline `break-before` styles.
+    Inline,
+    /// A `<div class="page-break">`, styled by the theme's print CSS.
+    Class,
+}
+
 /// Configuration for how to fold chapters of sidebar.
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(default, rename_all = "kebab-case")]
@@ -699,6 +723,424 @@ impl Default for Playground {
 pub struct Code {
     /// A prefix string to hide lines per language (one or more chars).
     pub hidelines: HashMap<String, String>,
+    /// Prefixes of code block attributes (like `hidelines=` or `editable`)
+    /// that are only meant for mdBook's own processing and are removed from
+    /// the class list of the rendered code block. Default: `[]`.
+    pub consumed_attributes: Vec<String>,
+    /// Emit the consumed attributes as `data-` attributes on the code block
+    /// instead of dropping them. Default: `false`.
+    pub consumed_attributes_as_data: bool,
+}
+
+/// Configuration for tweaking how the HTML renderer turns Markdown into HTML.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Markdown {
+    /// Render each footnote inline as a sidenote next to its reference
+    /// instead of collecting the definitions at the bottom of the page.
+    /// Default: `false`.
+    pub sidenotes: bool,
+    /// Names of HTML comment markers (like `<!-- toc -->`) that are turned
+    /// into `<div data-mdbook-directive="toc"></div>` elements, so that they
+    /// survive minification and can be targeted by scripts.
+    pub comment_directives: Vec<String>,
+    /// Warn about images without alternative text. Default: `false`.
+    pub warn_missing_alt_text: bool,
+    /// Warn about relative image paths that don't point to an existing file.
+    /// Default: `false`.
+    pub warn_missing_images: bool,
+    /// The deepest heading level that gets an ID and a header link. Deeper
+    /// headings are rendered as plain headings. Default: `6`.
+    pub max_id_heading_level: u8,
+    /// Render images with a title that stand in a paragraph of their own as
+    /// a `<figure>` with the title as caption. Default: `false`.
+    pub captioned_figures: bool,
+    /// Wrap two or more consecutive paragraphs that each only consist of an
+    /// image in a `<div class="gallery">`. Default: `false`.
+    pub image_galleries: bool,
+    /// IDs used by the theme that generated heading IDs must not collide
+    /// with. Default: `[]`.
+    pub reserved_ids: Vec<String>,
+    /// Open links pointing outside of the book in a new tab. Default: `false`.
+    pub external_links_new_tab: bool,
+    /// Values of the `rel` attribute of links pointing outside of the book,
+    /// like `nofollow` or `ugc`. Default: `[]`.
+    pub external_link_rel: Vec<String>,
+    /// Render a `{hreflang=fr}` marker right after a link as the `hreflang`
+    /// attribute of the link. Default: `false`.
+    pub hreflang_links: bool,
+    /// The URL schemes that autolinks like `<https://example.com>` are
+    /// rendered as links for. Other autolinks are rendered as text. Email
+    /// autolinks use the `mailto` scheme. Default: `None`, which renders
+    /// autolinks with any scheme as links.
+    pub autolink_schemes: Option<Vec<String>>,
+    /// The character that replaces whitespace in generated heading IDs and
+    /// separates the counter of duplicate IDs. Default: `'-'`.
+    pub id_separator: char,
+    /// Lowercase ASCII letters in generated heading IDs. Other letters always
+    /// keep their case. Default: `true`.
+    pub lowercase_ids: bool,
+    /// Spell accented Latin letters in generated heading IDs with their ASCII
+    /// base letters, so `Über` becomes `uber`. Default: `false`.
+    pub transliterate_ids: bool,
+    /// How emoji in headings are spelled in generated heading IDs.
+    /// Default: `strip`.
+    pub emoji_ids: EmojiIds,
+    /// Add `width` and `height` attributes to local PNG, JPEG, GIF, WebP, and
+    /// AVIF images, read from the image files. Default: `false`.
+    pub image_dimensions: bool,
+    /// The file extensions of the images that `image-dimensions` reads.
+    /// Default: `["png", "jpg", "jpeg", "gif", "webp", "avif"]`.
+    pub image_extensions: Vec<String>,
+    /// Render local images that have a dark variant next to them, like
+    /// `diagram.dark.png` for `diagram.png`, as a `<picture>` that shows the
+    /// dark variant when the reader prefers a dark color scheme.
+    /// Default: `false`.
+    pub dark_image_variants: bool,
+    /// Limit the width of images without a `width`, `height` or `style` to
+    /// this CSS length, like `100%`, with an inline style. Default: `None`.
+    pub max_image_width: Option<String>,
+    /// How references to footnotes are displayed. Default: `superscript`.
+    pub footnote_style: FootnoteStyle,
+    /// List the footnotes of each section, up to the next `<h2>`, at the end
+    /// of that section instead of the end of the page, numbering them per
+    /// section. Default: `false`.
+    pub footnote_sections: bool,
+    /// Render consecutive references to the same footnote, with nothing in
+    /// between, as a single reference. Default: `false`.
+    pub collapse_footnote_references: bool,
+    /// Render inline footnotes written as `^[text]` like footnotes with a
+    /// separate definition. Default: `false`.
+    pub inline_footnotes: bool,
+    /// The prefix of the class naming the language of a code block.
+    /// Default: `language-`.
+    pub code_class_prefix: String,
+    /// Guess the language of fenced code blocks without one from keywords in
+    /// their code, like `fn` and `let` for Rust. Default: `false`.
+    pub detect_code_languages: bool,
+    /// The share of the keywords found in a code block that must belong to
+    /// one language for `detect-code-languages` to pick it, between `0.0`
+    /// and `1.0`. Default: `0.8`.
+    pub detect_code_languages_confidence: f64,
+    /// What to do with code blocks without any content. Default: `keep`.
+    pub empty_code_blocks: EmptyCodeBlocks,
+    /// The text shown in empty code blocks with `empty-code-blocks =
+    /// "placeholder"`. Default: `(empty)`.
+    pub empty_code_block_placeholder: String,
+    /// Wrap fenced code blocks in a `<div class="code-block">` with a copy
+    /// button for the theme to wire up. Default: `false`.
+    pub code_copy_buttons: bool,
+    /// The languages of the code blocks that don't get a copy button with
+    /// `code-copy-buttons`, like `console`. Default: `[]`.
+    pub code_copy_buttons_exclude: Vec<String>,
+    /// The class of the `<hr>` elements that thematic breaks like `---` are
+    /// rendered as. Default: `None`.
+    pub thematic_break_class: Option<String>,
+    /// How the links to headings are rendered. Default: `whole-heading`.
+    pub header_links: HeaderLinkMode,
+    /// Percent-encode the IDs in the links to headings, like `#%E4%B8%AD`,
+    /// while the `id` attributes keep the raw characters. Default: `false`.
+    pub percent_encode_header_links: bool,
+    /// Add an empty `<span>` with the ID that GitHub generates for a heading
+    /// to headings whose ID differs from it, so that links to the heading
+    /// written for GitHub keep working. Default: `false`.
+    pub github_anchor_aliases: bool,
+    /// Start generated heading IDs with `sec-` and the number of the section
+    /// on the page, like `sec-1-2-title`. Default: `false`.
+    pub section_number_ids: bool,
+    /// Wrap every level two heading and everything up to the next one in a
+    /// `<section>` labelled by the heading's ID. Default: `false`.
+    pub heading_sections: bool,
+    /// Render task list checkboxes enabled, with a `data-task-index`
+    /// attribute, so that scripts can make them interactive.
+    /// Default: `false`.
+    pub interactive_tasklists: bool,
+    /// Wrap abbreviations defined with `*[HTML]: HyperText Markup Language`
+    /// in `<abbr>` elements with the definition as title. Default: `false`.
+    pub abbreviations: bool,
+    /// Render text written as `==text==` as `<mark>text</mark>`.
+    /// Default: `false`.
+    pub highlights: bool,
+    /// Render text written as `~text~` as subscript and `^text^` as
+    /// superscript. Default: `false`.
+    pub subscript_superscript: bool,
+    /// The element that `~~text~~` is rendered as. Default: `del`.
+    pub strikethrough_element: StrikethroughElement,
+    /// Wrap ISO 8601 dates like `2024-01-15` in `<time>` elements.
+    /// Default: `false`.
+    pub time_elements: bool,
+    /// The URL that references to issues like `#123` are linked to, with
+    /// `{n}` replaced by the issue number. Default: `None`.
+    pub issue_link_template: Option<String>,
+    /// Render block quotes whose first line starts with `[!details]` as
+    /// collapsible `<details>` elements. Default: `false`.
+    pub details_blockquotes: bool,
+    /// Give paragraphs that end with `{#id}` that ID, so that they can be
+    /// linked to. Default: `false`.
+    pub paragraph_ids: bool,
+    /// Give paragraphs the sequential IDs `p-1`, `p-2`, and so on, so that
+    /// every paragraph can be linked to. Default: `false`.
+    pub sequential_paragraph_ids: bool,
+    /// The title or ID of the heading whose section is the only one where
+    /// `sequential-paragraph-ids` numbers paragraphs. Default: `None`.
+    pub sequential_paragraph_ids_section: Option<String>,
+    /// The opening and closing primary quotes, followed by the opening and
+    /// closing secondary quotes, that straight quotes are replaced with. This
+    /// disables the quotes, dashes, and ellipses of `smart-punctuation`.
+    /// Default: `None`.
+    pub quotes: Option<[String; 4]>,
+    /// Keep only one `<h1>` per page by turning every following level one
+    /// heading into a level two heading. Default: `false`.
+    pub single_h1: bool,
+    /// With `single-h1`, treat the page title as the first `<h1>`, so every
+    /// level one heading in the chapter is turned into a level two heading.
+    /// Default: `false`.
+    pub page_title_is_h1: bool,
+    /// The class of the `<div>` wrapping every table. Default: `table-wrapper`.
+    pub table_wrapper_class: String,
+    /// If set, tables get an inner wrapper `<div>` with this class, for
+    /// example to style scroll shadows. Default: `None`.
+    pub table_scroll_class: Option<String>,
+    /// The name, without extension, of the page that links to a directory
+    /// or to its `README.md` or `index.md` point to. Default: `index`.
+    pub index_page: String,
+    /// What to do with links to pages that aren't part of the book.
+    /// Default: `ignore`.
+    pub missing_page_links: MissingPageLinks,
+    /// How links to other pages of the book are written. Default: `html`.
+    pub internal_link_style: InternalLinkStyle,
+    /// Render key sequences like `[[Ctrl+C]]` as `<kbd>` elements.
+    /// Default: `false`.
+    pub keyboard_keys: bool,
+    /// Turn wiki-style links like `[[Getting Started]]` into links to the
+    /// page or heading with that title. Default: `false`.
+    pub wiki_links: bool,
+    /// Strip YAML or TOML front matter from the beginning of pages, and
+    /// expose it to the template as `front_matter`. Default: `false`.
+    pub front_matter: bool,
+    /// Remove the elements and attributes of raw HTML that aren't allowed,
+    /// along with scripts, event handlers and `javascript:` URLs.
+    /// Default: `false`.
+    pub sanitize_html: bool,
+    /// The elements of raw HTML that `sanitize-html` keeps. Default: common
+    /// formatting elements, like `strong`, `a` and `table`.
+    pub sanitize_html_tags: Vec<String>,
+    /// The attributes of raw HTML that `sanitize-html` keeps. Default:
+    /// common attributes, like `href`, `class` and `id`.
+    pub sanitize_html_attributes: Vec<String>,
+}
+
+impl Default for Markdown {
+    fn default() -> Markdown {
+        // Please update the documentation of `Markdown` when changing values!
+        Markdown {
+            sidenotes: false,
+            comment_directives: Vec::new(),
+            warn_missing_alt_text: false,
+            warn_missing_images: false,
+            max_id_heading_level: 6,
+            captioned_figures: false,
+            image_galleries: false,
+            reserved_ids: Vec::new(),
+            external_links_new_tab: false,
+            hreflang_links: false,
+            external_link_rel: Vec::new(),
+            autolink_schemes: None,
+            id_separator: '-',
+            lowercase_ids: true,
+            transliterate_ids: false,
+            emoji_ids: EmojiIds::Strip,
+            image_dimensions: false,
+            image_extensions: ["png", "jpg", "jpeg", "gif", "webp", "avif"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            dark_image_variants: false,
+            max_image_width: None,
+            footnote_style: FootnoteStyle::Superscript,
+            footnote_sections: false,
+            collapse_footnote_references: false,
+            inline_footnotes: false,
+            code_class_prefix: "language-".to_string(),
+            detect_code_languages: false,
+            detect_code_languages_confidence: 0.8,
+            empty_code_blocks: EmptyCodeBlocks::Keep,
+            empty_code_block_placeholder: "(empty)".to_string(),
+            code_copy_buttons: false,
+            code_copy_buttons_exclude: Vec::new(),
+            thematic_break_class: None,
+            header_links: HeaderLinkMode::WholeHeading,
+            percent_encode_header_links: false,
+            github_anchor_aliases: false,
+            section_number_ids: false,
+            heading_sections: false,
+            interactive_tasklists: false,
+            abbreviations: false,
+            highlights: false,
+            subscript_superscript: false,
+            strikethrough_element: StrikethroughElement::Del,
+            time_elements: false,
+            issue_link_template: None,
+            details_blockquotes: false,
+            paragraph_ids: false,
+            sequential_paragraph_ids: false,
+            sequential_paragraph_ids_section: None,
+            quotes: None,
+            single_h1: false,
+            page_title_is_h1: false,
+            table_wrapper_class: "table-wrapper".to_string(),
+            table_scroll_class: None,
+            index_page: "index".to_string(),
+            missing_page_links: MissingPageLinks::Ignore,
+            internal_link_style: InternalLinkStyle::Html,
+            keyboard_keys: false,
+            wiki_links: false,
+            front_matter: false,
+            sanitize_html: false,
+            sanitize_html_tags: [
+                "a",
+                "abbr",
+                "b",
+                "blockquote",
+                "br",
+                "caption",
+                "cite",
+                "code",
+                "dd",
+                "del",
+                "details",
+                "div",
+                "dl",
+                "dt",
+                "em",
+                "figcaption",
+                "figure",
+                "h1",
+                "h2",
+                "h3",
+                "h4",
+                "h5",
+                "h6",
+                "hr",
+                "i",
+                "img",
+                "ins",
+                "kbd",
+                "li",
+                "mark",
+                "ol",
+                "p",
+                "pre",
+                "q",
+                "s",
+                "samp",
+                "small",
+                "span",
+                "strong",
+                "sub",
+                "summary",
+                "sup",
+                "table",
+                "tbody",
+                "td",
+                "tfoot",
+                "th",
+                "thead",
+                "time",
+                "tr",
+                "u",
+                "ul",
+                "var",
+            ]
+            .map(String::from)
+            .to_vec(),
+            sanitize_html_attributes: [
+                "align", "alt", "cite", "class", "colspan", "datetime", "dir", "height", "href",
+                "id", "lang", "name", "open", "rowspan", "src", "start", "title", "width",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+/// What to do with links to pages that aren't part of the book.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MissingPageLinks {
+    /// Leave the links as they are.
+    Ignore,
+    /// Leave the links as they are, but emit a warning.
+    Warn,
+    /// Point the links at the 404 page.
+    Redirect,
+}
+
+/// How links to other pages of the book are written.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InternalLinkStyle {
+    /// Link to the HTML file of the page, like `chapter/page.html#anchor`.
+    Html,
+    /// Link to the page without an extension and with a trailing slash, like
+    /// `chapter/page/#anchor`, and to index pages by their directory, like
+    /// `chapter/`.
+    TrailingSlash,
+}
+
+/// How emoji in headings are spelled in generated heading IDs.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EmojiIds {
+    /// Leave the emoji out, so `🚀 Launch` gets the ID `launch`.
+    Strip,
+    /// Spell common emoji with their name, so `🚀 Launch` gets the ID
+    /// `rocket-launch`. Other emoji are left out.
+    Shortcode,
+}
+
+/// How references to footnotes are displayed.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FootnoteStyle {
+    /// A superscript number, like <sup>1</sup>.
+    Superscript,
+    /// A number in brackets on the baseline, like \[1\].
+    Bracketed,
+}
+
+/// How the links to headings are rendered.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HeaderLinkMode {
+    /// The whole heading text is a link to the heading.
+    WholeHeading,
+    /// The heading text is followed by a small `#` link to the heading.
+    IconOnly,
+    /// The heading only gets an ID, without any link.
+    None,
+}
+
+/// What to do with code blocks without any content.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EmptyCodeBlocks {
+    /// Render them as empty code blocks.
+    Keep,
+    /// Leave them out of the page.
+    Drop,
+    /// Fill them with the placeholder text.
+    Placeholder,
+}
+
+/// The element that struck through text is rendered as.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StrikethroughElement {
+    /// `<del>`, for text that was removed from the document.
+    Del,
+    /// `<s>`, for text that is no longer accurate or relevant.
+    S,
 }
 
 /// Configuration of the search functionality of the HTML renderer.
@@ -1319,6 +1761,15 @@ mod tests {
         let html_config = got.html_config().unwrap();
         assert!(html_config.print.enable);
         assert!(!html_config.print.page_break);
+        assert_eq!(html_config.print.page_break_style, PageBreakStyle::Inline);
+        let src = r#"
+        [output.html.print]
+        page-break-style = "class"
+        "#;
+        let got = Config::from_str(src).unwrap();
+        let html_config = got.html_config().unwrap();
+        assert!(html_config.print.page_break);
+        assert_eq!(html_config.print.page_break_style, PageBreakStyle::Class);
     }
 
     #[test]
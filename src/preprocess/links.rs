@@ -1,17 +1,25 @@
 use crate::errors::*;
+use crate::utils::data::{parse_yaml, value_at_path, value_to_text};
 use crate::utils::{
-    take_anchored_lines, take_lines, take_rustdoc_include_anchored_lines,
-    take_rustdoc_include_lines,
+    expand_tabs, find_version_section, is_safe_url, new_cmark_parser, special_escape,
+    strip_comment_lines, strip_hidden_lines, take_anchored_lines, take_lines,
+    take_markdown_section, take_nth_anchored_lines, take_rustdoc_include_anchored_lines,
+    take_rustdoc_include_lines, take_rustdoc_include_nth_anchored_lines, trim_blank_lines,
+    trim_trailing_whitespace,
 };
 use regex::{CaptureMatches, Captures, Regex};
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
 use std::fs;
 use std::ops::{Bound, Range, RangeBounds, RangeFrom, RangeFull, RangeTo};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use super::{Preprocessor, PreprocessorContext};
 use crate::book::{Book, BookItem};
 use log::{error, warn};
 use once_cell::sync::Lazy;
+use pulldown_cmark::{Event, Tag};
 
 const ESCAPE_CHAR: char = '\\';
 const MAX_LINK_NESTED_DEPTH: usize = 10;
@@ -24,17 +32,149 @@ const MAX_LINK_NESTED_DEPTH: usize = 10;
 ///.  specified or the lines between specified anchors, and include the rest of the file behind `#`.
 ///   This hides the lines from initial display but shows them when the reader expands the code
 ///   block and provides them to Rustdoc for testing.
+/// - `{{# include_svg}}` - Insert an SVG image inline, with scripts removed.
+/// - `{{# include_tree}}` - Insert a list of the files in a directory.
 /// - `{{# playground}}` - Insert runnable Rust files
 /// - `{{# title}}` - Override \<title\> of a webpage.
+///
+/// Further directives can be added with a [`DirectiveRegistry`].
 #[derive(Default)]
-pub struct LinkPreprocessor;
+pub struct LinkPreprocessor {
+    directives: DirectiveRegistry,
+}
 
 impl LinkPreprocessor {
     pub(crate) const NAME: &'static str = "links";
 
     /// Create a new `LinkPreprocessor`.
     pub fn new() -> Self {
-        LinkPreprocessor
+        LinkPreprocessor::default()
+    }
+
+    /// Create a new `LinkPreprocessor` that also expands the custom
+    /// directives of the `registry`.
+    pub fn with_directives(registry: DirectiveRegistry) -> Self {
+        LinkPreprocessor {
+            directives: registry,
+        }
+    }
+}
+
+/// The names of the directives expanded by the [`LinkPreprocessor`] itself.
+const BUILTIN_DIRECTIVES: &[&str] = &[
+    "include",
+    "include_code",
+    "rustdoc_include",
+    "include_svg",
+    "include_tree",
+    "playground",
+    "playpen",
+    "title",
+    "raw",
+];
+
+/// A function expanding a custom directive like `{{#name args}}`, given the
+/// `args` and the context of the chapter it is used in.
+pub type DirectiveHandler = dyn Fn(&str, &DirectiveCtx<'_>) -> Result<String> + Send + Sync;
+
+/// The context a custom directive is expanded in.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct DirectiveCtx<'a> {
+    /// The directory that relative paths in the directive are resolved
+    /// against.
+    pub base: &'a Path,
+    /// The path of the chapter, relative to the source directory.
+    pub source: &'a Path,
+    /// The title of the chapter, which may have been changed with a
+    /// `{{#title}}` directive.
+    pub chapter_title: &'a str,
+}
+
+/// The directives understood by the [`LinkPreprocessor`], in addition to the
+/// built-in ones like `{{#include}}`.
+///
+/// ```rust
+/// # use mdbook::preprocess::{DirectiveRegistry, LinkPreprocessor};
+/// let mut registry = DirectiveRegistry::new();
+/// registry.register("shout", |args, _ctx| Ok(args.to_uppercase()));
+/// let preprocessor = LinkPreprocessor::with_directives(registry);
+/// ```
+#[derive(Clone, Default)]
+pub struct DirectiveRegistry {
+    handlers: HashMap<String, Arc<DirectiveHandler>>,
+}
+
+impl DirectiveRegistry {
+    /// Create a registry with only the built-in directives.
+    pub fn new() -> Self {
+        DirectiveRegistry::default()
+    }
+
+    /// Registers the `handler` for `{{#name args}}` directives, replacing
+    /// the one previously registered under `name`.
+    ///
+    /// # Panics
+    ///
+    /// If `name` is the name of a built-in directive, like `include`.
+    pub fn register<F>(&mut self, name: &str, handler: F) -> &mut Self
+    where
+        F: Fn(&str, &DirectiveCtx<'_>) -> Result<String> + Send + Sync + 'static,
+    {
+        assert!(
+            !BUILTIN_DIRECTIVES.contains(&name),
+            "`{name}` is a built-in directive and can't be replaced"
+        );
+        self.handlers.insert(name.to_owned(), Arc::new(handler));
+        self
+    }
+
+    /// Whether `name` is a built-in or registered directive.
+    pub fn contains(&self, name: &str) -> bool {
+        BUILTIN_DIRECTIVES.contains(&name) || self.handlers.contains_key(name)
+    }
+
+    fn handler(&self, name: &str) -> Option<&DirectiveHandler> {
+        self.handlers.get(name).map(|handler| &**handler)
+    }
+}
+
+/// What to do with directives that are neither built in nor registered
+/// (`unknown-directives`).
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+enum UnknownDirectives {
+    /// Leave them in the chapter as they are.
+    #[default]
+    Keep,
+    /// Report an error, and leave them in the chapter as they are.
+    Error,
+}
+
+/// The settings of the `[preprocessor.links]` table that control how the
+/// directives of a chapter are expanded.
+struct ReplaceOptions {
+    /// Leave directives inside of code blocks as they are
+    /// (`skip-code-blocks`).
+    skip_code_blocks: bool,
+    unknown_directives: UnknownDirectives,
+    directives: DirectiveRegistry,
+    /// The directory that the content of included URLs is cached in
+    /// (`remote-cache-dir`), or `None` to always fetch it.
+    remote_cache_dir: Option<PathBuf>,
+    /// The prefix of the language class of `{{#include_code}}` blocks, from
+    /// `output.html.markdown.code-class-prefix`.
+    code_class_prefix: String,
+}
+
+impl Default for ReplaceOptions {
+    fn default() -> ReplaceOptions {
+        ReplaceOptions {
+            skip_code_blocks: false,
+            unknown_directives: UnknownDirectives::default(),
+            directives: DirectiveRegistry::default(),
+            remote_cache_dir: None,
+            code_class_prefix: "language-".to_owned(),
+        }
     }
 }
 
@@ -45,6 +185,42 @@ impl Preprocessor for LinkPreprocessor {
 
     fn run(&self, ctx: &PreprocessorContext, mut book: Book) -> Result<Book> {
         let src_dir = ctx.root.join(&ctx.config.book.src);
+        let config = ctx.config.get_preprocessor(Self::NAME);
+        let mut diagnostics = IncludeDiagnostics::from_config(config);
+        let skip_code_blocks = config
+            .and_then(|config| config.get("skip-code-blocks"))
+            .and_then(toml::Value::as_bool)
+            .unwrap_or(false);
+        let unknown_directives = match config
+            .and_then(|config| config.get("unknown-directives"))
+            .and_then(toml::Value::as_str)
+        {
+            None | Some("keep") => UnknownDirectives::Keep,
+            Some("error") => UnknownDirectives::Error,
+            Some(other) => {
+                warn!(
+                    "unknown value `{}` for `unknown-directives`, expected `keep` or `error`",
+                    other
+                );
+                UnknownDirectives::Keep
+            }
+        };
+        let remote_cache_dir = config
+            .and_then(|config| config.get("remote-cache-dir"))
+            .and_then(toml::Value::as_str)
+            .unwrap_or(".mdbook-cache/includes");
+        let options = ReplaceOptions {
+            skip_code_blocks,
+            unknown_directives,
+            directives: self.directives.clone(),
+            remote_cache_dir: Some(ctx.root.join(remote_cache_dir)),
+            code_class_prefix: ctx
+                .config
+                .html_config()
+                .unwrap_or_default()
+                .markdown
+                .code_class_prefix,
+        };
 
         book.for_each_mut(|section: &mut BookItem| {
             if let BookItem::Chapter(ref mut ch) = *section {
@@ -55,9 +231,27 @@ impl Preprocessor for LinkPreprocessor {
                         .expect("All book items have a parent");
 
                     let mut chapter_title = ch.name.clone();
-                    let content =
-                        replace_all(&ch.content, base, chapter_path, 0, &mut chapter_title);
+                    let content = replace_all(
+                        &ch.content,
+                        base,
+                        chapter_path,
+                        0,
+                        &mut chapter_title,
+                        &mut diagnostics,
+                        &options,
+                    );
                     ch.content = content;
+                    let max_line_length = diagnostics.max_line_length.unwrap_or_default();
+                    for long_line in diagnostics.long_lines.drain(..) {
+                        warn!(
+                            "Line {} of {} in {} is {} columns long, more than the maximum of {}",
+                            long_line.line,
+                            long_line.link_text,
+                            chapter_path.display(),
+                            long_line.width,
+                            max_line_length,
+                        );
+                    }
                     if chapter_title != ch.name {
                         ctx.chapter_titles
                             .borrow_mut()
@@ -67,6 +261,12 @@ impl Preprocessor for LinkPreprocessor {
             }
         });
 
+        if let Some(error) = diagnostics.errors.pop() {
+            for error in diagnostics.errors {
+                error!("{:?}", error);
+            }
+            return Err(error);
+        }
         Ok(book)
     }
 }
@@ -77,6 +277,8 @@ fn replace_all<P1, P2>(
     source: P2,
     depth: usize,
     chapter_title: &mut String,
+    diagnostics: &mut IncludeDiagnostics,
+    options: &ReplaceOptions,
 ) -> String
 where
     P1: AsRef<Path>,
@@ -89,11 +291,30 @@ where
     let source = source.as_ref();
     let mut previous_end_index = 0;
     let mut replaced = String::new();
+    let code_blocks = if options.skip_code_blocks {
+        code_block_ranges(s)
+    } else {
+        Vec::new()
+    };
 
     for link in find_links(s) {
+        if code_blocks
+            .iter()
+            .any(|block| block.contains(&link.start_index))
+        {
+            continue;
+        }
         replaced.push_str(&s[previous_end_index..link.start_index]);
 
-        match link.render_with_path(path, chapter_title) {
+        let ctx = DirectiveCtx {
+            base: path,
+            source,
+            chapter_title,
+        };
+        match link
+            .render_custom(&ctx, options)
+            .unwrap_or_else(|| link.render_with_path(path, chapter_title, diagnostics, options))
+        {
             Ok(new_content) => {
                 if depth < MAX_LINK_NESTED_DEPTH {
                     if let Some(rel_path) = link.link_type.relative_path(path) {
@@ -103,6 +324,8 @@ where
                             source,
                             depth + 1,
                             chapter_title,
+                            diagnostics,
+                            options,
                         ));
                     } else {
                         replaced.push_str(&new_content);
@@ -115,6 +338,14 @@ where
                 }
                 previous_end_index = link.end_index;
             }
+            Err(e) if matches!(link.link_type, LinkType::RemoteInclude(..)) => {
+                // Remote includes fail the build, so that a network error
+                // doesn't go unnoticed.
+                diagnostics
+                    .errors
+                    .push(e.context(format!("Error updating {}", link.link_text)));
+                previous_end_index = link.start_index;
+            }
             Err(e) => {
                 error!("Error updating \"{}\", {}", link.link_text, e);
                 for cause in e.chain().skip(1) {
@@ -132,19 +363,501 @@ where
     replaced
 }
 
+/// The byte ranges of the fenced and indented code blocks in `s`.
+fn code_block_ranges(s: &str) -> Vec<Range<usize>> {
+    new_cmark_parser(s, false)
+        .into_offset_iter()
+        .filter_map(|(event, range)| {
+            matches!(event, Event::Start(Tag::CodeBlock(_))).then_some(range)
+        })
+        .collect()
+}
+
+/// Collects the included lines that are longer than the `max-line-length`
+/// of `[preprocessor.links]`, as well as the errors that fail the build, so
+/// that they can be reported.
+#[derive(Debug, Default)]
+struct IncludeDiagnostics {
+    /// The number of columns a line may take, or `None` to not check lines.
+    max_line_length: Option<usize>,
+    /// The number of columns up to the next tab stop (`tab-width`).
+    tab_width: usize,
+    long_lines: Vec<LongLine>,
+    errors: Vec<Error>,
+}
+
+/// An included line that is longer than the maximum line length.
+#[derive(PartialEq, Debug, Clone)]
+struct LongLine {
+    /// The `{{#include}}` the line comes from.
+    link_text: String,
+    /// The number of the line, counting from the first included line.
+    line: usize,
+    /// The width of the line in columns.
+    width: usize,
+}
+
+impl IncludeDiagnostics {
+    fn from_config(config: Option<&toml::value::Table>) -> IncludeDiagnostics {
+        let get = |key| {
+            config
+                .and_then(|config| config.get(key))
+                .and_then(toml::Value::as_integer)
+                .and_then(|value| usize::try_from(value).ok())
+        };
+        IncludeDiagnostics {
+            max_line_length: get("max-line-length"),
+            tab_width: get("tab-width").unwrap_or(4),
+            long_lines: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Records the lines of the included `content` that are too long.
+    fn check(&mut self, link_text: &str, content: &str) {
+        let Some(max_line_length) = self.max_line_length else {
+            return;
+        };
+        for (i, line) in content.lines().enumerate() {
+            let width = line_width(line, self.tab_width);
+            if width > max_line_length {
+                self.long_lines.push(LongLine {
+                    link_text: link_text.to_owned(),
+                    line: i + 1,
+                    width,
+                });
+            }
+        }
+    }
+}
+
+/// The number of columns `line` takes, with tabs advancing to the next
+/// multiple of `tab_width`.
+fn line_width(line: &str, tab_width: usize) -> usize {
+    line.chars().fold(0, |width, ch| match ch {
+        '\t' if tab_width > 0 => (width / tab_width + 1) * tab_width,
+        _ => width + 1,
+    })
+}
+
 #[derive(PartialEq, Debug, Clone)]
 enum LinkType<'a> {
     Escaped,
-    Include(PathBuf, RangeOrAnchor),
+    Include(PathBuf, RangeOrAnchor, IncludeOptions),
     Playground(PathBuf, Vec<&'a str>),
-    RustdocInclude(PathBuf, RangeOrAnchor),
+    Raw(&'a str),
+    RustdocInclude(PathBuf, RangeOrAnchor, IncludeOptions),
+    /// An `{{#include}}` of an `http://` or `https://` URL.
+    RemoteInclude(String, RangeOrAnchor, IncludeOptions),
+    /// An `{{#include_svg}}` of an SVG image to insert inline.
+    SvgInclude(PathBuf),
+    /// An `{{#include_tree}}` of a directory to list the files of.
+    TreeInclude(PathBuf, TreeOptions),
     Title(&'a str),
+    /// A directive that isn't built in, with its name and arguments.
+    Custom(&'a str, &'a str),
 }
 
 #[derive(PartialEq, Debug, Clone)]
 enum RangeOrAnchor {
     Range(LineRange),
     Anchor(String),
+    /// The `n`th section with the anchor name, written as `name#n`.
+    AnchorOccurrence(String, usize),
+    /// The markdown section under a heading, written as ` #Heading` after
+    /// the path.
+    Section(String),
+    /// The markdown sections from the heading mentioning one version to the
+    /// end of the section of the heading mentioning another, given with the
+    /// `from=version` and `to=version` properties.
+    Versions(Option<String>, Option<String>),
+    /// The value at a dotted path in a JSON or YAML file, given with the
+    /// `json:path` or `yaml:path` property.
+    DataValue(DataFormat, String),
+}
+
+/// The properties of an `{{#include_tree}}`.
+#[derive(PartialEq, Debug, Clone, Default)]
+struct TreeOptions {
+    /// How many levels of directories to list, all of them if `None`
+    /// (`depth=n`).
+    depth: Option<usize>,
+    /// List files and directories starting with a `.` (`hidden`).
+    hidden: bool,
+}
+
+impl TreeOptions {
+    fn from_props(props: &[&str]) -> TreeOptions {
+        let mut options = TreeOptions::default();
+        for prop in props {
+            match prop.strip_prefix("depth=").map(str::parse) {
+                Some(Ok(depth)) => options.depth = Some(depth),
+                None if *prop == "hidden" => options.hidden = true,
+                _ => warn!("Ignoring unknown property `{prop}` of {{{{#include_tree}}}}"),
+            }
+        }
+        options
+    }
+}
+
+/// Renders the files and directories in `dir` as a nested markdown list,
+/// directories first and then files, each sorted by name.
+fn directory_tree(dir: &Path, options: &TreeOptions) -> Result<String> {
+    fn list(dir: &Path, options: &TreeOptions, level: usize, out: &mut Vec<String>) -> Result<()> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') && !options.hidden {
+                continue;
+            }
+            // Symbolic links aren't followed, so they can't form cycles.
+            entries.push((!entry.file_type()?.is_dir(), name, entry.path()));
+        }
+        entries.sort();
+        for (is_file, name, path) in entries {
+            let name = if is_file { name } else { format!("{name}/") };
+            let code = if name.contains('`') {
+                format!("`` {name} ``")
+            } else {
+                format!("`{name}`")
+            };
+            out.push(format!("{}- {code}", "  ".repeat(level)));
+            if !is_file && options.depth.map_or(true, |depth| level + 1 < depth) {
+                list(&path, options, level + 1, out)?;
+            }
+        }
+        Ok(())
+    }
+
+    let mut lines = Vec::new();
+    list(dir, options, 0, &mut lines)?;
+    Ok(lines.join("\n"))
+}
+
+/// The format of a data file to include a value from.
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum DataFormat {
+    Json,
+    Yaml,
+}
+
+impl RangeOrAnchor {
+    /// The number of the first included line, counting from 1, for line
+    /// ranges.
+    fn first_line(&self) -> Option<usize> {
+        match self {
+            RangeOrAnchor::Range(range) => match range.start_bound() {
+                Bound::Included(&start) => Some(start + 1),
+                _ => Some(1),
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Additional properties given after the path of an `{{#include}}` or
+/// `{{#rustdoc_include}}`.
+#[derive(PartialEq, Debug, Clone, Default)]
+struct IncludeOptions {
+    /// Wrap the included content in a fenced code block (`lang` or
+    /// `lang=name`).
+    code_block: Option<CodeBlockLanguage>,
+    /// Remove trailing whitespace from every included line
+    /// (`trim-trailing`).
+    trim_trailing: bool,
+    /// Remove the blank lines at the start and end of the included content
+    /// (`trim-blank-lines`).
+    trim_blank_lines: bool,
+    /// Remove the lines that Rust code blocks hide (`strip-hidden`).
+    strip_hidden: bool,
+    /// Remove the lines that are comments in the language of the included
+    /// file (`strip-comments`).
+    strip_comments: bool,
+    /// Record the line number that an included line range starts at in the
+    /// code block, so that its line numbers can start there (`start-line`).
+    start_line: bool,
+    /// Replace tabs with spaces (`expand-tabs=width` for the indentation
+    /// only, or `expand-all-tabs=width` for every tab).
+    expand_tabs: Option<TabExpansion>,
+    /// Wrap the content in an HTML code block with the content escaped,
+    /// instead of a fenced code block (`{{#include_code}}`).
+    escape_html: bool,
+    /// The number of lines at the start of the file to skip before the
+    /// range or anchor is taken (`skip-lines=n`).
+    skip_lines: usize,
+    /// A regular expression matching the lines at the start of the file,
+    /// after `skip_lines`, to skip before the range or anchor is taken
+    /// (`skip-leading=pattern`).
+    skip_leading: Option<String>,
+}
+
+impl IncludeOptions {
+    /// Whether `prop` is one of the properties understood by
+    /// [`IncludeOptions::from_props`].
+    fn is_property(prop: &str) -> bool {
+        matches!(
+            prop,
+            "lang"
+                | "trim-trailing"
+                | "trim-blank-lines"
+                | "strip-hidden"
+                | "strip-comments"
+                | "start-line"
+        ) || [
+            "lang=",
+            "expand-tabs=",
+            "expand-all-tabs=",
+            "skip-lines=",
+            "skip-leading=",
+            "from=",
+            "to=",
+            "json:",
+            "yaml:",
+        ]
+        .iter()
+        .any(|prefix| prop.starts_with(prefix))
+    }
+
+    fn from_props(props: &[&str]) -> IncludeOptions {
+        let mut options = IncludeOptions::default();
+        for prop in props {
+            match prop.split_once('=') {
+                None if *prop == "lang" => {
+                    options.code_block = Some(CodeBlockLanguage::FromExtension);
+                }
+                None if *prop == "trim-trailing" => {
+                    options.trim_trailing = true;
+                }
+                None if *prop == "trim-blank-lines" => {
+                    options.trim_blank_lines = true;
+                }
+                None if *prop == "strip-hidden" => {
+                    options.strip_hidden = true;
+                }
+                None if *prop == "strip-comments" => {
+                    options.strip_comments = true;
+                }
+                None if *prop == "start-line" => {
+                    options.start_line = true;
+                }
+                // The versions select the included sections, see
+                // `parse_version_props`.
+                Some(("from" | "to", _)) => {}
+                // The path selects the included value, see
+                // `parse_data_path_prop`.
+                None if prop.starts_with("json:") || prop.starts_with("yaml:") => {}
+                Some(("lang", lang)) => {
+                    options.code_block = Some(CodeBlockLanguage::Named(lang.to_owned()));
+                }
+                Some((name @ ("expand-tabs" | "expand-all-tabs"), width)) => match width.parse() {
+                    Ok(width) => {
+                        options.expand_tabs = Some(TabExpansion {
+                            width,
+                            leading_only: name == "expand-tabs",
+                        });
+                    }
+                    Err(_) => warn!("invalid tab width in include property `{}`", prop),
+                },
+                Some(("skip-lines", count)) => match count.parse() {
+                    Ok(count) => options.skip_lines = count,
+                    Err(_) => warn!("invalid line count in include property `{}`", prop),
+                },
+                Some(("skip-leading", pattern)) => match Regex::new(pattern) {
+                    Ok(_) => options.skip_leading = Some(pattern.to_owned()),
+                    Err(e) => warn!("invalid pattern in include property `{}`: {}", prop, e),
+                },
+                _ => warn!("unknown include property `{}`", prop),
+            }
+        }
+        options
+    }
+
+    /// Skips the lines at the start of an included file selected by
+    /// `skip-lines` and `skip-leading`, returning the rest of the file and the
+    /// number of skipped lines.
+    fn skip_leading_lines<'a>(&self, content: &'a str) -> (&'a str, usize) {
+        let pattern = self
+            .skip_leading
+            .as_deref()
+            .and_then(|pattern| Regex::new(pattern).ok());
+        let mut rest = content;
+        let mut skipped = 0;
+        while !rest.is_empty() {
+            let (line, next) = match rest.find('\n') {
+                Some(end) => (&rest[..end], &rest[end + 1..]),
+                None => (rest, ""),
+            };
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            let skip = skipped < self.skip_lines
+                || pattern
+                    .as_ref()
+                    .is_some_and(|pattern| pattern.is_match(line));
+            if !skip {
+                break;
+            }
+            rest = next;
+            skipped += 1;
+        }
+        (rest, skipped)
+    }
+
+    /// Applies the options to the already extracted lines of an included file,
+    /// which start at line `first_line` of the file if that is known.
+    /// `code_class_prefix` is the prefix of the language class of an escaped
+    /// code block.
+    fn apply(
+        &self,
+        content: String,
+        path: &Path,
+        first_line: Option<usize>,
+        code_class_prefix: &str,
+    ) -> String {
+        let content = if self.strip_comments {
+            let lang = match &self.code_block {
+                Some(lang) => lang.for_path(path),
+                None => CodeBlockLanguage::FromExtension.for_path(path),
+            };
+            match comment_prefix(&lang) {
+                Some(prefix) => strip_comment_lines(&content, prefix),
+                None => {
+                    warn!(
+                        "strip-comments: unknown comment syntax for `{}`",
+                        path.display()
+                    );
+                    content
+                }
+            }
+        } else {
+            content
+        };
+        let content = if self.strip_hidden {
+            strip_hidden_lines(&content)
+        } else {
+            content
+        };
+        let content = match self.expand_tabs {
+            Some(tabs) => expand_tabs(&content, tabs.width, tabs.leading_only),
+            None => content,
+        };
+        let content = if self.trim_trailing {
+            trim_trailing_whitespace(&content)
+        } else {
+            content
+        };
+        let mut first_line = first_line.filter(|_| self.start_line);
+        let content = if self.trim_blank_lines {
+            let leading = content.lines().take_while(|l| l.trim().is_empty()).count();
+            first_line = first_line.map(|line| line + leading);
+            trim_blank_lines(&content)
+        } else {
+            content
+        };
+        let lang = self.code_block.as_ref().map(|lang| {
+            let mut lang = lang.for_path(path);
+            // The renderer turns this into a `data-start-line` attribute.
+            if let (Some(line), false) = (first_line, lang.is_empty()) {
+                lang.push_str(&format!(",start-line={line}"));
+            }
+            lang
+        });
+        match lang {
+            Some(lang) if self.escape_html => {
+                wrap_in_escaped_code_block(&content, &lang, code_class_prefix)
+            }
+            Some(lang) => wrap_in_code_block(&content, &lang),
+            None => content,
+        }
+    }
+}
+
+/// How the tabs of included content are replaced with spaces.
+#[derive(PartialEq, Debug, Clone, Copy)]
+struct TabExpansion {
+    /// The number of columns between tab stops.
+    width: usize,
+    /// Only replace the tabs in the indentation of each line.
+    leading_only: bool,
+}
+
+/// The prefix of a line comment in the language `lang`, as named by
+/// [`CodeBlockLanguage::for_path`], possibly followed by attributes like
+/// `rust,editable`.
+fn comment_prefix(lang: &str) -> Option<&'static str> {
+    match lang.split(',').next().unwrap() {
+        "rust" | "javascript" | "typescript" | "c" | "h" | "cpp" | "hpp" | "java" | "go"
+        | "kotlin" | "kt" | "swift" | "scala" | "cs" => Some("//"),
+        "python" | "yaml" | "toml" | "bash" | "zsh" | "ruby" | "rb" | "perl" | "pl" | "r" => {
+            Some("#")
+        }
+        "sql" | "lua" | "haskell" | "hs" => Some("--"),
+        _ => None,
+    }
+}
+
+/// The language used for the fenced code block wrapping included content.
+#[derive(PartialEq, Debug, Clone)]
+enum CodeBlockLanguage {
+    /// An explicit language hint.
+    Named(String),
+    /// Derived from the extension of the included file, if any.
+    FromExtension,
+}
+
+impl CodeBlockLanguage {
+    fn for_path(&self, path: &Path) -> String {
+        match self {
+            CodeBlockLanguage::Named(lang) => lang.clone(),
+            CodeBlockLanguage::FromExtension => {
+                let extension = path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .unwrap_or_default();
+                match extension {
+                    "rs" => "rust",
+                    "py" => "python",
+                    "js" => "javascript",
+                    "ts" => "typescript",
+                    "sh" => "bash",
+                    "yml" => "yaml",
+                    "md" => "markdown",
+                    ext => ext,
+                }
+                .to_owned()
+            }
+        }
+    }
+}
+
+/// Wraps `content` in a fenced code block, using a fence that is longer than
+/// any run of backticks inside of the content.
+fn wrap_in_code_block(content: &str, lang: &str) -> String {
+    let longest_run = content
+        .split(|ch| ch != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    format!("{fence}{lang}\n{content}\n{fence}")
+}
+
+/// Wraps `content` in an HTML code block with the content escaped, so that
+/// nothing in it is interpreted as Markdown or HTML. The language class
+/// starts with `prefix`, like the classes of fenced code blocks.
+fn wrap_in_escaped_code_block(content: &str, lang: &str, prefix: &str) -> String {
+    let class = if lang.is_empty() {
+        String::new()
+    } else {
+        format!(
+            " class=\"{}{}\"",
+            special_escape(prefix),
+            special_escape(lang)
+        )
+    };
+    format!(
+        "<pre><code{class}>{}\n</code></pre>",
+        special_escape(content)
+    )
 }
 
 // A range of lines specified with some include directive.
@@ -206,10 +919,15 @@ impl<'a> LinkType<'a> {
         let base = base.as_ref();
         match self {
             LinkType::Escaped => None,
-            LinkType::Include(p, _) => Some(return_relative_path(base, &p)),
+            LinkType::Include(p, _, _) => Some(return_relative_path(base, &p)),
             LinkType::Playground(p, _) => Some(return_relative_path(base, &p)),
-            LinkType::RustdocInclude(p, _) => Some(return_relative_path(base, &p)),
+            LinkType::Raw(_) => None,
+            LinkType::RustdocInclude(p, _, _) => Some(return_relative_path(base, &p)),
+            LinkType::RemoteInclude(..) => None,
+            LinkType::SvgInclude(p) => Some(return_relative_path(base, &p)),
+            LinkType::TreeInclude(..) => None,
             LinkType::Title(_) => None,
+            LinkType::Custom(..) => None,
         }
     }
 }
@@ -231,6 +949,11 @@ fn parse_range_or_anchor(parts: Option<&str>) -> RangeOrAnchor {
     } else if let Some("") = next_element {
         None
     } else if let Some(anchor) = next_element {
+        if let Some((name, n)) = anchor.rsplit_once('#') {
+            if let Ok(n) = n.parse::<usize>() {
+                return RangeOrAnchor::AnchorOccurrence(String::from(name), n);
+            }
+        }
         return RangeOrAnchor::Anchor(String::from(anchor));
     } else {
         None
@@ -251,200 +974,2146 @@ fn parse_range_or_anchor(parts: Option<&str>) -> RangeOrAnchor {
     }
 }
 
-fn parse_include_path(path: &str) -> LinkType<'static> {
+/// Splits the arguments of an include into the path and the trailing
+/// properties.
+///
+/// Only known properties are split off, so the path itself may contain
+/// whitespace, like `{{#include my file.rs lang}}`.
+fn split_include_props(rest: &str) -> (&str, Vec<&str>) {
+    let mut path = rest.trim();
+    let mut props = Vec::new();
+    while let Some((head, last)) = path.rsplit_once(char::is_whitespace) {
+        if !IncludeOptions::is_property(last) {
+            break;
+        }
+        props.push(last);
+        path = head.trim_end();
+    }
+    props.reverse();
+    (path, props)
+}
+
+/// Expands the `{{#include}}`, `{{#include_code}}`, and `{{#rustdoc_include}}`
+/// directives in the markdown `text`, resolving paths relative to `base`.
+///
+/// This is useful for tools that want to look at the markdown of a chapter
+/// as it is after the includes, without rendering it. Other directives, like
+/// `{{#playground}}` or `{{#title}}`, are left as they are.
+///
+/// Unlike the [`LinkPreprocessor`], which keeps a directive that can't be
+/// expanded in place and logs the error, this returns an error for missing
+/// files, line ranges starting past the end of a file, and anchors that
+/// don't occur in the file.
+pub fn expand_includes(text: &str, base: &Path) -> Result<String> {
+    expand_includes_at_depth(text, base, 0)
+}
+
+fn expand_includes_at_depth(s: &str, base: &Path, depth: usize) -> Result<String> {
+    let mut previous_end_index = 0;
+    let mut replaced = String::new();
+
+    for link in find_links(s) {
+        let (pat, range_or_anchor, include_options) = match &link.link_type {
+            LinkType::Include(pat, range_or_anchor, include_options)
+            | LinkType::RustdocInclude(pat, range_or_anchor, include_options) => {
+                (pat, range_or_anchor, include_options)
+            }
+            _ => continue,
+        };
+        if depth >= MAX_LINK_NESTED_DEPTH {
+            bail!(
+                "Stack depth exceeded while expanding {}. Check for cyclic includes",
+                link.link_text
+            );
+        }
+
+        let new_content = link
+            .render_with_path(
+                base,
+                &mut String::new(),
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default(),
+            )
+            .with_context(|| format!("Could not expand {}", link.link_text))?;
+        let target = base.join(pat);
+        let content = fs::read_to_string(&target)?;
+        let (content, _) = include_options.skip_leading_lines(&content);
+        check_range_or_anchor(content, range_or_anchor).with_context(|| {
+            format!("Could not expand {} ({})", link.link_text, target.display())
+        })?;
+
+        let rel_path = link
+            .link_type
+            .clone()
+            .relative_path(base)
+            .expect("includes have a path");
+        replaced.push_str(&s[previous_end_index..link.start_index]);
+        replaced.push_str(&expand_includes_at_depth(
+            &new_content,
+            &rel_path,
+            depth + 1,
+        )?);
+        previous_end_index = link.end_index;
+    }
+
+    replaced.push_str(&s[previous_end_index..]);
+    Ok(replaced)
+}
+
+/// A directive including a file that can't be resolved, as found by
+/// [`validate_includes`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct IncludeError {
+    /// The byte offset of the directive in the validated text.
+    pub offset: usize,
+    /// The directive, like `{{#include file.rs:2:10}}`.
+    pub directive: String,
+    /// What can't be resolved.
+    pub kind: IncludeErrorKind,
+}
+
+impl Display for IncludeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at byte {}: {}",
+            self.directive, self.offset, self.kind
+        )
+    }
+}
+
+impl std::error::Error for IncludeError {}
+
+/// What can't be resolved in a directive including a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IncludeErrorKind {
+    /// The included file can't be read.
+    MissingFile(PathBuf),
+    /// The line range starts past the end of the file.
+    BadRange {
+        /// The first line of the range, counting from 1.
+        line: usize,
+        /// The number of lines of the file.
+        lines: usize,
+    },
+    /// The anchor doesn't occur in the file.
+    MissingAnchor(String),
+    /// Another part of the include can't be resolved, like the heading of a
+    /// section, described by the message.
+    Other(String),
+}
+
+impl Display for IncludeErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            IncludeErrorKind::MissingFile(path) => {
+                write!(f, "Could not read file {}", path.display())
+            }
+            IncludeErrorKind::BadRange { line, lines } => write!(
+                f,
+                "Line {} is past the end of the file, which has {} line(s)",
+                line, lines
+            ),
+            IncludeErrorKind::MissingAnchor(anchor) => {
+                write!(f, "Anchor `{}` does not occur in the file", anchor)
+            }
+            IncludeErrorKind::Other(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for IncludeErrorKind {}
+
+/// Checks that the directives including files in the markdown `text` can be
+/// resolved, with paths relative to `base`, without expanding them.
+///
+/// This reports included files that can't be read, line ranges starting past
+/// the end of a file, anchors that don't occur in a file, and other parts of
+/// includes that [`expand_includes`] would fail on, in the order of the
+/// directives in the text. `{{#include_svg}}` and `{{#playground}}` are only
+/// checked for their file, while includes of URLs aren't checked, since that
+/// would need fetching them. Includes within the included files aren't
+/// checked either.
+pub fn validate_includes(text: &str, base: &Path) -> Vec<IncludeError> {
+    find_links(text)
+        .filter_map(|link| {
+            let (pat, checked) = match &link.link_type {
+                LinkType::Include(pat, range_or_anchor, include_options)
+                | LinkType::RustdocInclude(pat, range_or_anchor, include_options) => {
+                    (pat, Some((range_or_anchor, include_options)))
+                }
+                LinkType::SvgInclude(pat) | LinkType::Playground(pat, _) => (pat, None),
+                _ => return None,
+            };
+            let target = base.join(pat);
+            let kind = match fs::read_to_string(&target) {
+                Ok(content) => {
+                    let (range_or_anchor, include_options) = checked?;
+                    let (content, _) = include_options.skip_leading_lines(&content);
+                    check_range_or_anchor(content, range_or_anchor).err()?
+                }
+                Err(_) => IncludeErrorKind::MissingFile(target),
+            };
+            Some(IncludeError {
+                offset: link.start_index,
+                directive: link.link_text.to_owned(),
+                kind,
+            })
+        })
+        .collect()
+}
+
+/// Checks that the lines selected by an include exist in the included
+/// `content`.
+fn check_range_or_anchor(
+    content: &str,
+    range_or_anchor: &RangeOrAnchor,
+) -> Result<(), IncludeErrorKind> {
+    let other = |err: Error| IncludeErrorKind::Other(format!("{:#}", err));
+    match range_or_anchor {
+        RangeOrAnchor::Range(range) => {
+            let lines = content.lines().count();
+            match range.start_bound() {
+                Bound::Included(&start) if start > 0 && start >= lines => {
+                    Err(IncludeErrorKind::BadRange {
+                        line: start + 1,
+                        lines,
+                    })
+                }
+                _ => Ok(()),
+            }
+        }
+        RangeOrAnchor::Anchor(anchor) | RangeOrAnchor::AnchorOccurrence(anchor, _) => {
+            match take_nth_anchored_lines(content, anchor, 1) {
+                Some(_) => Ok(()),
+                None => Err(IncludeErrorKind::MissingAnchor(anchor.clone())),
+            }
+        }
+        RangeOrAnchor::Section(heading) => match take_markdown_section(content, heading) {
+            Some(_) => Ok(()),
+            None => Err(IncludeErrorKind::Other(format!(
+                "Heading `{}` does not occur in the file",
+                heading
+            ))),
+        },
+        RangeOrAnchor::Versions(from, to) => take_version_sections(
+            content,
+            from.as_deref(),
+            to.as_deref(),
+            Path::new("the file"),
+        )
+        .map(drop)
+        .map_err(other),
+        RangeOrAnchor::DataValue(format, path) => {
+            take_data_value(content, *format, path, Path::new("the file"))
+                .map(drop)
+                .map_err(other)
+        }
+    }
+}
+
+/// Decodes the percent-encoded characters in the path of an include, like
+/// `%20` for a space or `%C3%A9` for `é`.
+///
+/// A `%` that isn't followed by two hex digits is kept as it is, and so is
+/// the whole path if the decoded bytes aren't valid UTF-8.
+fn decode_include_path(path: &str) -> PathBuf {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match byte {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded)
+        .unwrap_or_else(|_| path.to_owned())
+        .into()
+}
+
+/// Whether the path of an include is a URL to fetch the content from.
+fn is_remote_include(path: &str) -> bool {
+    path.starts_with("https://") || path.starts_with("http://")
+}
+
+/// The versions given with the `from=version` and `to=version` properties
+/// of an include, if any.
+fn parse_version_props(props: &[&str]) -> Option<RangeOrAnchor> {
+    let find = |name: &str| {
+        props
+            .iter()
+            .find_map(|prop| prop.strip_prefix(name))
+            .map(String::from)
+    };
+    match (find("from="), find("to=")) {
+        (None, None) => None,
+        (from, to) => Some(RangeOrAnchor::Versions(from, to)),
+    }
+}
+
+/// The path of the value given with the `json:path` or `yaml:path` property
+/// of an include, if any.
+fn parse_data_path_prop(props: &[&str]) -> Option<RangeOrAnchor> {
+    props.iter().find_map(|prop| {
+        if let Some(path) = prop.strip_prefix("json:") {
+            Some(RangeOrAnchor::DataValue(DataFormat::Json, path.to_owned()))
+        } else {
+            let path = prop.strip_prefix("yaml:")?;
+            Some(RangeOrAnchor::DataValue(DataFormat::Yaml, path.to_owned()))
+        }
+    })
+}
+
+fn parse_include_path(path: &str, props: &[&str]) -> LinkType<'static> {
+    let sections = match path.split_once(" #") {
+        Some((path, heading)) => Some((
+            path.trim_end(),
+            RangeOrAnchor::Section(heading.trim().to_owned()),
+        )),
+        None => parse_version_props(props)
+            .or_else(|| parse_data_path_prop(props))
+            .map(|selection| (path, selection)),
+    };
+    if let Some((path, sections)) = sections {
+        let options = IncludeOptions::from_props(props);
+        return if is_remote_include(path) {
+            LinkType::RemoteInclude(path.to_owned(), sections, options)
+        } else {
+            LinkType::Include(decode_include_path(path), sections, options)
+        };
+    }
+    if is_remote_include(path) {
+        // The range or anchor follows the last path segment of the URL, so
+        // that the colons of the scheme and port are kept.
+        let segment_start = path.rfind('/').unwrap_or(0);
+        let (url, range_or_anchor) = match path[segment_start..].find(':') {
+            Some(i) => (
+                &path[..segment_start + i],
+                Some(&path[segment_start + i + 1..]),
+            ),
+            None => (path, None),
+        };
+        return LinkType::RemoteInclude(
+            url.to_owned(),
+            parse_range_or_anchor(range_or_anchor),
+            IncludeOptions::from_props(props),
+        );
+    }
+    let mut parts = path.splitn(2, ':');
+
+    let path = decode_include_path(parts.next().unwrap());
+    let range_or_anchor = parse_range_or_anchor(parts.next());
+
+    LinkType::Include(path, range_or_anchor, IncludeOptions::from_props(props))
+}
+
+fn parse_include_code_path(path: &str, props: &[&str]) -> LinkType<'static> {
     let mut parts = path.splitn(2, ':');
 
-    let path = parts.next().unwrap().into();
+    let path = decode_include_path(parts.next().unwrap());
     let range_or_anchor = parse_range_or_anchor(parts.next());
 
-    LinkType::Include(path, range_or_anchor)
+    let mut options = IncludeOptions::from_props(props);
+    options
+        .code_block
+        .get_or_insert(CodeBlockLanguage::FromExtension);
+    options.escape_html = true;
+
+    LinkType::Include(path, range_or_anchor, options)
 }
 
-fn parse_rustdoc_include_path(path: &str) -> LinkType<'static> {
+fn parse_rustdoc_include_path(path: &str, props: &[&str]) -> LinkType<'static> {
     let mut parts = path.splitn(2, ':');
 
-    let path = parts.next().unwrap().into();
-    let range_or_anchor = parse_range_or_anchor(parts.next());
+    let path = decode_include_path(parts.next().unwrap());
+    let range_or_anchor = parse_range_or_anchor(parts.next());
+
+    LinkType::RustdocInclude(path, range_or_anchor, IncludeOptions::from_props(props))
+}
+
+#[derive(PartialEq, Debug, Clone)]
+struct Link<'a> {
+    start_index: usize,
+    end_index: usize,
+    link_type: LinkType<'a>,
+    link_text: &'a str,
+}
+
+impl<'a> Link<'a> {
+    fn from_capture(cap: Captures<'a>) -> Option<Link<'a>> {
+        if let Some(raw) = cap.get(3) {
+            return cap.get(0).map(|mat| Link {
+                start_index: mat.start(),
+                end_index: mat.end(),
+                link_type: LinkType::Raw(raw.as_str()),
+                link_text: mat.as_str(),
+            });
+        }
+
+        let link_type = match (cap.get(0), cap.get(1), cap.get(2)) {
+            (_, Some(typ), Some(title)) if typ.as_str() == "title" => {
+                Some(LinkType::Title(title.as_str()))
+            }
+            (_, Some(typ), Some(rest))
+                if matches!(typ.as_str(), "include" | "include_code" | "rustdoc_include") =>
+            {
+                let (pth, props) = split_include_props(rest.as_str());
+                match typ.as_str() {
+                    "include" => Some(parse_include_path(pth, &props)),
+                    "include_code" => Some(parse_include_code_path(pth, &props)),
+                    _ => Some(parse_rustdoc_include_path(pth, &props)),
+                }
+            }
+            (_, Some(typ), Some(rest)) => {
+                let mut path_props = rest.as_str().split_whitespace();
+                let file_arg = path_props.next();
+                let props: Vec<&str> = path_props.collect();
+
+                match (typ.as_str(), file_arg) {
+                    ("include_svg", Some(_)) => Some(LinkType::SvgInclude(decode_include_path(
+                        rest.as_str().trim(),
+                    ))),
+                    ("include_tree", Some(pth)) => Some(LinkType::TreeInclude(
+                        decode_include_path(pth),
+                        TreeOptions::from_props(&props),
+                    )),
+                    ("playground", Some(pth)) => Some(LinkType::Playground(pth.into(), props)),
+                    ("playpen", Some(pth)) => {
+                        warn!(
+                            "the {{{{#playpen}}}} expression has been \
+                            renamed to {{{{#playground}}}}, \
+                            please update your book to use the new name"
+                        );
+                        Some(LinkType::Playground(pth.into(), props))
+                    }
+                    (name, _) if !BUILTIN_DIRECTIVES.contains(&name) => {
+                        Some(LinkType::Custom(name, rest.as_str().trim()))
+                    }
+                    _ => None,
+                }
+            }
+            (Some(mat), None, None) if mat.as_str().starts_with(ESCAPE_CHAR) => {
+                Some(LinkType::Escaped)
+            }
+            _ => None,
+        };
+
+        link_type.and_then(|lnk_type| {
+            cap.get(0).map(|mat| Link {
+                start_index: mat.start(),
+                end_index: mat.end(),
+                link_type: lnk_type,
+                link_text: mat.as_str(),
+            })
+        })
+    }
+
+    /// Expands a custom directive with its registered handler, or according
+    /// to the `unknown-directives` policy if there is none.
+    ///
+    /// Returns `None` for the built-in directives.
+    fn render_custom(
+        &self,
+        ctx: &DirectiveCtx<'_>,
+        options: &ReplaceOptions,
+    ) -> Option<Result<String>> {
+        let LinkType::Custom(name, args) = self.link_type else {
+            return None;
+        };
+        Some(match options.directives.handler(name) {
+            Some(handler) => handler(args, ctx),
+            None if options.unknown_directives == UnknownDirectives::Error => {
+                Err(anyhow::anyhow!("Unknown directive `{}`", name))
+            }
+            None => Ok(self.link_text.to_owned()),
+        })
+    }
+
+    fn render_with_path<P: AsRef<Path>>(
+        &self,
+        base: P,
+        chapter_title: &mut String,
+        diagnostics: &mut IncludeDiagnostics,
+        options: &ReplaceOptions,
+    ) -> Result<String> {
+        let base = base.as_ref();
+        match self.link_type {
+            // omit the escape char
+            LinkType::Escaped => Ok(self.link_text[1..].to_owned()),
+            // keep the contents of a raw block as-is
+            LinkType::Raw(raw) => Ok(raw.to_owned()),
+            LinkType::Include(ref pat, ref range_or_anchor, ref include_options) => {
+                let target = base.join(pat);
+
+                let s = fs::read_to_string(&target).with_context(|| {
+                    format!(
+                        "Could not read file for link {} ({})",
+                        self.link_text,
+                        target.display(),
+                    )
+                })?;
+                let (s, skipped) = include_options.skip_leading_lines(&s);
+                let s = take_included_lines(s, range_or_anchor, &target)?;
+                diagnostics.check(self.link_text, &s);
+                let first_line = range_or_anchor.first_line().map(|line| line + skipped);
+                Ok(include_options.apply(s, pat, first_line, &options.code_class_prefix))
+            }
+            LinkType::RemoteInclude(ref url, ref range_or_anchor, ref include_options) => {
+                let s = read_remote_include(url, options.remote_cache_dir.as_deref())?;
+                let (s, skipped) = include_options.skip_leading_lines(&s);
+                let s = take_included_lines(s, range_or_anchor, Path::new(url))?;
+                diagnostics.check(self.link_text, &s);
+                let first_line = range_or_anchor.first_line().map(|line| line + skipped);
+                Ok(
+                    include_options.apply(
+                        s,
+                        Path::new(url),
+                        first_line,
+                        &options.code_class_prefix,
+                    ),
+                )
+            }
+            LinkType::RustdocInclude(ref pat, ref range_or_anchor, ref include_options) => {
+                let target = base.join(pat);
+
+                let s = fs::read_to_string(&target).with_context(|| {
+                    format!(
+                        "Could not read file for link {} ({})",
+                        self.link_text,
+                        target.display(),
+                    )
+                })?;
+                let (s, _) = include_options.skip_leading_lines(&s);
+                let s = match range_or_anchor {
+                    RangeOrAnchor::Range(range) => take_rustdoc_include_lines(s, range.clone()),
+                    RangeOrAnchor::Anchor(anchor) => take_rustdoc_include_anchored_lines(s, anchor),
+                    RangeOrAnchor::AnchorOccurrence(anchor, n) => {
+                        take_rustdoc_include_nth_anchored_lines(s, anchor, *n)
+                            .with_context(|| anchor_occurrence_error(anchor, *n, &target))?
+                    }
+                    RangeOrAnchor::Section(_)
+                    | RangeOrAnchor::Versions(..)
+                    | RangeOrAnchor::DataValue(..) => {
+                        take_included_lines(s, range_or_anchor, &target)?
+                    }
+                };
+                diagnostics.check(self.link_text, &s);
+                // The hidden lines before the range are included too.
+                Ok(include_options.apply(s, pat, None, &options.code_class_prefix))
+            }
+            LinkType::Playground(ref pat, ref attrs) => {
+                let target = base.join(pat);
+
+                let mut contents = fs::read_to_string(&target).with_context(|| {
+                    format!(
+                        "Could not read file for link {} ({})",
+                        self.link_text,
+                        target.display()
+                    )
+                })?;
+                let ftype = if !attrs.is_empty() { "rust," } else { "rust" };
+                if !contents.ends_with('\n') {
+                    contents.push('\n');
+                }
+                Ok(format!(
+                    "```{}{}\n{}```\n",
+                    ftype,
+                    attrs.join(","),
+                    contents
+                ))
+            }
+            LinkType::SvgInclude(ref pat) => {
+                let target = base.join(pat);
+
+                let s = fs::read_to_string(&target).with_context(|| {
+                    format!(
+                        "Could not read file for link {} ({})",
+                        self.link_text,
+                        target.display()
+                    )
+                })?;
+                // The wrapper makes the SVG an HTML block even if its opening
+                // tag spans several lines, as long as it has no blank lines.
+                Ok(format!(
+                    "<div class=\"inline-svg\">\n{}\n</div>",
+                    sanitize_svg(&s)
+                ))
+            }
+            LinkType::TreeInclude(ref pat, ref tree_options) => {
+                let target = base.join(pat);
+
+                directory_tree(&target, tree_options).with_context(|| {
+                    format!(
+                        "Could not read directory for link {} ({})",
+                        self.link_text,
+                        target.display()
+                    )
+                })
+            }
+            LinkType::Title(title) => {
+                *chapter_title = title.to_owned();
+                Ok(String::new())
+            }
+            LinkType::Custom(..) => Ok(self.link_text.to_owned()),
+        }
+    }
+}
+
+struct LinkIter<'a>(CaptureMatches<'a, 'a>);
+
+impl<'a> Iterator for LinkIter<'a> {
+    type Item = Link<'a>;
+    fn next(&mut self) -> Option<Link<'a>> {
+        for cap in &mut self.0 {
+            if let Some(inc) = Link::from_capture(cap) {
+                return Some(inc);
+            }
+        }
+        None
+    }
+}
+
+/// Takes the lines of an included file selected by `range_or_anchor`.
+fn take_included_lines(s: &str, range_or_anchor: &RangeOrAnchor, target: &Path) -> Result<String> {
+    Ok(match range_or_anchor {
+        RangeOrAnchor::Range(range) => take_lines(s, range.clone()),
+        RangeOrAnchor::Anchor(anchor) => take_anchored_lines(s, anchor),
+        RangeOrAnchor::AnchorOccurrence(anchor, n) => take_nth_anchored_lines(s, anchor, *n)
+            .with_context(|| anchor_occurrence_error(anchor, *n, target))?,
+        RangeOrAnchor::Section(heading) => take_markdown_section(s, heading)
+            .with_context(|| {
+                format!(
+                    "Heading `{}` does not occur in {}",
+                    heading,
+                    target.display()
+                )
+            })?
+            .to_owned(),
+        RangeOrAnchor::Versions(from, to) => {
+            take_version_sections(s, from.as_deref(), to.as_deref(), target)?.to_owned()
+        }
+        RangeOrAnchor::DataValue(format, path) => take_data_value(s, *format, path, target)?,
+    })
+}
+
+/// Takes the text of the value at the dotted `path` of a JSON or YAML file,
+/// like `server.port` or `items.0.name`.
+fn take_data_value(s: &str, format: DataFormat, path: &str, target: &Path) -> Result<String> {
+    let value = match format {
+        DataFormat::Json => serde_json::from_str(s)
+            .with_context(|| format!("Could not parse {} as JSON", target.display()))?,
+        DataFormat::Yaml => parse_yaml(s)
+            .with_context(|| format!("Could not parse {} as YAML", target.display()))?,
+    };
+    let value = value_at_path(&value, path)
+        .with_context(|| format!("No value at `{}` in {}", path, target.display()))?;
+    Ok(value_to_text(value))
+}
+
+/// Takes the markdown sections of a changelog from the heading mentioning
+/// the version `from` to the end of the section of the heading mentioning
+/// `to`, in whichever order they occur.
+fn take_version_sections<'a>(
+    s: &'a str,
+    from: Option<&str>,
+    to: Option<&str>,
+    target: &Path,
+) -> Result<&'a str> {
+    let (Some(from), Some(to)) = (from, to) else {
+        bail!("Including the sections between two versions needs both `from=` and `to=`");
+    };
+    let find = |version: &str| {
+        find_version_section(s, version).with_context(|| {
+            format!(
+                "No heading mentions version `{}` in {}",
+                version,
+                target.display()
+            )
+        })
+    };
+    let (from, to) = (find(from)?, find(to)?);
+    Ok(s[from.start.min(to.start)..from.end.max(to.end)].trim_end())
+}
+
+/// How long fetching an included URL may take, including connecting to the
+/// host and reading the response.
+#[cfg(feature = "remote-include")]
+const REMOTE_INCLUDE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Reads the content of an included URL from the cache in `cache_dir`, or
+/// fetches and caches it if it isn't cached yet.
+#[cfg(feature = "remote-include")]
+fn read_remote_include(url: &str, cache_dir: Option<&Path>) -> Result<String> {
+    use std::io::Write;
+
+    // The cache file is named after a hash of the URL that is stable across
+    // releases.
+    let hash = crate::utils::fnv1a_hash(url.as_bytes());
+    let cache_file = cache_dir.map(|dir| dir.join(format!("{hash:016x}")));
+    if let Some(cache_file) = cache_file.as_ref().filter(|file| file.exists()) {
+        return fs::read_to_string(cache_file)
+            .with_context(|| format!("Could not read the cached content of {}", url));
+    }
+
+    // An unresponsive host would otherwise hang the build.
+    let agent = ureq::AgentBuilder::new()
+        .timeout(REMOTE_INCLUDE_TIMEOUT)
+        .build();
+    let content = agent
+        .get(url)
+        .call()
+        .with_context(|| format!("Could not fetch {}", url))?
+        .into_string()
+        .with_context(|| format!("Could not read the content of {}", url))?;
+    if let Some(cache_file) = cache_file {
+        crate::utils::fs::create_file(&cache_file)?
+            .write_all(content.as_bytes())
+            .with_context(|| format!("Could not cache the content of {}", url))?;
+    }
+    Ok(content)
+}
+
+#[cfg(not(feature = "remote-include"))]
+fn read_remote_include(url: &str, _cache_dir: Option<&Path>) -> Result<String> {
+    bail!(
+        "Could not include {}, mdBook must be built with the `remote-include` feature to include URLs",
+        url
+    )
+}
+
+fn anchor_occurrence_error(anchor: &str, n: usize, target: &Path) -> String {
+    format!(
+        "Anchor `{}` does not occur {} time(s) in {}",
+        anchor,
+        n,
+        target.display()
+    )
+}
+
+/// Makes an SVG image safe to insert inline into a page.
+///
+/// This is deliberately conservative rather than a full sanitizer, so only
+/// trusted images should be included. It removes:
+///
+/// - `<script>` and `<foreignObject>` elements, with their content,
+/// - `on*` event handler attributes, like `onclick`,
+/// - `href` and `xlink:href` attributes with a URL that isn't safe, like
+///   `javascript:` or `data:`, see [`is_safe_url`],
+/// - `<set>` and `<animate>` elements changing an `href` attribute, which
+///   could set such a URL,
+/// - the XML declaration, doctype and comments, which don't belong in HTML,
+/// - blank lines, which would end the HTML block in markdown.
+///
+/// Shapes, styles and everything else are kept as they are.
+fn sanitize_svg(svg: &str) -> String {
+    static REMOVED: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(
+            r"(?xis)
+            <\?xml.*?\?>
+            | <!DOCTYPE[^>]*>
+            | <!--(?:-?>|.*?--!?>|.*)
+            | <script\b[^>]*?/>
+            | <script\b.*?</script\s*>
+            | <foreignObject\b[^>]*?/>
+            | <foreignObject\b.*?</foreignObject\s*>",
+        )
+        .unwrap()
+    });
+    static TAG: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(
+            r#"<([a-zA-Z][^\s/>]*)(?:\s+[^\s=/>]+(?:\s*=\s*(?:"[^"]*"|'[^']*'|[^\s"'>]+))?)*\s*/?>"#,
+        )
+        .unwrap()
+    });
+    static ATTR: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r#"\s+([^\s=/>]+)(?:\s*=\s*("[^"]*"|'[^']*'|[^\s"'>]+))?"#).unwrap()
+    });
+
+    let svg = REMOVED.replace_all(svg, "");
+    let attr_value = |attr: &Captures<'_>| {
+        attr.get(2)
+            .map_or("", |value| value.as_str().trim_matches(['"', '\'']))
+            .to_owned()
+    };
+    let svg = TAG.replace_all(&svg, |caps: &Captures<'_>| {
+        let element = caps[1].to_ascii_lowercase();
+        if (element == "set" || element == "animate")
+            && ATTR.captures_iter(&caps[0]).any(|attr| {
+                attr[1].eq_ignore_ascii_case("attributename")
+                    && matches!(
+                        attr_value(&attr).trim().to_ascii_lowercase().as_str(),
+                        "href" | "xlink:href"
+                    )
+            })
+        {
+            return String::new();
+        }
+        ATTR.replace_all(&caps[0], |attr: &Captures<'_>| {
+            let name = attr[1].to_ascii_lowercase();
+            let is_unsafe = name.starts_with("on")
+                || ((name == "href" || name == "xlink:href") && !is_safe_url(&attr_value(attr)));
+            if is_unsafe {
+                String::new()
+            } else {
+                attr[0].to_owned()
+            }
+        })
+        .into_owned()
+    });
+    svg.lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn find_links(contents: &str) -> LinkIter<'_> {
+    // lazily compute following regex
+    // r"\\\{\{#.*\}\}|\{\{#([a-zA-Z0-9]+)\s*([^}]+)\}\}")?;
+    static RE: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(
+            r"(?x)              # insignificant whitespace mode
+        \\\{\{\#.*\}\}      # match escaped link
+        |                   # or
+        \{\{\s*             # link opening parens and whitespace
+        \#([a-zA-Z0-9_]+)   # link type
+        \s+                 # separating whitespace
+        ([^}]+)             # link target path and space separated properties
+        \}\}                # link closing parens
+        |                   # or
+        \{\{\#raw\}\}        # raw block opening
+        (?s:(.*?))          # raw block contents, kept verbatim
+        \{\{/raw\}\}        # raw block closing",
+        )
+        .unwrap()
+    });
+
+    LinkIter(RE.captures_iter(contents))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replace_all_escaped() {
+        let start = r"
+        Some text over here.
+        ```hbs
+        \{{#include file.rs}} << an escaped link!
+        ```";
+        let end = r"
+        Some text over here.
+        ```hbs
+        {{#include file.rs}} << an escaped link!
+        ```";
+        let mut chapter_title = "test_replace_all_escaped".to_owned();
+        assert_eq!(
+            replace_all(
+                start,
+                "",
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            end
+        );
+    }
+
+    #[test]
+    fn test_replace_all_raw() {
+        let start = "Some text over here.\n\
+                     {{#raw}}\n\
+                     {{#include file.rs}} and {{#playground file.rs editable}}\n\
+                     \\{{#title Title}}\n\
+                     {{/raw}}\n\
+                     {{#raw}}{{#rustdoc_include file.rs:2}}{{/raw}}";
+        let end = "Some text over here.\n\
+                   \n\
+                   {{#include file.rs}} and {{#playground file.rs editable}}\n\
+                   \\{{#title Title}}\n\
+                   \n\
+                   {{#rustdoc_include file.rs:2}}";
+        let mut chapter_title = "test_replace_all_raw".to_owned();
+        assert_eq!(
+            replace_all(
+                start,
+                "",
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            end
+        );
+        assert_eq!(chapter_title, "test_replace_all_raw");
+    }
+
+    #[test]
+    fn test_replace_all_include_with_language() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(temp.path().join("snippet.txt"), "fn main() {}\n").unwrap();
+        fs::write(temp.path().join("snippet.rs"), "fn main() {}\n").unwrap();
+        fs::write(temp.path().join("snippet"), "fn main() {}\n").unwrap();
+        let mut chapter_title = "test_replace_all_include_with_language".to_owned();
+
+        let start = "{{#include snippet.txt lang=rust}}";
+        let end = "```rust\nfn main() {}\n```";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            end
+        );
+
+        let start = "{{#include snippet.rs lang}}";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            end
+        );
+
+        let start = "{{#include snippet lang}}";
+        let end = "```\nfn main() {}\n```";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            end
+        );
+
+        let start = "{{#include snippet.rs}}";
+        let end = "fn main() {}";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            end
+        );
+    }
+
+    /// Serves `body` over HTTP to the given number of `requests`, returning
+    /// the URL of the server.
+    #[cfg(feature = "remote-include")]
+    fn serve(body: &'static str, requests: usize) -> (String, std::thread::JoinHandle<()>) {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        (url, server)
+    }
+
+    #[test]
+    #[cfg(feature = "remote-include")]
+    fn test_replace_all_remote_include() {
+        let (url, server) = serve(
+            "fn main() {\n    // ANCHOR: body\n    println!(\"hi\");\n    // ANCHOR_END: body\n}\n",
+            1,
+        );
+        let cache = tempfile::tempdir().unwrap();
+        let options = ReplaceOptions {
+            remote_cache_dir: Some(cache.path().to_path_buf()),
+            ..ReplaceOptions::default()
+        };
+        let mut chapter_title = "test_replace_all_remote_include".to_owned();
+        let mut diagnostics = IncludeDiagnostics::default();
+        let start =
+            format!("{{{{#include {url}/main.rs:1}}}}\n{{{{#include {url}/main.rs:body lang}}}}");
+        let end = "fn main() {\n```rust\n    println!(\"hi\");\n```";
+        let replaced = replace_all(
+            &start,
+            "",
+            "",
+            0,
+            &mut chapter_title,
+            &mut diagnostics,
+            &options,
+        );
+        assert!(diagnostics.errors.is_empty(), "{:?}", diagnostics.errors);
+        assert_eq!(replaced, end);
+
+        // The server only answers once, so the second include and this
+        // build use the cached content.
+        server.join().unwrap();
+        let replaced = replace_all(
+            &start,
+            "",
+            "",
+            0,
+            &mut chapter_title,
+            &mut diagnostics,
+            &options,
+        );
+        assert!(diagnostics.errors.is_empty(), "{:?}", diagnostics.errors);
+        assert_eq!(replaced, end);
+        assert_eq!(fs::read_dir(cache.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "remote-include")]
+    fn test_replace_all_remote_include_network_error() {
+        // Nothing listens on the port of a dropped listener.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/main.rs", listener.local_addr().unwrap());
+        drop(listener);
+
+        let start = format!("Text {{{{#include {url}}}}}");
+        let mut chapter_title = "test_replace_all_remote_include_network_error".to_owned();
+        let mut diagnostics = IncludeDiagnostics::default();
+        let replaced = replace_all(
+            &start,
+            "",
+            "",
+            0,
+            &mut chapter_title,
+            &mut diagnostics,
+            &ReplaceOptions::default(),
+        );
+        assert_eq!(replaced, start);
+        assert_eq!(diagnostics.errors.len(), 1);
+        assert_eq!(
+            diagnostics.errors[0].to_string(),
+            format!("Error updating {{{{#include {url}}}}}")
+        );
+        assert_eq!(
+            diagnostics.errors[0].chain().nth(1).unwrap().to_string(),
+            format!("Could not fetch {url}")
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "remote-include"))]
+    fn test_replace_all_remote_include_needs_feature() {
+        let start = "{{#include https://example.com/main.rs}}";
+        let mut chapter_title = "test_replace_all_remote_include_needs_feature".to_owned();
+        let mut diagnostics = IncludeDiagnostics::default();
+        let replaced = replace_all(
+            start,
+            "",
+            "",
+            0,
+            &mut chapter_title,
+            &mut diagnostics,
+            &ReplaceOptions::default(),
+        );
+        assert_eq!(replaced, start);
+        assert_eq!(diagnostics.errors.len(), 1);
+        assert!(format!("{:#}", diagnostics.errors[0]).contains("`remote-include` feature"));
+    }
+
+    #[test]
+    fn test_replace_all_reports_long_lines() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(
+            temp.path().join("long.txt"),
+            "short\n0123456789ab\n\tshort\n",
+        )
+        .unwrap();
+        let mut chapter_title = "test_replace_all_reports_long_lines".to_owned();
+        let mut config = toml::value::Table::new();
+        config.insert("max-line-length".into(), 10.into());
+        let mut diagnostics = IncludeDiagnostics::from_config(Some(&config));
+
+        let start = "{{#include long.txt}}";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut diagnostics,
+                &ReplaceOptions::default(),
+            ),
+            "short\n0123456789ab\n\tshort"
+        );
+        assert_eq!(
+            diagnostics.long_lines,
+            vec![LongLine {
+                link_text: start.to_owned(),
+                line: 2,
+                width: 12,
+            }]
+        );
+
+        config.insert("tab-width".into(), 8.into());
+        let mut diagnostics = IncludeDiagnostics::from_config(Some(&config));
+        let start = "{{#include long.txt:3}}";
+        replace_all(
+            start,
+            temp.path(),
+            "",
+            0,
+            &mut chapter_title,
+            &mut diagnostics,
+            &ReplaceOptions::default(),
+        );
+        assert_eq!(
+            diagnostics.long_lines,
+            vec![LongLine {
+                link_text: start.to_owned(),
+                line: 1,
+                width: 13,
+            }]
+        );
+
+        let mut diagnostics = IncludeDiagnostics::default();
+        replace_all(
+            start,
+            temp.path(),
+            "",
+            0,
+            &mut chapter_title,
+            &mut diagnostics,
+            &ReplaceOptions::default(),
+        );
+        assert!(diagnostics.long_lines.is_empty());
+    }
+
+    #[test]
+    fn test_replace_all_skips_code_blocks() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(temp.path().join("file.txt"), "included\n").unwrap();
+        let mut chapter_title = "test_replace_all_skips_code_blocks".to_owned();
+
+        let start = "{{#include file.txt}}\n\n\
+                     ```hbs\n{{#include file.txt}}\n```\n\n\
+                     ~~~~\n{{#include file.txt}}\n~~~~\n\n    \
+                     {{#include file.txt}}\n\n\
+                     Text {{#include file.txt}}\n";
+        let end = "included\n\n\
+                   ```hbs\n{{#include file.txt}}\n```\n\n\
+                   ~~~~\n{{#include file.txt}}\n~~~~\n\n    \
+                   {{#include file.txt}}\n\n\
+                   Text included\n";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions {
+                    skip_code_blocks: true,
+                    ..ReplaceOptions::default()
+                },
+            ),
+            end
+        );
+
+        let start = "```\n{{#include file.txt}}\n```";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default(),
+            ),
+            "```\nincluded\n```"
+        );
+    }
+
+    #[test]
+    fn test_expand_includes() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(temp.path().join("lines.txt"), "one\ntwo\nthree\nfour\n").unwrap();
+        fs::create_dir(temp.path().join("nested")).unwrap();
+        fs::write(
+            temp.path().join("nested/outer.md"),
+            "before {{#include inner.md}} after",
+        )
+        .unwrap();
+        fs::write(temp.path().join("nested/inner.md"), "inner").unwrap();
+
+        let text = "Lines:\n{{#include lines.txt:2:3}}\n{{#playground example.rs}}";
+        assert_eq!(
+            expand_includes(text, temp.path()).unwrap(),
+            "Lines:\ntwo\nthree\n{{#playground example.rs}}"
+        );
+        assert_eq!(
+            expand_includes("{{#include nested/outer.md}}", temp.path()).unwrap(),
+            "before inner after"
+        );
+
+        let err = expand_includes("{{#include missing.txt}}", temp.path()).unwrap_err();
+        assert!(format!("{:?}", err).contains("Could not read file"));
+        let err = expand_includes("{{#include lines.txt:10:12}}", temp.path()).unwrap_err();
+        assert!(format!("{:?}", err).contains("Line 10 is past the end of the file"));
+        let err = expand_includes("{{#include lines.txt:missing}}", temp.path()).unwrap_err();
+        assert!(format!("{:?}", err).contains("Anchor `missing` does not occur"));
+    }
+
+    #[test]
+    fn test_validate_includes() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(
+            temp.path().join("lines.txt"),
+            "// ANCHOR: used\none\n// ANCHOR_END: used\ntwo\n",
+        )
+        .unwrap();
+
+        let text = "{{#include lines.txt:used}}\n\
+                    {{#include missing.txt}}\n\
+                    \\{{#include escaped.txt}}\n\
+                    {{#include lines.txt:10:12}} and {{#rustdoc_include lines.txt:unused}}\n\
+                    {{#playground missing.rs}}\n";
+        let errors = validate_includes(text, temp.path());
+        assert_eq!(
+            errors,
+            vec![
+                IncludeError {
+                    offset: 28,
+                    directive: "{{#include missing.txt}}".to_owned(),
+                    kind: IncludeErrorKind::MissingFile(temp.path().join("missing.txt")),
+                },
+                IncludeError {
+                    offset: 79,
+                    directive: "{{#include lines.txt:10:12}}".to_owned(),
+                    kind: IncludeErrorKind::BadRange { line: 10, lines: 4 },
+                },
+                IncludeError {
+                    offset: 112,
+                    directive: "{{#rustdoc_include lines.txt:unused}}".to_owned(),
+                    kind: IncludeErrorKind::MissingAnchor("unused".to_owned()),
+                },
+                IncludeError {
+                    offset: 150,
+                    directive: "{{#playground missing.rs}}".to_owned(),
+                    kind: IncludeErrorKind::MissingFile(temp.path().join("missing.rs")),
+                },
+            ]
+        );
+        for error in &errors {
+            assert_eq!(
+                &text[error.offset..error.offset + error.directive.len()],
+                error.directive
+            );
+        }
+        assert_eq!(
+            errors[1].to_string(),
+            "{{#include lines.txt:10:12}} at byte 79: \
+             Line 10 is past the end of the file, which has 4 line(s)"
+        );
+
+        assert!(validate_includes("{{#include lines.txt:2}}", temp.path()).is_empty());
+    }
+
+    #[test]
+    fn test_replace_all_include_expand_tabs() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(
+            temp.path().join("tabs.rs"),
+            "fn main() {\n\tlet s = \"a\tb\";\n}\n",
+        )
+        .unwrap();
+        let mut chapter_title = "test_replace_all_include_expand_tabs".to_owned();
+
+        let inputs = [
+            (
+                "{{#include tabs.rs expand-tabs=4}}",
+                "fn main() {\n    let s = \"a\tb\";\n}",
+            ),
+            (
+                "{{#include tabs.rs expand-all-tabs=4}}",
+                "fn main() {\n    let s = \"a  b\";\n}",
+            ),
+            (
+                "{{#include tabs.rs:2 expand-tabs=2 lang}}",
+                "```rust\n  let s = \"a\tb\";\n```",
+            ),
+        ];
+        for (start, end) in inputs {
+            assert_eq!(
+                replace_all(
+                    start,
+                    temp.path(),
+                    "",
+                    0,
+                    &mut chapter_title,
+                    &mut IncludeDiagnostics::default(),
+                    &ReplaceOptions::default()
+                ),
+                end
+            );
+        }
+    }
+
+    #[test]
+    fn test_replace_all_include_skip_leading_lines() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(
+            temp.path().join("point.rs"),
+            "// SPDX-License-Identifier: MIT\n// Copyright the authors\n#![allow(unused)]\n\
+             #[derive(Debug)]\nstruct Point;\n\nfn main() {}\n",
+        )
+        .unwrap();
+        let mut chapter_title = "test_replace_all_include_skip_leading_lines".to_owned();
+
+        let inputs = [
+            (
+                "{{#include point.rs skip-lines=2}}",
+                "#![allow(unused)]\n#[derive(Debug)]\nstruct Point;\n\nfn main() {}",
+            ),
+            (
+                "{{#include point.rs skip-leading=^//}}",
+                "#![allow(unused)]\n#[derive(Debug)]\nstruct Point;\n\nfn main() {}",
+            ),
+            (
+                "{{#include point.rs skip-lines=2 skip-leading=^#!?\\[}}",
+                "struct Point;\n\nfn main() {}",
+            ),
+            // Ranges count the lines after the skipped ones.
+            (
+                "{{#include point.rs:1:2 skip-lines=2}}",
+                "#![allow(unused)]\n#[derive(Debug)]",
+            ),
+            ("{{#include point.rs skip-lines=20}}", ""),
+        ];
+        for (start, end) in inputs {
+            assert_eq!(
+                replace_all(
+                    start,
+                    temp.path(),
+                    "",
+                    0,
+                    &mut chapter_title,
+                    &mut IncludeDiagnostics::default(),
+                    &ReplaceOptions::default()
+                ),
+                end
+            );
+        }
+    }
+
+    #[test]
+    fn test_replace_all_include_strip_hidden() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(
+            temp.path().join("example.rs"),
+            "# use std::fmt;\n#\nfn main() {\n    # let hidden = 1;\n    println!(\"##\");\n##[escaped]\n}\n",
+        )
+        .unwrap();
+        let mut chapter_title = "test_replace_all_include_strip_hidden".to_owned();
+
+        let start = "{{#include example.rs strip-hidden}}";
+        let end = "fn main() {\n    println!(\"##\");\n##[escaped]\n}";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            end
+        );
+
+        let start = "{{#include example.rs:3:4}}";
+        let end = "fn main() {\n    # let hidden = 1;";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            end
+        );
+    }
+
+    #[test]
+    fn test_replace_all_include_code() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(
+            temp.path().join("config.json"),
+            "{\n  \"html\": \"<b>bold</b> & co\"\n}\n",
+        )
+        .unwrap();
+        fs::write(temp.path().join("snippet"), "a < b\n").unwrap();
+        let mut chapter_title = "test_replace_all_include_code".to_owned();
+
+        let start = "{{#include_code config.json}}";
+        let end = "<pre><code class=\"language-json\">{\n  &quot;html&quot;: \
+                   &quot;&lt;b&gt;bold&lt;/b&gt; &amp; co&quot;\n}\n</code></pre>";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            end
+        );
+
+        let start = "{{#include_code config.json:2 lang=js}}";
+        let end = "<pre><code class=\"language-js\">  &quot;html&quot;: \
+                   &quot;&lt;b&gt;bold&lt;/b&gt; &amp; co&quot;\n</code></pre>";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            end
+        );
+
+        let start = "{{#include_code snippet}}";
+        let end = "<pre><code>a &lt; b\n</code></pre>";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            end
+        );
+
+        // The language class uses the configured prefix.
+        let start = "{{#include_code snippet lang=text}}";
+        let end = "<pre><code class=\"lang-text\">a &lt; b\n</code></pre>";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions {
+                    code_class_prefix: "lang-".to_owned(),
+                    ..ReplaceOptions::default()
+                }
+            ),
+            end
+        );
+    }
+
+    #[test]
+    fn test_replace_all_include_anchor_occurrence() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(
+            temp.path().join("snippet.rs"),
+            "// ANCHOR: example\nfirst\n// ANCHOR_END: example\n\
+             // ANCHOR: example\nsecond\n// ANCHOR_END: example\n",
+        )
+        .unwrap();
+        let mut chapter_title = "test_replace_all_include_anchor_occurrence".to_owned();
+
+        let start = "{{#include snippet.rs:example#2}}";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            "second"
+        );
+
+        let start = "{{#include snippet.rs:example}}";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            "first"
+        );
+
+        // An out of range occurrence is an error, so the link is kept as-is.
+        let start = "{{#include snippet.rs:example#3}}";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            start
+        );
+        let start = "{{#rustdoc_include snippet.rs:example#3}}";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            start
+        );
+    }
+
+    #[test]
+    fn test_replace_all_include_markdown_section() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(
+            temp.path().join("other.md"),
+            "# Guide\n\nIntro.\n\n## Installation\n\nRun the installer.\n\n\
+             ### On Windows\n\nUse the MSI.\n\n## Usage\n\nRun it.\n",
+        )
+        .unwrap();
+        let mut chapter_title = "test_replace_all_include_markdown_section".to_owned();
+
+        let end = "## Installation\n\nRun the installer.\n\n### On Windows\n\nUse the MSI.";
+        for start in [
+            "{{#include other.md #Installation}}",
+            "{{#include other.md #installation}}",
+        ] {
+            assert_eq!(
+                replace_all(
+                    start,
+                    temp.path(),
+                    "",
+                    0,
+                    &mut chapter_title,
+                    &mut IncludeDiagnostics::default(),
+                    &ReplaceOptions::default()
+                ),
+                end
+            );
+        }
+
+        let start = "{{#include other.md #On Windows}}";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            "### On Windows\n\nUse the MSI."
+        );
+
+        // A missing heading is an error, so the link is kept as-is.
+        let start = "{{#include other.md #Uninstallation}}";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            start
+        );
+        let err = expand_includes(start, temp.path()).unwrap_err();
+        assert!(format!("{:#}", err).contains("Heading `Uninstallation` does not occur"));
+    }
+
+    #[test]
+    fn test_replace_all_include_trim_trailing() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(
+            temp.path().join("snippet.rs"),
+            "fn main() {  \n    let x = 5;\t\n\n}\n",
+        )
+        .unwrap();
+        let mut chapter_title = "test_replace_all_include_trim_trailing".to_owned();
+
+        let start = "{{#include snippet.rs trim-trailing}}";
+        let end = "fn main() {\n    let x = 5;\n\n}";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            end
+        );
+
+        let start = "{{#rustdoc_include snippet.rs:2 trim-trailing}}";
+        let end = "# fn main() {\n    let x = 5;\n#\n# }";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            end
+        );
+
+        let start = "{{#include snippet.rs:1}}";
+        let end = "fn main() {  ";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            end
+        );
+    }
+
+    #[test]
+    fn test_sanitize_svg() {
+        let svg = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- Made by hand -->
+<svg xmlns="http://www.w3.org/2000/svg"
+     width="100" height="50" onload="alert(1)">
+
+  <style>.box { fill: teal; }</style>
+  <script type="text/javascript">alert("svg");</script>
+  <SCRIPT href="evil.js"/>
+  <a xlink:href=" javascript:alert(2)" href="https://example.com"><text class="one onward">Hi</text></a>
+  <rect class="box" width="10" height="10" ONCLICK='alert(3)' opacity="0.5"/>
+  <foreignObject><iframe src="javascript:alert(4)"></iframe></foreignObject>
+  <a href="&#106;avascript:alert(5)"><text>Encoded</text></a>
+  <a href="data:text/html,<script>alert(6)</script>"><text>Data</text></a>
+  <a href="top.html"><set attributeName="href" to="javascript:alert(7)"/><text>Set</text></a>
+  <a><animate attributeName="xlink:href" values="javascript:alert(8)"/></a>
+  <animate attributeName="opacity" values="0;1" dur="1s"/>
+  <!--><script>alert(9)</script>-->
+  <!-- --!><rect onclick="alert(10)"/> -->
+</svg>
+"#;
+        assert_eq!(
+            sanitize_svg(svg),
+            r#"<svg xmlns="http://www.w3.org/2000/svg"
+     width="100" height="50">
+  <style>.box { fill: teal; }</style>
+  <a href="https://example.com"><text class="one onward">Hi</text></a>
+  <rect class="box" width="10" height="10" opacity="0.5"/>
+  <a><text>Encoded</text></a>
+  <a><text>Data</text></a>
+  <a href="top.html"><text>Set</text></a>
+  <a></a>
+  <animate attributeName="opacity" values="0;1" dur="1s"/>
+  -->
+  <rect/> -->
+</svg>"#
+        );
+    }
+
+    #[test]
+    fn test_replace_all_include_svg() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(
+            temp.path().join("diagram.svg"),
+            "<svg viewBox=\"0 0 10 10\">\n\n<script>alert(1)</script>\n\
+             <circle cx=\"5\" cy=\"5\" r=\"4\" style=\"fill: red\" onmouseover=\"alert(2)\"/>\n</svg>\n",
+        )
+        .unwrap();
+
+        let start = "Before\n\n{{#include_svg diagram.svg}}\n\nAfter";
+        let end = "Before\n\n<div class=\"inline-svg\">\n<svg viewBox=\"0 0 10 10\">\n\
+                   <circle cx=\"5\" cy=\"5\" r=\"4\" style=\"fill: red\"/>\n</svg>\n</div>\n\nAfter";
+        let mut chapter_title = "test_replace_all_include_svg".to_owned();
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            end
+        );
+
+        // The SVG stays one HTML block in the rendered page.
+        let html = crate::utils::render_markdown(end, false);
+        assert!(html.contains("<p>Before</p>\n<div class=\"inline-svg\">\n<svg viewBox"));
+        assert!(html.contains("</svg>\n</div>\n<p>After</p>"));
+        assert!(!html.contains("script"));
+    }
+
+    #[test]
+    fn test_replace_all_include_tree() {
+        let temp = tempfile::tempdir().unwrap();
+        let examples = temp.path().join("examples");
+        for dir in ["src/bin", "assets", ".git"] {
+            fs::create_dir_all(examples.join(dir)).unwrap();
+        }
+        for file in [
+            "Cargo.toml",
+            "README.md",
+            ".gitignore",
+            "src/main.rs",
+            "src/lib.rs",
+            "src/bin/tool.rs",
+            "assets/logo.svg",
+        ] {
+            fs::write(examples.join(file), "").unwrap();
+        }
+
+        let replace = |start: &str| {
+            let mut chapter_title = "test_replace_all_include_tree".to_owned();
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default(),
+            )
+        };
+        assert_eq!(
+            replace("{{#include_tree examples/}}\n"),
+            "- `assets/`\n  - `logo.svg`\n- `src/`\n  - `bin/`\n    - `tool.rs`\n  \
+             - `lib.rs`\n  - `main.rs`\n- `Cargo.toml`\n- `README.md`\n"
+        );
+        assert_eq!(
+            replace("{{#include_tree examples/ depth=2}}\n"),
+            "- `assets/`\n  - `logo.svg`\n- `src/`\n  - `bin/`\n  - `lib.rs`\n  \
+             - `main.rs`\n- `Cargo.toml`\n- `README.md`\n"
+        );
+        assert_eq!(
+            replace("{{#include_tree examples depth=1 hidden}}\n"),
+            "- `.git/`\n- `assets/`\n- `src/`\n- `.gitignore`\n- `Cargo.toml`\n- `README.md`\n"
+        );
+        // A missing directory leaves the directive as it is.
+        assert_eq!(
+            replace("{{#include_tree missing/}}\n"),
+            "{{#include_tree missing/}}\n"
+        );
+
+        let html = crate::utils::render_markdown(&replace("{{#include_tree examples/src}}"), false);
+        assert_eq!(
+            html,
+            "<ul>\n<li><code>bin/</code>\n<ul>\n<li><code>tool.rs</code></li>\n</ul>\n</li>\n\
+             <li><code>lib.rs</code></li>\n<li><code>main.rs</code></li>\n</ul>\n"
+        );
+    }
+
+    #[test]
+    fn test_replace_all_include_data_values() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(
+            temp.path().join("config.json"),
+            r#"{"server": {"port": 8080, "host": "localhost"}, "items": [{"name": "first"}]}"#,
+        )
+        .unwrap();
+        fs::write(
+            temp.path().join("config.yml"),
+            "server:\n  port: 8080 # the default\n  host: localhost\nitems:\n  - name: first\n",
+        )
+        .unwrap();
+        let mut chapter_title = "test_replace_all_include_data_values".to_owned();
+
+        for file in ["config.json json:", "config.yml yaml:"] {
+            let start = format!(
+                "Port {{{{#include {file}server.port}}}} on \
+                 `{{{{#include {file}server.host}}}}`, {{{{#include {file}items.0.name}}}}."
+            );
+            assert_eq!(
+                replace_all(
+                    &start,
+                    temp.path(),
+                    "",
+                    0,
+                    &mut chapter_title,
+                    &mut IncludeDiagnostics::default(),
+                    &ReplaceOptions::default()
+                ),
+                "Port 8080 on `localhost`, first."
+            );
+
+            // A missing path is an error, so the link is kept as-is.
+            let start = format!("{{{{#include {file}server.timeout}}}}");
+            assert_eq!(
+                replace_all(
+                    &start,
+                    temp.path(),
+                    "",
+                    0,
+                    &mut chapter_title,
+                    &mut IncludeDiagnostics::default(),
+                    &ReplaceOptions::default()
+                ),
+                start
+            );
+            let err = expand_includes(&start, temp.path()).unwrap_err();
+            assert!(format!("{:#}", err).contains("No value at `server.timeout` in"));
+        }
 
-    LinkType::RustdocInclude(path, range_or_anchor)
-}
+        fs::write(temp.path().join("broken.json"), "{").unwrap();
+        let err = expand_includes("{{#include broken.json json:a}}", temp.path()).unwrap_err();
+        assert!(format!("{:#}", err).contains("Could not parse"));
+    }
 
-#[derive(PartialEq, Debug, Clone)]
-struct Link<'a> {
-    start_index: usize,
-    end_index: usize,
-    link_type: LinkType<'a>,
-    link_text: &'a str,
-}
+    #[test]
+    fn test_replace_all_include_version_sections() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(
+            temp.path().join("CHANGELOG.md"),
+            "# Changelog\n\n## [1.6.0]\n\n- Six.\n\n## [1.5.0] - 2024-03-01\n\n### Fixed\n\n\
+             - Five.\n\n## 1.4.0\n\n- Four.\n\n## v1.2.0\n\n- Two.\n\n## 1.1.0\n\n- One.\n",
+        )
+        .unwrap();
+        let mut chapter_title = "test_replace_all_include_version_sections".to_owned();
+
+        let end = "## [1.5.0] - 2024-03-01\n\n### Fixed\n\n- Five.\n\n## 1.4.0\n\n- Four.\n\n\
+                   ## v1.2.0\n\n- Two.";
+        for start in [
+            "{{#include CHANGELOG.md from=1.2.0 to=1.5.0}}",
+            "{{#include CHANGELOG.md from=1.5.0 to=1.2.0}}",
+        ] {
+            assert_eq!(
+                replace_all(
+                    start,
+                    temp.path(),
+                    "",
+                    0,
+                    &mut chapter_title,
+                    &mut IncludeDiagnostics::default(),
+                    &ReplaceOptions::default()
+                ),
+                end
+            );
+        }
 
-impl<'a> Link<'a> {
-    fn from_capture(cap: Captures<'a>) -> Option<Link<'a>> {
-        let link_type = match (cap.get(0), cap.get(1), cap.get(2)) {
-            (_, Some(typ), Some(title)) if typ.as_str() == "title" => {
-                Some(LinkType::Title(title.as_str()))
-            }
-            (_, Some(typ), Some(rest)) => {
-                let mut path_props = rest.as_str().split_whitespace();
-                let file_arg = path_props.next();
-                let props: Vec<&str> = path_props.collect();
+        // A version that no heading mentions is an error, so the link is kept
+        // as-is.
+        let start = "{{#include CHANGELOG.md from=1.2.0 to=1.7.0}}";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            start
+        );
+        let err = expand_includes(start, temp.path()).unwrap_err();
+        assert!(format!("{:#}", err).contains("No heading mentions version `1.7.0`"));
 
-                match (typ.as_str(), file_arg) {
-                    ("include", Some(pth)) => Some(parse_include_path(pth)),
-                    ("playground", Some(pth)) => Some(LinkType::Playground(pth.into(), props)),
-                    ("playpen", Some(pth)) => {
-                        warn!(
-                            "the {{{{#playpen}}}} expression has been \
-                            renamed to {{{{#playground}}}}, \
-                            please update your book to use the new name"
-                        );
-                        Some(LinkType::Playground(pth.into(), props))
-                    }
-                    ("rustdoc_include", Some(pth)) => Some(parse_rustdoc_include_path(pth)),
-                    _ => None,
-                }
-            }
-            (Some(mat), None, None) if mat.as_str().starts_with(ESCAPE_CHAR) => {
-                Some(LinkType::Escaped)
-            }
-            _ => None,
-        };
+        // `1.5` is only part of the version `1.5.0`.
+        let err = expand_includes("{{#include CHANGELOG.md from=1.2.0 to=1.5}}", temp.path())
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("No heading mentions version `1.5`"));
 
-        link_type.and_then(|lnk_type| {
-            cap.get(0).map(|mat| Link {
-                start_index: mat.start(),
-                end_index: mat.end(),
-                link_type: lnk_type,
-                link_text: mat.as_str(),
-            })
-        })
+        let err = expand_includes("{{#include CHANGELOG.md from=1.2.0}}", temp.path()).unwrap_err();
+        assert!(format!("{:#}", err).contains("needs both `from=` and `to=`"));
     }
 
-    fn render_with_path<P: AsRef<Path>>(
-        &self,
-        base: P,
-        chapter_title: &mut String,
-    ) -> Result<String> {
-        let base = base.as_ref();
-        match self.link_type {
-            // omit the escape char
-            LinkType::Escaped => Ok(self.link_text[1..].to_owned()),
-            LinkType::Include(ref pat, ref range_or_anchor) => {
-                let target = base.join(pat);
+    #[test]
+    fn test_replace_all_include_trim_blank_lines() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(
+            temp.path().join("snippet.rs"),
+            "// ANCHOR: body\n\n    let x = 5;\n\n    let y = 6;\n  \n// ANCHOR_END: body\n\
+             // ANCHOR: blank\n\n\t\n// ANCHOR_END: blank\n",
+        )
+        .unwrap();
+        let mut chapter_title = "test_replace_all_include_trim_blank_lines".to_owned();
 
-                fs::read_to_string(&target)
-                    .map(|s| match range_or_anchor {
-                        RangeOrAnchor::Range(range) => take_lines(&s, range.clone()),
-                        RangeOrAnchor::Anchor(anchor) => take_anchored_lines(&s, anchor),
-                    })
-                    .with_context(|| {
-                        format!(
-                            "Could not read file for link {} ({})",
-                            self.link_text,
-                            target.display(),
-                        )
-                    })
-            }
-            LinkType::RustdocInclude(ref pat, ref range_or_anchor) => {
-                let target = base.join(pat);
+        let start = "{{#include snippet.rs:body trim-blank-lines}}";
+        let end = "    let x = 5;\n\n    let y = 6;";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            end
+        );
 
-                fs::read_to_string(&target)
-                    .map(|s| match range_or_anchor {
-                        RangeOrAnchor::Range(range) => {
-                            take_rustdoc_include_lines(&s, range.clone())
-                        }
-                        RangeOrAnchor::Anchor(anchor) => {
-                            take_rustdoc_include_anchored_lines(&s, anchor)
-                        }
-                    })
-                    .with_context(|| {
-                        format!(
-                            "Could not read file for link {} ({})",
-                            self.link_text,
-                            target.display(),
-                        )
-                    })
-            }
-            LinkType::Playground(ref pat, ref attrs) => {
-                let target = base.join(pat);
+        let start = "{{#include snippet.rs:blank trim-blank-lines lang=rust}}";
+        let end = "```rust\n\n```";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            end
+        );
+    }
 
-                let mut contents = fs::read_to_string(&target).with_context(|| {
-                    format!(
-                        "Could not read file for link {} ({})",
-                        self.link_text,
-                        target.display()
-                    )
-                })?;
-                let ftype = if !attrs.is_empty() { "rust," } else { "rust" };
-                if !contents.ends_with('\n') {
-                    contents.push('\n');
-                }
-                Ok(format!(
-                    "```{}{}\n{}```\n",
-                    ftype,
-                    attrs.join(","),
-                    contents
-                ))
-            }
-            LinkType::Title(title) => {
-                *chapter_title = title.to_owned();
-                Ok(String::new())
-            }
+    #[test]
+    fn test_replace_all_include_strip_comments() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(
+            temp.path().join("snippet.rs"),
+            "// Copyright (c) Example\n// Licensed under MIT\n\nfn main() {\n    \
+             // Greet\n    println!(\"// hello\"); // inline\n}\n",
+        )
+        .unwrap();
+        fs::write(
+            temp.path().join("query.txt"),
+            "-- all users\nSELECT * FROM users;\n",
+        )
+        .unwrap();
+        let mut chapter_title = "test_replace_all_include_strip_comments".to_owned();
+
+        let cases = [
+            (
+                "{{#include snippet.rs strip-comments trim-blank-lines}}",
+                "fn main() {\n    println!(\"// hello\"); // inline\n}",
+            ),
+            (
+                "{{#include query.txt lang=sql strip-comments}}",
+                "```sql\nSELECT * FROM users;\n```",
+            ),
+            // The comment syntax of `.txt` files is unknown.
+            (
+                "{{#include query.txt strip-comments}}",
+                "-- all users\nSELECT * FROM users;",
+            ),
+        ];
+        for (start, end) in cases {
+            assert_eq!(
+                replace_all(
+                    start,
+                    temp.path(),
+                    "",
+                    0,
+                    &mut chapter_title,
+                    &mut IncludeDiagnostics::default(),
+                    &ReplaceOptions::default()
+                ),
+                end
+            );
         }
     }
-}
-
-struct LinkIter<'a>(CaptureMatches<'a, 'a>);
 
-impl<'a> Iterator for LinkIter<'a> {
-    type Item = Link<'a>;
-    fn next(&mut self) -> Option<Link<'a>> {
-        for cap in &mut self.0 {
-            if let Some(inc) = Link::from_capture(cap) {
-                return Some(inc);
-            }
+    #[test]
+    fn test_replace_all_include_start_line() {
+        let temp = tempfile::tempdir().unwrap();
+        let lines: Vec<String> = (1..=12).map(|n| format!("line {n}")).collect();
+        fs::write(temp.path().join("snippet.rs"), lines.join("\n")).unwrap();
+        let mut chapter_title = "test_replace_all_include_start_line".to_owned();
+
+        let cases = [
+            (
+                "{{#include snippet.rs:10:11 lang start-line}}",
+                "```rust,start-line=10\nline 10\nline 11\n```",
+            ),
+            (
+                "{{#include_code snippet.rs:12 start-line}}",
+                "<pre><code class=\"language-rust,start-line=12\">line 12\n</code></pre>",
+            ),
+            // Without a code block or a line range there is nothing to record.
+            (
+                "{{#include snippet.rs:10:11 start-line}}",
+                "line 10\nline 11",
+            ),
+            (
+                "{{#include snippet.rs:10:11 lang}}",
+                "```rust\nline 10\nline 11\n```",
+            ),
+        ];
+        for (start, end) in cases {
+            assert_eq!(
+                replace_all(
+                    start,
+                    temp.path(),
+                    "",
+                    0,
+                    &mut chapter_title,
+                    &mut IncludeDiagnostics::default(),
+                    &ReplaceOptions::default()
+                ),
+                end
+            );
         }
-        None
     }
-}
 
-fn find_links(contents: &str) -> LinkIter<'_> {
-    // lazily compute following regex
-    // r"\\\{\{#.*\}\}|\{\{#([a-zA-Z0-9]+)\s*([^}]+)\}\}")?;
-    static RE: Lazy<Regex> = Lazy::new(|| {
-        Regex::new(
-            r"(?x)              # insignificant whitespace mode
-        \\\{\{\#.*\}\}      # match escaped link
-        |                   # or
-        \{\{\s*             # link opening parens and whitespace
-        \#([a-zA-Z0-9_]+)   # link type
-        \s+                 # separating whitespace
-        ([^}]+)             # link target path and space separated properties
-        \}\}                # link closing parens",
-        )
-        .unwrap()
-    });
+    #[test]
+    fn test_replace_all_include_path_with_spaces() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(temp.path().join("my file.rs"), "fn main() {}\n").unwrap();
+        let mut chapter_title = "test_replace_all_include_path_with_spaces".to_owned();
+
+        for start in [
+            "{{#include my file.rs}}",
+            "{{#include my%20file.rs}}",
+            "{{#include  my file.rs:1 }}",
+        ] {
+            assert_eq!(
+                replace_all(
+                    start,
+                    temp.path(),
+                    "",
+                    0,
+                    &mut chapter_title,
+                    &mut IncludeDiagnostics::default(),
+                    &ReplaceOptions::default()
+                ),
+                "fn main() {}"
+            );
+        }
 
-    LinkIter(RE.captures_iter(contents))
-}
+        let start = "{{#include my file.rs lang}}";
+        let end = "```rust\nfn main() {}\n```";
+        assert_eq!(
+            replace_all(
+                start,
+                temp.path(),
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            end
+        );
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        // Other characters can be percent-encoded as well, including ones
+        // encoded as several UTF-8 bytes.
+        fs::write(temp.path().join("#1 (café).rs"), "fn cafe() {}\n").unwrap();
+        for start in [
+            "{{#include %231%20%28caf%C3%A9%29.rs}}",
+            "{{#include %231 (café%29.rs:1}}",
+        ] {
+            assert_eq!(
+                replace_all(
+                    start,
+                    temp.path(),
+                    "",
+                    0,
+                    &mut chapter_title,
+                    &mut IncludeDiagnostics::default(),
+                    &ReplaceOptions::default()
+                ),
+                "fn cafe() {}"
+            );
+        }
+        assert_eq!(decode_include_path("100%.rs"), PathBuf::from("100%.rs"));
+        assert_eq!(decode_include_path("%+1%zz.rs"), PathBuf::from("%+1%zz.rs"));
+        assert_eq!(decode_include_path("%FF.rs"), PathBuf::from("%FF.rs"));
+    }
 
     #[test]
-    fn test_replace_all_escaped() {
-        let start = r"
-        Some text over here.
-        ```hbs
-        \{{#include file.rs}} << an escaped link!
-        ```";
-        let end = r"
-        Some text over here.
-        ```hbs
-        {{#include file.rs}} << an escaped link!
-        ```";
-        let mut chapter_title = "test_replace_all_escaped".to_owned();
-        assert_eq!(replace_all(start, "", "", 0, &mut chapter_title), end);
+    fn test_split_include_props() {
+        assert_eq!(split_include_props("file.rs"), ("file.rs", vec![]));
+        assert_eq!(
+            split_include_props(" my  file.rs:2 lang=rust trim-trailing "),
+            ("my  file.rs:2", vec!["lang=rust", "trim-trailing"])
+        );
+        assert_eq!(
+            split_include_props("my file.rs lang other"),
+            ("my file.rs lang other", vec![])
+        );
+    }
+
+    #[test]
+    fn test_wrap_in_code_block_uses_longer_fence() {
+        assert_eq!(
+            wrap_in_code_block("```rust\n```", "markdown"),
+            "````markdown\n```rust\n```\n````"
+        );
     }
 
     #[test]
@@ -456,7 +3125,18 @@ mod tests {
         # My Chapter
         ";
         let mut chapter_title = "test_set_chapter_title".to_owned();
-        assert_eq!(replace_all(start, "", "", 0, &mut chapter_title), end);
+        assert_eq!(
+            replace_all(
+                start,
+                "",
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &ReplaceOptions::default()
+            ),
+            end
+        );
         assert_eq!(chapter_title, "My Title");
     }
 
@@ -485,7 +3165,77 @@ mod tests {
     #[test]
     fn test_find_links_unknown_link_type() {
         let s = "Some random text with {{#playgroundz ar.rs}} and {{#incn}} {{baz}} {{#bar}}...";
-        assert!(find_links(s).collect::<Vec<_>>() == vec![]);
+        let res = find_links(s).collect::<Vec<_>>();
+        println!("\nOUTPUT: {:?}\n", res);
+        assert_eq!(
+            res,
+            vec![Link {
+                start_index: 22,
+                end_index: 44,
+                link_type: LinkType::Custom("playgroundz", "ar.rs"),
+                link_text: "{{#playgroundz ar.rs}}",
+            }]
+        );
+    }
+
+    #[test]
+    fn test_replace_all_custom_directives() {
+        let mut registry = DirectiveRegistry::new();
+        registry.register("shout", |args, ctx| {
+            Ok(format!("{} ({})", args.to_uppercase(), ctx.chapter_title))
+        });
+        assert!(registry.contains("shout"));
+        assert!(registry.contains("include"));
+        assert!(!registry.contains("whisper"));
+
+        let start = "{{#title Intro}}{{#shout hello world }} and {{#whisper hi}}";
+        let mut chapter_title = "test_replace_all_custom_directives".to_owned();
+        let options = ReplaceOptions {
+            directives: registry,
+            ..ReplaceOptions::default()
+        };
+        assert_eq!(
+            replace_all(
+                start,
+                "",
+                "",
+                0,
+                &mut chapter_title,
+                &mut IncludeDiagnostics::default(),
+                &options,
+            ),
+            "HELLO WORLD (Intro) and {{#whisper hi}}"
+        );
+    }
+
+    #[test]
+    fn test_unknown_directives_policy() {
+        let link = find_links("{{#whisper hi}}").next().unwrap();
+        let ctx = DirectiveCtx {
+            base: Path::new(""),
+            source: Path::new("chapter.md"),
+            chapter_title: "Chapter",
+        };
+        let keep = ReplaceOptions::default();
+        assert_eq!(
+            link.render_custom(&ctx, &keep).unwrap().unwrap(),
+            "{{#whisper hi}}"
+        );
+        let error = ReplaceOptions {
+            unknown_directives: UnknownDirectives::Error,
+            ..ReplaceOptions::default()
+        };
+        let err = link.render_custom(&ctx, &error).unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "Unknown directive `whisper`");
+
+        let link = find_links("{{#title Intro}}").next().unwrap();
+        assert!(link.render_custom(&ctx, &error).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "`include` is a built-in directive")]
+    fn test_builtin_directives_cannot_be_replaced() {
+        DirectiveRegistry::new().register("include", |_, _| Ok(String::new()));
     }
 
     #[test]
@@ -544,7 +3294,8 @@ mod tests {
                 end_index: 48,
                 link_type: LinkType::Include(
                     PathBuf::from("file.rs"),
-                    RangeOrAnchor::Range(LineRange::from(9..20))
+                    RangeOrAnchor::Range(LineRange::from(9..20)),
+                    IncludeOptions::default()
                 ),
                 link_text: "{{#include file.rs:10:20}}",
             }]
@@ -563,7 +3314,8 @@ mod tests {
                 end_index: 45,
                 link_type: LinkType::Include(
                     PathBuf::from("file.rs"),
-                    RangeOrAnchor::Range(LineRange::from(9..10))
+                    RangeOrAnchor::Range(LineRange::from(9..10)),
+                    IncludeOptions::default()
                 ),
                 link_text: "{{#include file.rs:10}}",
             }]
@@ -582,7 +3334,8 @@ mod tests {
                 end_index: 46,
                 link_type: LinkType::Include(
                     PathBuf::from("file.rs"),
-                    RangeOrAnchor::Range(LineRange::from(9..))
+                    RangeOrAnchor::Range(LineRange::from(9..)),
+                    IncludeOptions::default()
                 ),
                 link_text: "{{#include file.rs:10:}}",
             }]
@@ -601,7 +3354,8 @@ mod tests {
                 end_index: 46,
                 link_type: LinkType::Include(
                     PathBuf::from("file.rs"),
-                    RangeOrAnchor::Range(LineRange::from(..20))
+                    RangeOrAnchor::Range(LineRange::from(..20)),
+                    IncludeOptions::default()
                 ),
                 link_text: "{{#include file.rs::20}}",
             }]
@@ -620,7 +3374,8 @@ mod tests {
                 end_index: 44,
                 link_type: LinkType::Include(
                     PathBuf::from("file.rs"),
-                    RangeOrAnchor::Range(LineRange::from(..))
+                    RangeOrAnchor::Range(LineRange::from(..)),
+                    IncludeOptions::default()
                 ),
                 link_text: "{{#include file.rs::}}",
             }]
@@ -639,7 +3394,8 @@ mod tests {
                 end_index: 42,
                 link_type: LinkType::Include(
                     PathBuf::from("file.rs"),
-                    RangeOrAnchor::Range(LineRange::from(..))
+                    RangeOrAnchor::Range(LineRange::from(..)),
+                    IncludeOptions::default()
                 ),
                 link_text: "{{#include file.rs}}",
             }]
@@ -658,13 +3414,50 @@ mod tests {
                 end_index: 49,
                 link_type: LinkType::Include(
                     PathBuf::from("file.rs"),
-                    RangeOrAnchor::Anchor(String::from("anchor"))
+                    RangeOrAnchor::Anchor(String::from("anchor")),
+                    IncludeOptions::default()
                 ),
                 link_text: "{{#include file.rs:anchor}}",
             }]
         );
     }
 
+    #[test]
+    fn test_find_links_with_remote_include() {
+        let s = "{{#include https://example.com:8080/code/main.rs:2:3 lang}} and \
+                 {{#include http://example.com/main.rs}}";
+        let res = find_links(s).collect::<Vec<_>>();
+        println!("\nOUTPUT: {:?}\n", res);
+        assert_eq!(
+            res,
+            vec![
+                Link {
+                    start_index: 0,
+                    end_index: 59,
+                    link_type: LinkType::RemoteInclude(
+                        String::from("https://example.com:8080/code/main.rs"),
+                        RangeOrAnchor::Range(LineRange::from(1..3)),
+                        IncludeOptions {
+                            code_block: Some(CodeBlockLanguage::FromExtension),
+                            ..IncludeOptions::default()
+                        }
+                    ),
+                    link_text: "{{#include https://example.com:8080/code/main.rs:2:3 lang}}",
+                },
+                Link {
+                    start_index: 64,
+                    end_index: 103,
+                    link_type: LinkType::RemoteInclude(
+                        String::from("http://example.com/main.rs"),
+                        RangeOrAnchor::Range(LineRange::from(RangeFull)),
+                        IncludeOptions::default()
+                    ),
+                    link_text: "{{#include http://example.com/main.rs}}",
+                },
+            ]
+        );
+    }
+
     #[test]
     fn test_find_links_escaped_link() {
         let s = "Some random text with escaped playground \\{{#playground file.rs editable}} ...";
@@ -730,7 +3523,8 @@ mod tests {
                 end_index: 61,
                 link_type: LinkType::Include(
                     PathBuf::from("file.rs"),
-                    RangeOrAnchor::Range(LineRange::from(..))
+                    RangeOrAnchor::Range(LineRange::from(..)),
+                    IncludeOptions::default()
                 ),
                 link_text: "{{#include file.rs}}",
             }
@@ -760,180 +3554,274 @@ mod tests {
 
     #[test]
     fn parse_without_colon_includes_all() {
-        let link_type = parse_include_path("arbitrary");
+        let link_type = parse_include_path("arbitrary", &[]);
+        assert_eq!(
+            link_type,
+            LinkType::Include(
+                PathBuf::from("arbitrary"),
+                RangeOrAnchor::Range(LineRange::from(RangeFull)),
+                IncludeOptions::default()
+            )
+        );
+    }
+
+    #[test]
+    fn parse_include_properties() {
+        let link_type = parse_include_path("arbitrary:5", &["lang=rust"]);
+        assert_eq!(
+            link_type,
+            LinkType::Include(
+                PathBuf::from("arbitrary"),
+                RangeOrAnchor::Range(LineRange::from(4..5)),
+                IncludeOptions {
+                    code_block: Some(CodeBlockLanguage::Named("rust".to_owned())),
+                    trim_trailing: false,
+                    trim_blank_lines: false,
+                    strip_hidden: false,
+                    strip_comments: false,
+                    start_line: false,
+                    expand_tabs: None,
+                    escape_html: false,
+                    skip_lines: 0,
+                    skip_leading: None,
+                }
+            )
+        );
+
+        let link_type = parse_include_path("arbitrary", &["lang"]);
+        assert_eq!(
+            link_type,
+            LinkType::Include(
+                PathBuf::from("arbitrary"),
+                RangeOrAnchor::Range(LineRange::from(RangeFull)),
+                IncludeOptions {
+                    code_block: Some(CodeBlockLanguage::FromExtension),
+                    trim_trailing: false,
+                    trim_blank_lines: false,
+                    strip_hidden: false,
+                    strip_comments: false,
+                    start_line: false,
+                    expand_tabs: None,
+                    escape_html: false,
+                    skip_lines: 0,
+                    skip_leading: None,
+                }
+            )
+        );
+
+        let link_type = parse_rustdoc_include_path("arbitrary:anchor", &["trim-trailing"]);
+        assert_eq!(
+            link_type,
+            LinkType::RustdocInclude(
+                PathBuf::from("arbitrary"),
+                RangeOrAnchor::Anchor("anchor".to_owned()),
+                IncludeOptions {
+                    code_block: None,
+                    trim_trailing: true,
+                    trim_blank_lines: false,
+                    strip_hidden: false,
+                    strip_comments: false,
+                    start_line: false,
+                    expand_tabs: None,
+                    escape_html: false,
+                    skip_lines: 0,
+                    skip_leading: None,
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn parse_with_anchor_occurrence() {
+        let link_type = parse_include_path("arbitrary:some-anchor#2", &[]);
         assert_eq!(
             link_type,
             LinkType::Include(
                 PathBuf::from("arbitrary"),
-                RangeOrAnchor::Range(LineRange::from(RangeFull))
+                RangeOrAnchor::AnchorOccurrence("some-anchor".to_string(), 2),
+                IncludeOptions::default()
             )
         );
     }
 
     #[test]
     fn parse_with_nothing_after_colon_includes_all() {
-        let link_type = parse_include_path("arbitrary:");
+        let link_type = parse_include_path("arbitrary:", &[]);
         assert_eq!(
             link_type,
             LinkType::Include(
                 PathBuf::from("arbitrary"),
-                RangeOrAnchor::Range(LineRange::from(RangeFull))
+                RangeOrAnchor::Range(LineRange::from(RangeFull)),
+                IncludeOptions::default()
             )
         );
     }
 
     #[test]
     fn parse_with_two_colons_includes_all() {
-        let link_type = parse_include_path("arbitrary::");
+        let link_type = parse_include_path("arbitrary::", &[]);
         assert_eq!(
             link_type,
             LinkType::Include(
                 PathBuf::from("arbitrary"),
-                RangeOrAnchor::Range(LineRange::from(RangeFull))
+                RangeOrAnchor::Range(LineRange::from(RangeFull)),
+                IncludeOptions::default()
             )
         );
     }
 
     #[test]
     fn parse_with_garbage_after_two_colons_includes_all() {
-        let link_type = parse_include_path("arbitrary::NaN");
+        let link_type = parse_include_path("arbitrary::NaN", &[]);
         assert_eq!(
             link_type,
             LinkType::Include(
                 PathBuf::from("arbitrary"),
-                RangeOrAnchor::Range(LineRange::from(RangeFull))
+                RangeOrAnchor::Range(LineRange::from(RangeFull)),
+                IncludeOptions::default()
             )
         );
     }
 
     #[test]
     fn parse_with_one_number_after_colon_only_that_line() {
-        let link_type = parse_include_path("arbitrary:5");
+        let link_type = parse_include_path("arbitrary:5", &[]);
         assert_eq!(
             link_type,
             LinkType::Include(
                 PathBuf::from("arbitrary"),
-                RangeOrAnchor::Range(LineRange::from(4..5))
+                RangeOrAnchor::Range(LineRange::from(4..5)),
+                IncludeOptions::default()
             )
         );
     }
 
     #[test]
     fn parse_with_one_based_start_becomes_zero_based() {
-        let link_type = parse_include_path("arbitrary:1");
+        let link_type = parse_include_path("arbitrary:1", &[]);
         assert_eq!(
             link_type,
             LinkType::Include(
                 PathBuf::from("arbitrary"),
-                RangeOrAnchor::Range(LineRange::from(0..1))
+                RangeOrAnchor::Range(LineRange::from(0..1)),
+                IncludeOptions::default()
             )
         );
     }
 
     #[test]
     fn parse_with_zero_based_start_stays_zero_based_but_is_probably_an_error() {
-        let link_type = parse_include_path("arbitrary:0");
+        let link_type = parse_include_path("arbitrary:0", &[]);
         assert_eq!(
             link_type,
             LinkType::Include(
                 PathBuf::from("arbitrary"),
-                RangeOrAnchor::Range(LineRange::from(0..1))
+                RangeOrAnchor::Range(LineRange::from(0..1)),
+                IncludeOptions::default()
             )
         );
     }
 
     #[test]
     fn parse_start_only_range() {
-        let link_type = parse_include_path("arbitrary:5:");
+        let link_type = parse_include_path("arbitrary:5:", &[]);
         assert_eq!(
             link_type,
             LinkType::Include(
                 PathBuf::from("arbitrary"),
-                RangeOrAnchor::Range(LineRange::from(4..))
+                RangeOrAnchor::Range(LineRange::from(4..)),
+                IncludeOptions::default()
             )
         );
     }
 
     #[test]
     fn parse_start_with_garbage_interpreted_as_start_only_range() {
-        let link_type = parse_include_path("arbitrary:5:NaN");
+        let link_type = parse_include_path("arbitrary:5:NaN", &[]);
         assert_eq!(
             link_type,
             LinkType::Include(
                 PathBuf::from("arbitrary"),
-                RangeOrAnchor::Range(LineRange::from(4..))
+                RangeOrAnchor::Range(LineRange::from(4..)),
+                IncludeOptions::default()
             )
         );
     }
 
     #[test]
     fn parse_end_only_range() {
-        let link_type = parse_include_path("arbitrary::5");
+        let link_type = parse_include_path("arbitrary::5", &[]);
         assert_eq!(
             link_type,
             LinkType::Include(
                 PathBuf::from("arbitrary"),
-                RangeOrAnchor::Range(LineRange::from(..5))
+                RangeOrAnchor::Range(LineRange::from(..5)),
+                IncludeOptions::default()
             )
         );
     }
 
     #[test]
     fn parse_start_and_end_range() {
-        let link_type = parse_include_path("arbitrary:5:10");
+        let link_type = parse_include_path("arbitrary:5:10", &[]);
         assert_eq!(
             link_type,
             LinkType::Include(
                 PathBuf::from("arbitrary"),
-                RangeOrAnchor::Range(LineRange::from(4..10))
+                RangeOrAnchor::Range(LineRange::from(4..10)),
+                IncludeOptions::default()
             )
         );
     }
 
     #[test]
     fn parse_with_negative_interpreted_as_anchor() {
-        let link_type = parse_include_path("arbitrary:-5");
+        let link_type = parse_include_path("arbitrary:-5", &[]);
         assert_eq!(
             link_type,
             LinkType::Include(
                 PathBuf::from("arbitrary"),
-                RangeOrAnchor::Anchor("-5".to_string())
+                RangeOrAnchor::Anchor("-5".to_string()),
+                IncludeOptions::default()
             )
         );
     }
 
     #[test]
     fn parse_with_floating_point_interpreted_as_anchor() {
-        let link_type = parse_include_path("arbitrary:-5.7");
+        let link_type = parse_include_path("arbitrary:-5.7", &[]);
         assert_eq!(
             link_type,
             LinkType::Include(
                 PathBuf::from("arbitrary"),
-                RangeOrAnchor::Anchor("-5.7".to_string())
+                RangeOrAnchor::Anchor("-5.7".to_string()),
+                IncludeOptions::default()
             )
         );
     }
 
     #[test]
     fn parse_with_anchor_followed_by_colon() {
-        let link_type = parse_include_path("arbitrary:some-anchor:this-gets-ignored");
+        let link_type = parse_include_path("arbitrary:some-anchor:this-gets-ignored", &[]);
         assert_eq!(
             link_type,
             LinkType::Include(
                 PathBuf::from("arbitrary"),
-                RangeOrAnchor::Anchor("some-anchor".to_string())
+                RangeOrAnchor::Anchor("some-anchor".to_string()),
+                IncludeOptions::default()
             )
         );
     }
 
     #[test]
     fn parse_with_more_than_three_colons_ignores_everything_after_third_colon() {
-        let link_type = parse_include_path("arbitrary:5:10:17:anything:");
+        let link_type = parse_include_path("arbitrary:5:10:17:anything:", &[]);
         assert_eq!(
             link_type,
             LinkType::Include(
                 PathBuf::from("arbitrary"),
-                RangeOrAnchor::Range(LineRange::from(4..10))
+                RangeOrAnchor::Range(LineRange::from(4..10)),
+                IncludeOptions::default()
             )
         );
     }
@@ -2,7 +2,10 @@
 
 pub use self::cmd::CmdPreprocessor;
 pub use self::index::IndexPreprocessor;
-pub use self::links::LinkPreprocessor;
+pub use self::links::{
+    expand_includes, validate_includes, DirectiveCtx, DirectiveHandler, DirectiveRegistry,
+    IncludeError, IncludeErrorKind, LinkPreprocessor,
+};
 
 mod cmd;
 mod index;
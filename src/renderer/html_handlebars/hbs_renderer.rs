@@ -1,5 +1,8 @@
 use crate::book::{Book, BookItem};
-use crate::config::{BookConfig, Code, Config, HtmlConfig, Playground, RustEdition};
+use crate::config::{
+    BookConfig, Code, Config, HeaderLinkMode, HtmlConfig, Markdown, MissingPageLinks,
+    PageBreakStyle, Playground, RustEdition,
+};
 use crate::errors::*;
 use crate::renderer::html_handlebars::helpers;
 use crate::renderer::{RenderContext, Renderer};
@@ -8,11 +11,13 @@ use crate::utils;
 
 use std::borrow::Cow;
 use std::collections::BTreeMap;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, File};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 
 use crate::utils::fs::get_404_output_file;
+use crate::utils::Endnotes;
 use handlebars::Handlebars;
 use log::{debug, trace, warn};
 use once_cell::sync::Lazy;
@@ -32,6 +37,7 @@ impl HtmlHandlebars {
         item: &BookItem,
         mut ctx: RenderItemContext<'_>,
         print_content: &mut String,
+        endnotes: &mut Endnotes,
     ) -> Result<()> {
         // FIXME: This should be made DRY-er and rely less on mutable state
 
@@ -41,53 +47,123 @@ impl HtmlHandlebars {
         };
 
         if let Some(ref edit_url_template) = ctx.html_config.edit_url_template {
-            let full_path = ctx.book_config.src.to_str().unwrap_or_default().to_owned()
-                + "/"
-                + ch.source_path
-                    .clone()
-                    .unwrap_or_default()
-                    .to_str()
-                    .unwrap_or_default();
-
-            let edit_url = edit_url_template.replace("{path}", &full_path);
+            let edit_url = edit_url(
+                edit_url_template,
+                &ctx.book_config.src,
+                ch.source_path.as_deref().unwrap_or(Path::new("")),
+            );
             ctx.data
                 .insert("git_repository_edit_url".to_owned(), json!(edit_url));
         }
 
-        let content = utils::render_markdown(&ch.content, ctx.html_config.smart_punctuation());
+        let (front_matter, markdown) = if ctx.html_config.markdown.front_matter {
+            utils::split_front_matter(&ch.content)
+        } else {
+            (None, ch.content.as_str())
+        };
+        let markdown = if ctx.html_config.markdown.wiki_links {
+            let (linked, unresolved) = utils::link_wiki_references(markdown, path, ctx.wiki_links);
+            for (title, e) in unresolved {
+                // `keyboard-keys` renders the ones without whitespace as keys.
+                if !ctx.html_config.markdown.keyboard_keys || title.contains(char::is_whitespace) {
+                    warn!(
+                        "Wiki link `[[{}]]` in {} is left as text: {}",
+                        title,
+                        path.display(),
+                        e
+                    );
+                }
+            }
+            linked
+        } else {
+            Cow::Borrowed(markdown)
+        };
+        let markdown = markdown.as_ref();
 
-        let printed_item = utils::render_markdown_with_path_and_redirects(
-            &ch.content,
+        let mut content = utils::render_markdown_with_options(
+            markdown,
+            ctx.html_config.smart_punctuation(),
+            &ctx.html_config.markdown,
+        );
+
+        if ctx.html_config.markdown.warn_missing_alt_text {
+            for src in utils::images_without_alt_text(markdown) {
+                warn!("Image `{}` in {} has no alt text", src, path.display());
+            }
+        }
+        if ctx.html_config.markdown.warn_missing_images {
+            let chapter_dir = ctx.src_dir.join(path.parent().unwrap_or(Path::new("")));
+            for src in utils::missing_images(markdown, &chapter_dir) {
+                warn!("Image `{}` in {} does not exist", src, path.display());
+            }
+        }
+
+        let mut printed_item = utils::render_markdown_with_path_and_redirects(
+            markdown,
             ctx.html_config.smart_punctuation(),
             Some(path),
             &ctx.html_config.redirect,
+            &ctx.html_config.markdown,
+            ctx.html_config.print.endnotes.then_some(&mut *endnotes),
+        );
+        content = fix_missing_page_links(
+            &content,
+            path,
+            ctx.known_pages,
+            ctx.html_config.markdown.missing_page_links,
+            &get_404_output_file(&ctx.html_config.input_404),
         );
+        if ctx.html_config.markdown.image_dimensions {
+            let chapter_dir = ctx.src_dir.join(path.parent().unwrap_or(Path::new("")));
+            let extensions = &ctx.html_config.markdown.image_extensions;
+            content = add_image_dimensions(&content, &chapter_dir, extensions);
+            // Images on the print page are relative to the source directory.
+            printed_item = add_image_dimensions(&printed_item, &ctx.src_dir, extensions);
+        }
+        if ctx.html_config.markdown.dark_image_variants {
+            let chapter_dir = ctx.src_dir.join(path.parent().unwrap_or(Path::new("")));
+            content = add_dark_image_variants(&content, &chapter_dir);
+            printed_item = add_dark_image_variants(&printed_item, &ctx.src_dir);
+        }
         if !ctx.is_index && ctx.html_config.print.page_break {
             // Add page break between chapters
-            // See https://developer.mozilla.org/en-US/docs/Web/CSS/break-before and https://developer.mozilla.org/en-US/docs/Web/CSS/page-break-before
-            // Add both two CSS properties because of the compatibility issue
-            print_content
-                .push_str(r#"<div style="break-before: page; page-break-before: always;"></div>"#);
+            match ctx.html_config.print.page_break_style {
+                // See https://developer.mozilla.org/en-US/docs/Web/CSS/break-before and https://developer.mozilla.org/en-US/docs/Web/CSS/page-break-before
+                // Add both two CSS properties because of the compatibility issue
+                PageBreakStyle::Inline => print_content.push_str(
+                    r#"<div style="break-before: page; page-break-before: always;"></div>"#,
+                ),
+                // Leave the styling to print.css
+                PageBreakStyle::Class => {
+                    print_content.push_str(r#"<div class="page-break"></div>"#)
+                }
+            }
         }
         let print_page_id = {
             let mut base = path.display().to_string();
             if base.ends_with(".md") {
                 base.truncate(base.len() - 3);
             }
-            &base
-                .replace("/", "-")
-                .replace("\\", "-")
-                .to_ascii_lowercase()
+            &utils::normalize_print_page_id(base.replace("\\", "/"))
         };
+        if ctx.html_config.print.endnotes {
+            endnotes.finish_page(print_page_id);
+        }
 
         // We have to build header links in advance so that we can know the ranges
         // for the headers in one page.
         // Insert a dummy div to make sure that we can locate the specific page.
         print_content.push_str(&(format!(r#"<div id="{print_page_id}"></div>"#)));
-        print_content.push_str(&build_header_links(
+        let printed_item = build_header_links(
             &build_print_element_id(&printed_item, &print_page_id),
             Some(print_page_id),
-        ));
+            &ctx.html_config.markdown,
+        );
+        if ctx.html_config.markdown.heading_sections {
+            print_content.push_str(&wrap_heading_sections(&printed_item));
+        } else {
+            print_content.push_str(&printed_item);
+        }
 
         // Update the context with data for this file
         let ctx_path = path
@@ -118,6 +194,20 @@ impl HtmlHandlebars {
         ctx.data.insert("content".to_owned(), json!(content));
         ctx.data.insert("chapter_title".to_owned(), json!(ch.name));
         ctx.data.insert("title".to_owned(), json!(title));
+        if let Some(front_matter) = front_matter {
+            if let Some(class) = page_class(&front_matter) {
+                ctx.data.insert("page_class".to_owned(), json!(class));
+            }
+            ctx.data
+                .insert("front_matter".to_owned(), json!(front_matter));
+        }
+        if ctx.html_config.page_descriptions {
+            // Search engines show about 160 characters of a description.
+            if let Some(description) = utils::page_description(markdown, 160) {
+                ctx.data
+                    .insert("description".to_owned(), json!(description));
+            }
+        }
         ctx.data.insert(
             "path_to_root".to_owned(),
             json!(utils::fs::path_to_root(path)),
@@ -135,6 +225,7 @@ impl HtmlHandlebars {
             rendered,
             &ctx.html_config.playground,
             &ctx.html_config.code,
+            &ctx.html_config.markdown,
             ctx.edition,
         );
 
@@ -151,6 +242,7 @@ impl HtmlHandlebars {
                 rendered_index,
                 &ctx.html_config.playground,
                 &ctx.html_config.code,
+                &ctx.html_config.markdown,
                 ctx.edition,
             );
             debug!("Creating index.html from {}", ctx_path);
@@ -186,8 +278,11 @@ impl HtmlHandlebars {
                     .to_string()
             }
         };
-        let html_content_404 =
-            utils::render_markdown(&content_404, html_config.smart_punctuation());
+        let html_content_404 = utils::render_markdown_with_options(
+            &content_404,
+            html_config.smart_punctuation(),
+            &html_config.markdown,
+        );
 
         let mut data_404 = data.clone();
         let base_url = if let Some(site_url) = &html_config.site_url {
@@ -217,6 +312,7 @@ impl HtmlHandlebars {
             rendered,
             &html_config.playground,
             &html_config.code,
+            &html_config.markdown,
             ctx.config.rust.edition,
         );
         let output_file = get_404_output_file(&html_config.input_404);
@@ -231,10 +327,20 @@ impl HtmlHandlebars {
         rendered: String,
         playground_config: &Playground,
         code_config: &Code,
+        markdown_config: &Markdown,
         edition: Option<RustEdition>,
     ) -> String {
-        let rendered = build_header_links(&rendered, None);
-        let rendered = self.post_process_common(rendered, &playground_config, code_config, edition);
+        let mut rendered = build_header_links(&rendered, None, markdown_config);
+        if markdown_config.heading_sections {
+            rendered = wrap_heading_sections(&rendered);
+        }
+        let rendered = self.post_process_common(
+            rendered,
+            &playground_config,
+            code_config,
+            markdown_config,
+            edition,
+        );
 
         rendered
     }
@@ -248,11 +354,15 @@ impl HtmlHandlebars {
         rendered: String,
         playground_config: &Playground,
         code_config: &Code,
+        markdown_config: &Markdown,
         edition: Option<RustEdition>,
     ) -> String {
+        let prefix = &markdown_config.code_class_prefix;
         let rendered = fix_code_blocks(&rendered);
-        let rendered = add_playground_pre(&rendered, playground_config, edition);
-        let rendered = hide_lines(&rendered, code_config);
+        let rendered = add_playground_pre(&rendered, playground_config, prefix, edition);
+        let rendered = hide_lines(&rendered, code_config, prefix);
+        let rendered = add_start_line_attributes(&rendered);
+        let rendered = strip_consumed_attributes(&rendered, code_config);
 
         rendered
     }
@@ -570,10 +680,17 @@ impl Renderer for HtmlHandlebars {
 
         // Print version
         let mut print_content = String::new();
+        let mut endnotes = Endnotes::default();
 
         fs::create_dir_all(destination)
             .with_context(|| "Unexpected error when constructing destination path")?;
 
+        let known_pages = known_pages(book, &html_config);
+        let wiki_links = if html_config.markdown.wiki_links {
+            wiki_links(book, &html_config)
+        } else {
+            utils::WikiLinks::new()
+        };
         let mut is_index = true;
         for item in book.iter() {
             let ctx = RenderItemContext {
@@ -581,12 +698,15 @@ impl Renderer for HtmlHandlebars {
                 destination: destination.to_path_buf(),
                 data: data.clone(),
                 is_index,
+                src_dir: ctx.root.join(&book_config.src),
                 book_config: book_config.clone(),
                 html_config: html_config.clone(),
                 edition: ctx.config.rust.edition,
                 chapter_titles: &ctx.chapter_titles,
+                known_pages: &known_pages,
+                wiki_links: &wiki_links,
             };
-            self.render_item(item, ctx, &mut print_content)?;
+            self.render_item(item, ctx, &mut print_content, &mut endnotes)?;
             // Only the first non-draft chapter item should be treated as the "index"
             is_index &= !matches!(item, BookItem::Chapter(ch) if !ch.is_draft_chapter());
         }
@@ -597,6 +717,7 @@ impl Renderer for HtmlHandlebars {
         }
 
         // Print version
+        print_content.push_str(&endnotes.to_html());
         self.configure_print_version(&mut data, &print_content);
         if let Some(ref title) = ctx.config.book.title {
             data.insert("title".to_owned(), json!(title));
@@ -611,6 +732,7 @@ impl Renderer for HtmlHandlebars {
                 rendered,
                 &html_config.playground,
                 &html_config.code,
+                &html_config.markdown,
                 ctx.config.rust.edition,
             );
 
@@ -629,7 +751,7 @@ impl Renderer for HtmlHandlebars {
         {
             let search = html_config.search.unwrap_or_default();
             if search.enable {
-                super::search::create_files(&search, destination, book)?;
+                super::search::create_files(&search, &html_config.markdown, destination, book)?;
             }
         }
 
@@ -818,24 +940,45 @@ fn make_data(
     Ok(data)
 }
 
+/// Builds the URL for editing a chapter from the `edit-url-template`, whose
+/// `{path}` is replaced with the path of the chapter's source file relative to
+/// the book root, like `src/guide/intro.md`.
+fn edit_url(template: &str, src: &Path, source_path: &Path) -> String {
+    let full_path = format!(
+        "{}/{}",
+        src.to_str().unwrap_or_default(),
+        source_path.to_str().unwrap_or_default()
+    );
+    // Repositories use forward slashes, also when building on Windows.
+    template.replace("{path}", &full_path.replace('\\', "/"))
+}
+
 /// Go through the rendered print page HTML,
 /// add path id prefix to all the elements id as well as footnote links.
 fn build_print_element_id(html: &str, print_page_id: &str) -> String {
     static ALL_ID: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(<[^>]*?id=")([^"]+?)""#).unwrap());
     static FOOTNOTE_ID: Lazy<Regex> = Lazy::new(|| {
         Regex::new(
-            r##"(<sup [^>]*?class="footnote-reference"[^>]*?>[^<]*?<a [^>]*?href="#)([^"]+?)""##,
+            r##"(<sup [^>]*?class="footnote-reference"[^>]*?>[^<]*?<a [^>]*?href="#|<a class="footnote-reference" href="#)([^"]+?)""##,
         )
         .unwrap()
     });
 
     let temp_html = ALL_ID.replace_all(html, |caps: &Captures<'_>| {
-        format!("{}{}-{}\"", &caps[1], print_page_id, &caps[2])
+        format!(
+            "{}{}\"",
+            &caps[1],
+            utils::print_element_id(print_page_id, &caps[2])
+        )
     });
 
     FOOTNOTE_ID
         .replace_all(&temp_html, |caps: &Captures<'_>| {
-            format!("{}{}-{}\"", &caps[1], print_page_id, &caps[2])
+            format!(
+                "{}{}\"",
+                &caps[1],
+                utils::print_element_id(print_page_id, &caps[2])
+            )
         })
         .into_owned()
 }
@@ -845,74 +988,461 @@ fn build_print_element_id(html: &str, print_page_id: &str) -> String {
 ///
 /// `print_page_id` should be set to the print page ID prefix when adjusting the
 /// print page.
-fn build_header_links(html: &str, print_page_id: Option<&str>) -> String {
+///
+/// Headers deeper than `markdown_config.max_id_heading_level` are left as-is.
+fn build_header_links(
+    html: &str,
+    print_page_id: Option<&str>,
+    markdown_config: &Markdown,
+) -> String {
+    let mut result = String::with_capacity(html.len());
+    let mut last = 0;
+    for header in header_ids(html, print_page_id, markdown_config) {
+        result.push_str(&html[last..header.range.start]);
+        result.push_str(&insert_link_into_header(&header, markdown_config));
+        last = header.range.end;
+    }
+    result.push_str(&html[last..]);
+    result
+}
+
+/// A header of rendered HTML that [`build_header_links`] gives an ID.
+struct HeaderId<'h> {
+    /// Where the whole header is in the HTML.
+    range: Range<usize>,
+    level: usize,
+    /// The HTML content of the header.
+    content: &'h str,
+    classes: Option<&'h str>,
+    id: String,
+    /// The ID that GitHub would give the header, if it is added as well.
+    alias: Option<String>,
+}
+
+/// Finds the headers of the rendered HTML that get an ID, together with the
+/// ID, making sure each ID is unique by appending an auto-incremented number
+/// (if necessary).
+///
+/// For `print.html`, the IDs get the `print_page_id` prefix. A generated ID
+/// starts with `sec-` and the section number of the header with
+/// `section-number-ids`.
+fn header_ids<'h>(
+    html: &'h str,
+    print_page_id: Option<&str>,
+    markdown_config: &Markdown,
+) -> Vec<HeaderId<'h>> {
     static BUILD_HEADER_LINKS: Lazy<Regex> = Lazy::new(|| {
         Regex::new(r#"<h(\d)(?: id="([^"]+)")?(?: class="([^"]+)")?>(.*?)</h\d>"#).unwrap()
     });
     static IGNORE_CLASS: &[&str] = &["menu-title"];
 
-    let mut id_counter = HashMap::new();
+    let id_style = markdown_config.into();
+    let mut id_counter = utils::id_counter_with_reserved(&markdown_config.reserved_ids);
+    let mut github_slug_counter: HashMap<String, usize> = HashMap::new();
+    let mut section_counters = [0; 6];
+    let mut headers = Vec::new();
+
+    for caps in BUILD_HEADER_LINKS.captures_iter(html) {
+        let level: usize = caps[1]
+            .parse()
+            .expect("Regex should ensure we only ever get numbers here");
+
+        // Ignore .menu-title because now it's getting detected by the regex.
+        let classes = caps.get(3).map(|classes| classes.as_str());
+        if classes.is_some_and(|classes| {
+            classes
+                .split(' ')
+                .any(|class| IGNORE_CLASS.contains(&class))
+        }) {
+            continue;
+        }
 
-    BUILD_HEADER_LINKS
-        .replace_all(html, |caps: &Captures<'_>| {
-            let level = caps[1]
-                .parse()
-                .expect("Regex should ensure we only ever get numbers here");
-
-            // Ignore .menu-title because now it's getting detected by the regex.
-            if let Some(classes) = caps.get(3) {
-                for class in classes.as_str().split(" ") {
-                    if IGNORE_CLASS.contains(&class) {
-                        return caps[0].to_string();
-                    }
+        let section_number = markdown_config
+            .section_number_ids
+            .then(|| utils::next_section_number(&mut section_counters, level));
+
+        if level > usize::from(markdown_config.max_id_heading_level) {
+            continue;
+        }
+
+        let content = caps.get(4).unwrap().as_str();
+        let id_content = strip_footnote_references(content);
+
+        // The IDs on the print page are prefixed, so GitHub's never apply.
+        let alias = (markdown_config.github_anchor_aliases && print_page_id.is_none()).then(|| {
+            let slug = utils::github_slug(&id_content);
+            let count = github_slug_counter.entry(slug.clone()).or_insert(0);
+            let alias = match *count {
+                0 => slug,
+                count => format!("{slug}-{count}"),
+            };
+            *count += 1;
+            alias
+        });
+
+        let id_content = match section_number {
+            Some(number) => Cow::Owned(format!("sec {} {}", number, id_content)),
+            None => id_content,
+        };
+        let id = match caps.get(2) {
+            Some(id) => id.as_str().to_string(),
+            None => {
+                let id = utils::unique_id_from_content_with_style(
+                    &id_content,
+                    &mut id_counter,
+                    id_style,
+                );
+                match print_page_id {
+                    Some(print_page_id) => utils::print_element_id(print_page_id, &id),
+                    None => id,
                 }
             }
+        };
 
-            insert_link_into_header(
-                level,
-                &caps[4],
-                caps.get(2).map(|x| x.as_str().to_string()),
-                caps.get(3).map(|x| x.as_str().to_string()),
-                &mut id_counter,
-                print_page_id,
-            )
+        headers.push(HeaderId {
+            range: caps.get(0).unwrap().range(),
+            level,
+            content,
+            classes,
+            id,
+            alias,
+        });
+    }
+    headers
+}
+
+/// Wraps every level two header and the HTML after it up to the next one in
+/// a `<section>` labelled by the header's ID, which `build_header_links` must
+/// have added already.
+///
+/// Headers nested in another element, like a `<blockquote>`, are left alone
+/// since a section can't start there.
+fn wrap_heading_sections(html: &str) -> String {
+    static TAG: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(
+            r#"<(/?)(?:aside|blockquote|details|div|figure|li|section|table)\b[^>]*>|<h2 id="([^"]+)"[^>]*>"#,
+        )
+        .unwrap()
+    });
+
+    let mut result = String::with_capacity(html.len());
+    let mut depth = 0usize;
+    let mut open = false;
+    let mut last = 0;
+    for caps in TAG.captures_iter(html) {
+        let Some(id) = caps.get(2) else {
+            if caps[1].is_empty() {
+                depth += 1;
+            } else {
+                depth = depth.saturating_sub(1);
+            }
+            continue;
+        };
+        if depth > 0 {
+            continue;
+        }
+        let start = caps.get(0).unwrap().start();
+        result.push_str(&html[last..start]);
+        if open {
+            result.push_str("</section>\n");
+        }
+        result.push_str(&format!(r#"<section aria-labelledby="{}">"#, id.as_str()));
+        result.push('\n');
+        open = true;
+        last = start;
+    }
+    result.push_str(&html[last..]);
+    if open {
+        if !result.ends_with('\n') {
+            result.push('\n');
+        }
+        result.push_str("</section>\n");
+    }
+    result
+}
+
+/// The paths of all pages of the rendered book, relative to its root.
+fn known_pages(book: &Book, html_config: &HtmlConfig) -> HashSet<String> {
+    let mut pages: HashSet<String> = book
+        .iter()
+        .filter_map(|item| match item {
+            BookItem::Chapter(ch) => ch.path.as_ref(),
+            _ => None,
         })
-        .into_owned()
+        .map(|path| utils::normalize_path(path.with_extension("html")))
+        .collect();
+    pages.extend(
+        html_config
+            .redirect
+            .keys()
+            .map(|page| page.trim_start_matches('/').to_string()),
+    );
+    pages.extend([
+        "index.html".to_string(),
+        "print.html".to_string(),
+        "toc.html".to_string(),
+        get_404_output_file(&html_config.input_404),
+    ]);
+    pages
 }
 
-/// Insert a sinle link into a header, making sure each link gets its own
-/// unique ID by appending an auto-incremented number (if necessary).
+/// The chapters of the book and their headings, with the IDs that
+/// `build_header_links` gives them, for wiki-style links to point to.
 ///
-/// For `print.html`, we will add a path id prefix.
-fn insert_link_into_header(
-    level: usize,
-    content: &str,
-    id: Option<String>,
-    classes: Option<String>,
-    id_counter: &mut HashMap<String, usize>,
-    print_page_id: Option<&str>,
-) -> String {
-    let id = if let Some(print_page_id) = print_page_id {
-        let content_id = {
-            #[allow(deprecated)]
-            utils::id_from_content(content)
+/// The chapters are rendered to find their headings, so that the IDs are
+/// derived from the rendered headers just like `build_header_links` does.
+fn wiki_links(book: &Book, html_config: &HtmlConfig) -> utils::WikiLinks {
+    let markdown_config = &html_config.markdown;
+    let mut links = utils::WikiLinks::new();
+    for item in book.iter() {
+        let BookItem::Chapter(ch) = item else {
+            continue;
         };
-        let with_prefix = format!("{} {}", print_page_id, content_id);
-        id.unwrap_or_else(|| utils::unique_id_from_content(&with_prefix, id_counter))
-    } else {
-        id.unwrap_or_else(|| utils::unique_id_from_content(content, id_counter))
-    };
-    let classes = classes
+        let Some(path) = &ch.path else {
+            continue;
+        };
+        links.add_page(&ch.name, path);
+
+        let content = if markdown_config.front_matter {
+            utils::split_front_matter(&ch.content).1
+        } else {
+            &ch.content
+        };
+        let html = utils::render_markdown_with_options(
+            content,
+            html_config.smart_punctuation(),
+            markdown_config,
+        );
+        for header in header_ids(&html, None, markdown_config) {
+            links.add_heading(&header_text(header.content), path, &header.id);
+        }
+    }
+    links
+}
+
+/// The text of the HTML content of a header, without its footnote
+/// references.
+fn header_text(content: &str) -> String {
+    static TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+    TAG.replace_all(&strip_footnote_references(content), "")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#92;", "\\")
+        .replace("&amp;", "&")
+}
+
+/// Handles links from the chapter at `path` to pages that aren't part of the
+/// book according to `mode`.
+///
+/// Links that leave the book, like `../../other-book/index.html`, are never
+/// touched.
+fn fix_missing_page_links(
+    html: &str,
+    path: &Path,
+    known_pages: &HashSet<String>,
+    mode: MissingPageLinks,
+    not_found_page: &str,
+) -> String {
+    static A_LINK: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r##"(<a [^>]*?href=")([^"#?]+\.html)([#?][^"]*)?""##).unwrap());
+
+    if mode == MissingPageLinks::Ignore {
+        return html.to_string();
+    }
+    let chapter_dir = path.parent().unwrap_or(Path::new(""));
+    A_LINK
+        .replace_all(html, |caps: &Captures<'_>| {
+            let href = &caps[2];
+            if utils::is_external_link(href) || href.starts_with('/') {
+                return caps[0].to_string();
+            }
+            let target = utils::normalize_path(chapter_dir.join(href));
+            if target.starts_with("../") || known_pages.contains(&target) {
+                return caps[0].to_string();
+            }
+            match mode {
+                MissingPageLinks::Redirect => format!(
+                    r#"{}{}{}""#,
+                    &caps[1],
+                    utils::fs::path_to_root(path),
+                    not_found_page
+                ),
+                _ => {
+                    warn!(
+                        "{} links to `{}`, which is not a page of the book",
+                        path.display(),
+                        href
+                    );
+                    caps[0].to_string()
+                }
+            }
+        })
+        .into_owned()
+}
+
+/// Adds `width` and `height` attributes to `<img>` elements that point to a
+/// local image under `base` whose dimensions can be read.
+///
+/// Only images whose file extension is one of `extensions` are read. Remote
+/// images, unreadable images, and images that already have a `width` or
+/// `height` are left untouched.
+fn add_image_dimensions(html: &str, base: &Path, extensions: &[String]) -> String {
+    static IMG: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(<img\s[^>]*?)(\s*/?>)"#).unwrap());
+    static SRC: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\ssrc="([^"]*)""#).unwrap());
+    static SIZE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\s(?:width|height)="#).unwrap());
+
+    IMG.replace_all(html, |caps: &Captures<'_>| {
+        let attrs = &caps[1];
+        if SIZE.is_match(attrs) {
+            return caps[0].to_string();
+        }
+        let dimensions = SRC
+            .captures(attrs)
+            .map(|src| src[1].to_string())
+            .filter(|src| !utils::is_external_link(src))
+            .and_then(|src| {
+                let src = src.split(['?', '#']).next().unwrap_or_default();
+                let path = base.join(src.replace("%20", " "));
+                let extension = path.extension()?.to_str()?;
+                if !extensions
+                    .iter()
+                    .any(|known| known.eq_ignore_ascii_case(extension))
+                {
+                    return None;
+                }
+                utils::image::dimensions(&path)
+            });
+        match dimensions {
+            Some((width, height)) => {
+                format!(r#"{attrs} width="{width}" height="{height}"{}"#, &caps[2])
+            }
+            None => caps[0].to_string(),
+        }
+    })
+    .into_owned()
+}
+
+/// Wraps each `<img>` pointing to a local image under `base` that has a dark
+/// variant next to it, like `diagram.dark.png` for `diagram.png`, in a
+/// `<picture>` with a source for the dark variant that applies when the
+/// reader prefers a dark color scheme.
+///
+/// Remote images and images without a dark variant on disk are left
+/// untouched.
+fn add_dark_image_variants(html: &str, base: &Path) -> String {
+    static IMG: Lazy<Regex> = Lazy::new(|| Regex::new(r#"<img\s[^>]*?/?>"#).unwrap());
+    static SRC: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\ssrc="([^"]*)""#).unwrap());
+
+    IMG.replace_all(html, |caps: &Captures<'_>| {
+        let img = &caps[0];
+        let dark_src = SRC
+            .captures(img)
+            .map(|src| src[1].to_string())
+            .filter(|src| !utils::is_external_link(src))
+            .and_then(|src| {
+                let end = src.find(['?', '#']).unwrap_or(src.len());
+                let (file, suffix) = src.split_at(end);
+                let (stem, extension) = file.rsplit_once('.')?;
+                if stem.is_empty() || stem.ends_with('/') || stem.ends_with(".dark") {
+                    return None;
+                }
+                let dark_file = format!("{stem}.dark.{extension}");
+                base.join(dark_file.replace("%20", " "))
+                    .is_file()
+                    .then(|| format!("{dark_file}{suffix}"))
+            });
+        match dark_src {
+            Some(dark_src) => format!(
+                r#"<picture><source srcset="{dark_src}" media="(prefers-color-scheme: dark)">{img}</picture>"#
+            ),
+            None => img.to_string(),
+        }
+    })
+    .into_owned()
+}
+
+/// Insert a sinle link into a header, with the ID that [`header_ids`] gave it.
+///
+/// An empty `<span>` with the `alias` as its ID is added to the start of the
+/// header, unless the alias is the header's ID.
+fn insert_link_into_header(header: &HeaderId<'_>, markdown_config: &Markdown) -> String {
+    let (level, content, id) = (header.level, header.content, &header.id);
+    let classes = header
+        .classes
         .map(|s| format!(" class=\"{s}\""))
         .unwrap_or_default();
+    let alias = header
+        .alias
+        .as_deref()
+        .filter(|&alias| alias != id)
+        .map(|alias| format!(r#"<span id="{alias}"></span>"#))
+        .unwrap_or_default();
+    let href = if markdown_config.percent_encode_header_links {
+        utils::percent_encode_id(id)
+    } else {
+        Cow::Borrowed(id.as_str())
+    };
+
+    match markdown_config.header_links {
+        HeaderLinkMode::WholeHeading => format!(
+            r##"<h{level} id="{id}"{classes}>{alias}<a class="header" href="#{href}">{text}</a></h{level}>"##,
+            level = level,
+            id = id,
+            href = href,
+            text = content,
+            classes = classes,
+            alias = alias
+        ),
+        HeaderLinkMode::IconOnly => format!(
+            r##"<h{level} id="{id}"{classes}>{alias}{text}<a class="header-anchor" href="#{href}" aria-label="Link to this heading">#</a></h{level}>"##,
+            level = level,
+            id = id,
+            href = href,
+            text = content,
+            classes = classes,
+            alias = alias
+        ),
+        HeaderLinkMode::None => format!(
+            r##"<h{level} id="{id}"{classes}>{alias}{text}</h{level}>"##,
+            level = level,
+            id = id,
+            text = content,
+            classes = classes,
+            alias = alias
+        ),
+    }
+}
 
-    format!(
-        r##"<h{level} id="{id}"{classes}><a class="header" href="#{id}">{text}</a></h{level}>"##,
-        level = level,
-        id = id,
-        text = content,
-        classes = classes
-    )
+/// The classes given by the `class` value of the front matter of a page,
+/// which is either a string of space separated classes or a list of them.
+fn page_class(front_matter: &utils::FrontMatter) -> Option<String> {
+    let classes: Vec<&str> = match front_matter.get("class")? {
+        toml::Value::String(classes) => classes.split_whitespace().collect(),
+        toml::Value::Array(classes) => classes
+            .iter()
+            .filter_map(toml::Value::as_str)
+            .flat_map(str::split_whitespace)
+            .collect(),
+        _ => return None,
+    };
+    (!classes.is_empty()).then(|| classes.join(" "))
+}
+
+/// Removes the footnote references (and their inline sidenotes) from the
+/// rendered heading content, so that only the heading's own text is used to
+/// derive its ID.
+fn strip_footnote_references(content: &str) -> Cow<'_, str> {
+    static FOOTNOTE_REFERENCE: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(
+            r#"(?:<sup class="footnote-reference">.*?</sup>|<a class="footnote-reference" href="[^"]*">[^<]*</a>)(?:<span class="sidenote"[^>]*>.*?</span>)?"#,
+        )
+        .unwrap()
+    });
+
+    FOOTNOTE_REFERENCE.replace_all(content, "")
 }
 
 // The rust book uses annotations for rustdoc to test code snippets,
@@ -949,15 +1479,17 @@ static CODE_BLOCK_RE: Lazy<Regex> =
 fn add_playground_pre(
     html: &str,
     playground_config: &Playground,
+    class_prefix: &str,
     edition: Option<RustEdition>,
 ) -> String {
+    let rust_class = format!("{}rust", class_prefix);
     CODE_BLOCK_RE
         .replace_all(html, |caps: &Captures<'_>| {
             let text = &caps[1];
             let classes = &caps[2];
             let code = &caps[3];
 
-            if classes.contains("language-rust")
+            if classes.contains(&rust_class)
                 && ((!classes.contains("ignore")
                     && !classes.contains("noplayground")
                     && !classes.contains("noplaypen")
@@ -1011,9 +1543,9 @@ fn add_playground_pre(
 
 /// Modifies all `<code>` blocks to convert "hidden" lines and to wrap them in
 /// a `<span class="boring">`.
-fn hide_lines(html: &str, code_config: &Code) -> String {
-    static LANGUAGE_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\blanguage-(\w+)\b").unwrap());
+fn hide_lines(html: &str, code_config: &Code, class_prefix: &str) -> String {
     static HIDELINES_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\bhidelines=(\S+)").unwrap());
+    let rust_class = format!("{}rust", class_prefix);
 
     CODE_BLOCK_RE
         .replace_all(html, |caps: &Captures<'_>| {
@@ -1021,7 +1553,7 @@ fn hide_lines(html: &str, code_config: &Code) -> String {
             let classes = &caps[2];
             let code = &caps[3];
 
-            if classes.contains("language-rust") {
+            if classes.contains(&rust_class) {
                 format!(
                     "<code class=\"{}\">{}</code>",
                     classes,
@@ -1034,9 +1566,16 @@ fn hide_lines(html: &str, code_config: &Code) -> String {
                     Some(capture) => Some(&capture[1]),
                     None => {
                         // Then look up the prefix by language
-                        LANGUAGE_REGEX.captures(classes).and_then(|capture| {
-                            code_config.hidelines.get(&capture[1]).map(|p| p.as_str())
-                        })
+                        classes
+                            .split_whitespace()
+                            .find_map(|class| class.strip_prefix(class_prefix))
+                            .and_then(|language| {
+                                let end = language
+                                    .find(|c: char| !c.is_alphanumeric() && c != '_')
+                                    .unwrap_or(language.len());
+                                code_config.hidelines.get(&language[..end])
+                            })
+                            .map(|p| p.as_str())
                     }
                 };
 
@@ -1053,6 +1592,88 @@ fn hide_lines(html: &str, code_config: &Code) -> String {
         .into_owned()
 }
 
+/// Turns the `start-line=N` attribute of code blocks, which includes with the
+/// `start-line` property add, into a `data-start-line` attribute that
+/// line number gutters can start counting at.
+fn add_start_line_attributes(html: &str) -> String {
+    static CODE_CLASSES: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r##"<code([^>]*?)class="([^"]*\bstart-line=[^"]*)"([^>]*)>"##).unwrap()
+    });
+
+    CODE_CLASSES
+        .replace_all(html, |caps: &Captures<'_>| {
+            let mut start_line = None;
+            let classes: Vec<&str> = caps[2]
+                .split_whitespace()
+                .filter(|class| match class.strip_prefix("start-line=") {
+                    Some(line) if line.parse::<usize>().is_ok() => {
+                        start_line = Some(line);
+                        false
+                    }
+                    _ => true,
+                })
+                .collect();
+            let Some(start_line) = start_line else {
+                return caps[0].to_owned();
+            };
+            let mut code = if classes.is_empty() {
+                format!("<code{}", caps[1].trim_end())
+            } else {
+                format!(r#"<code{}class="{}""#, &caps[1], classes.join(" "))
+            };
+            code.push_str(&format!(r#" data-start-line="{start_line}""#));
+            code.push_str(&caps[3]);
+            code.push('>');
+            code
+        })
+        .into_owned()
+}
+
+/// Moves the code block attributes listed in `consumed-attributes` out of the
+/// class list, either dropping them or turning them into `data-` attributes.
+fn strip_consumed_attributes(html: &str, code_config: &Code) -> String {
+    static CODE_CLASSES: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r##"<code([^>]*?)class="([^"]+)"([^>]*)>"##).unwrap());
+
+    if code_config.consumed_attributes.is_empty() {
+        return html.to_owned();
+    }
+
+    CODE_CLASSES
+        .replace_all(html, |caps: &Captures<'_>| {
+            let (consumed, classes): (Vec<&str>, Vec<&str>) =
+                caps[2].split_whitespace().partition(|class| {
+                    code_config
+                        .consumed_attributes
+                        .iter()
+                        .any(|prefix| class.starts_with(prefix.as_str()))
+                });
+            if consumed.is_empty() {
+                return caps[0].to_owned();
+            }
+
+            let mut code = if classes.is_empty() {
+                format!("<code{}", caps[1].trim_end())
+            } else {
+                format!(r#"<code{}class="{}""#, &caps[1], classes.join(" "))
+            };
+            if code_config.consumed_attributes_as_data {
+                for attribute in consumed {
+                    match attribute.split_once('=') {
+                        Some((name, value)) => {
+                            code.push_str(&format!(r#" data-{}="{}""#, name, value))
+                        }
+                        None => code.push_str(&format!(" data-{}", attribute)),
+                    }
+                }
+            }
+            code.push_str(&caps[3]);
+            code.push('>');
+            code
+        })
+        .into_owned()
+}
+
 fn hide_lines_rust(content: &str) -> String {
     static BORING_LINES_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(\s*)#(.?)(.*)$").unwrap());
 
@@ -1132,10 +1753,13 @@ struct RenderItemContext<'a> {
     destination: PathBuf,
     data: serde_json::Map<String, serde_json::Value>,
     is_index: bool,
+    src_dir: PathBuf,
     book_config: BookConfig,
     html_config: HtmlConfig,
     edition: Option<RustEdition>,
     chapter_titles: &'a HashMap<PathBuf, String>,
+    known_pages: &'a HashSet<String>,
+    wiki_links: &'a utils::WikiLinks,
 }
 
 #[cfg(test)]
@@ -1190,11 +1814,510 @@ mod tests {
         ];
 
         for (src, should_be) in inputs {
-            let got = build_header_links(src, None);
+            let got = build_header_links(src, None, &Markdown::default());
             assert_eq!(got, should_be);
         }
     }
 
+    #[test]
+    fn build_header_links_up_to_max_level() {
+        let src = "<h2>Two</h2><h3>Three</h3><h4>Four</h4><h5>Five</h5><h6>Six</h6><h5>Two</h5>";
+        let should_be = concat!(
+            r##"<h2 id="two"><a class="header" href="#two">Two</a></h2>"##,
+            r##"<h3 id="three"><a class="header" href="#three">Three</a></h3>"##,
+            r##"<h4 id="four"><a class="header" href="#four">Four</a></h4>"##,
+            "<h5>Five</h5><h6>Six</h6><h5>Two</h5>",
+        );
+        let markdown_config = Markdown {
+            max_id_heading_level: 4,
+            ..Markdown::default()
+        };
+        assert_eq!(build_header_links(src, None, &markdown_config), should_be);
+    }
+
+    #[test]
+    fn build_header_links_percent_encoded() {
+        let src = "<h1>中文標題</h1><h2>Plain</h2>";
+        let should_be = concat!(
+            r##"<h1 id="中文標題"><a class="header" href="#%E4%B8%AD%E6%96%87%E6%A8%99%E9%A1%8C">中文標題</a></h1>"##,
+            r##"<h2 id="plain"><a class="header" href="#plain">Plain</a></h2>"##,
+        );
+        let markdown_config = Markdown {
+            percent_encode_header_links: true,
+            ..Markdown::default()
+        };
+        assert_eq!(build_header_links(src, None, &markdown_config), should_be);
+    }
+
+    #[test]
+    fn build_header_links_with_github_anchor_aliases() {
+        let src = "<h1>Q &amp; A</h1><h2>Über uns</h2><h2>Über uns</h2><h2>🚀 Launch</h2>";
+        let should_be = concat!(
+            r##"<h1 id="q--a"><a class="header" href="#q--a">Q &amp; A</a></h1>"##,
+            r##"<h2 id="Über-uns"><span id="über-uns"></span><a class="header" href="#Über-uns">Über uns</a></h2>"##,
+            r##"<h2 id="Über-uns-1"><span id="über-uns-1"></span><a class="header" href="#Über-uns-1">Über uns</a></h2>"##,
+            r##"<h2 id="launch"><span id="-launch"></span><a class="header" href="#launch">🚀 Launch</a></h2>"##,
+        );
+        let markdown_config = Markdown {
+            github_anchor_aliases: true,
+            ..Markdown::default()
+        };
+        assert_eq!(build_header_links(src, None, &markdown_config), should_be);
+
+        // The `&` is spelled differently when IDs keep their case.
+        let markdown_config = Markdown {
+            github_anchor_aliases: true,
+            lowercase_ids: false,
+            ..Markdown::default()
+        };
+        assert_eq!(
+            build_header_links("<h2>Tom &amp; Jerry</h2>", None, &markdown_config),
+            r##"<h2 id="Tom--Jerry"><span id="tom--jerry"></span><a class="header" href="#Tom--Jerry">Tom &amp; Jerry</a></h2>"##
+        );
+        assert_eq!(
+            build_header_links("<h2>Tom &amp; Jerry</h2>", Some("intro"), &markdown_config),
+            r##"<h2 id="intro:Tom--Jerry"><a class="header" href="#intro:Tom--Jerry">Tom &amp; Jerry</a></h2>"##
+        );
+    }
+
+    #[test]
+    fn page_class_from_front_matter() {
+        let page_class = |text| page_class(&utils::split_front_matter(text).0.unwrap());
+        assert_eq!(
+            page_class("---\nclass: landing\n---\n"),
+            Some("landing".to_owned())
+        );
+        assert_eq!(
+            page_class("---\nclass: [landing, wide]\n---\n"),
+            Some("landing wide".to_owned())
+        );
+        assert_eq!(
+            page_class("+++\nclass = \"landing  wide\"\n+++\n"),
+            Some("landing wide".to_owned())
+        );
+        assert_eq!(page_class("---\ntitle: Home\n---\n"), None);
+        assert_eq!(page_class("---\nclass: []\n---\n"), None);
+    }
+
+    #[test]
+    fn build_print_element_id_prefixes_footnote_references() {
+        let html = concat!(
+            r##"<p><sup class="footnote-reference"><a href="#a">1</a></sup>"##,
+            r##"<a class="footnote-reference" href="#b">[2]</a><a href="#first-page-c">c</a></p>"##,
+            r##"<div class="footnote-definition" id="a"></div>"##,
+        );
+        assert_eq!(
+            build_print_element_id(html, "first-page"),
+            concat!(
+                r##"<p><sup class="footnote-reference"><a href="#first-page:a">1</a></sup>"##,
+                r##"<a class="footnote-reference" href="#first-page:b">[2]</a><a href="#first-page-c">c</a></p>"##,
+                r##"<div class="footnote-definition" id="first-page:a"></div>"##,
+            )
+        );
+    }
+
+    #[test]
+    fn fix_links_to_missing_pages() {
+        let known_pages: HashSet<String> = ["index.html", "guide/intro.html", "guide/setup.html"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let path = Path::new("guide/intro.md");
+        let html = r#"<a href="setup.html#install">Setup</a> <a href="../index.html">Home</a> <a href="missing.html?x=1">Missing</a> <a href="https://example.com/missing.html">External</a> <a href="../../other/index.html">Other</a>"#;
+
+        assert_eq!(
+            fix_missing_page_links(
+                html,
+                path,
+                &known_pages,
+                MissingPageLinks::Ignore,
+                "404.html"
+            ),
+            html
+        );
+        assert_eq!(
+            fix_missing_page_links(html, path, &known_pages, MissingPageLinks::Warn, "404.html"),
+            html
+        );
+        assert_eq!(
+            fix_missing_page_links(
+                html,
+                path,
+                &known_pages,
+                MissingPageLinks::Redirect,
+                "404.html"
+            ),
+            html.replace("missing.html?x=1", "../404.html")
+        );
+    }
+
+    #[test]
+    fn edit_url_substitutes_the_source_path() {
+        let template = "https://github.com/org/repo/edit/main/{path}?plain=1";
+        assert_eq!(
+            edit_url(
+                template,
+                Path::new("src"),
+                Path::new("guide/nested/intro.md")
+            ),
+            "https://github.com/org/repo/edit/main/src/guide/nested/intro.md?plain=1"
+        );
+        assert_eq!(
+            edit_url(
+                template,
+                Path::new("docs\\src"),
+                Path::new("guide\\intro.md")
+            ),
+            "https://github.com/org/repo/edit/main/docs/src/guide/intro.md?plain=1"
+        );
+    }
+
+    #[test]
+    fn add_image_dimensions_to_local_images() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(
+            temp.path().join("cat.png"),
+            b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\x01\x2c\0\0\0\x96\x08\x06\0\0\0",
+        )
+        .unwrap();
+
+        let inputs = vec![
+            (
+                r#"<img src="cat.png" alt="A cat" />"#,
+                r#"<img src="cat.png" alt="A cat" width="300" height="150" />"#,
+            ),
+            (
+                r#"<p><img src="cat.png?v=1">"#,
+                r#"<p><img src="cat.png?v=1" width="300" height="150">"#,
+            ),
+            (
+                r#"<img src="cat.png" width="30" />"#,
+                r#"<img src="cat.png" width="30" />"#,
+            ),
+            (
+                r#"<img src="missing.png" />"#,
+                r#"<img src="missing.png" />"#,
+            ),
+            (
+                r#"<img src="https://example.com/cat.png" />"#,
+                r#"<img src="https://example.com/cat.png" />"#,
+            ),
+        ];
+
+        let extensions = Markdown::default().image_extensions;
+        for (src, should_be) in inputs {
+            assert_eq!(
+                add_image_dimensions(src, temp.path(), &extensions),
+                should_be
+            );
+        }
+    }
+
+    #[test]
+    fn add_image_dimensions_for_configured_extensions() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(
+            temp.path().join("cat.avif"),
+            b"\0\0\0\x14ftypavif\0\0\0\0mif1\0\0\0\x14ispe\0\0\0\0\0\0\x01\x2c\0\0\0\x96",
+        )
+        .unwrap();
+        let src = r#"<img src="cat.avif?v=1" alt="A cat" />"#;
+
+        let should_be = r#"<img src="cat.avif?v=1" alt="A cat" width="300" height="150" />"#;
+        let extensions = Markdown::default().image_extensions;
+        assert_eq!(
+            add_image_dimensions(src, temp.path(), &extensions),
+            should_be
+        );
+
+        let mut extensions = vec!["png".to_string()];
+        assert_eq!(add_image_dimensions(src, temp.path(), &extensions), src);
+
+        extensions.push("AVIF".to_string());
+        assert_eq!(
+            add_image_dimensions(src, temp.path(), &extensions),
+            should_be
+        );
+    }
+
+    #[test]
+    fn add_dark_image_variants_to_local_images() {
+        let temp = tempfile::tempdir().unwrap();
+        for file in ["diagram.png", "diagram.dark.png", "light-only.png"] {
+            fs::write(temp.path().join(file), b"").unwrap();
+        }
+
+        let inputs = vec![
+            (
+                r#"<p><img src="diagram.png" alt="A diagram" /></p>"#,
+                r#"<p><picture><source srcset="diagram.dark.png" media="(prefers-color-scheme: dark)"><img src="diagram.png" alt="A diagram" /></picture></p>"#,
+            ),
+            (
+                r#"<img src="diagram.png?v=1">"#,
+                r#"<picture><source srcset="diagram.dark.png?v=1" media="(prefers-color-scheme: dark)"><img src="diagram.png?v=1"></picture>"#,
+            ),
+            (
+                r#"<img src="light-only.png" alt="Light" />"#,
+                r#"<img src="light-only.png" alt="Light" />"#,
+            ),
+            (
+                r#"<img src="diagram.dark.png" />"#,
+                r#"<img src="diagram.dark.png" />"#,
+            ),
+            (
+                r#"<img src="https://example.com/diagram.png" />"#,
+                r#"<img src="https://example.com/diagram.png" />"#,
+            ),
+        ];
+
+        for (src, should_be) in inputs {
+            assert_eq!(add_dark_image_variants(src, temp.path()), should_be);
+        }
+    }
+
+    #[test]
+    fn wrap_heading_sections_at_level_two_headers() {
+        let src = "<p>Intro</p>\n\
+                   <h2 id=\"one\">One</h2>\n<p>First</p>\n\
+                   <h3 id=\"nested\">Nested</h3>\n<p>Deeper</p>\n\
+                   <blockquote>\n<h2 id=\"quoted\">Quoted</h2>\n</blockquote>\n\
+                   <h2 id=\"two\" class=\"x\">Two</h2>\n<p>Second</p>\n";
+        let should_be = "<p>Intro</p>\n\
+                         <section aria-labelledby=\"one\">\n\
+                         <h2 id=\"one\">One</h2>\n<p>First</p>\n\
+                         <h3 id=\"nested\">Nested</h3>\n<p>Deeper</p>\n\
+                         <blockquote>\n<h2 id=\"quoted\">Quoted</h2>\n</blockquote>\n\
+                         </section>\n<section aria-labelledby=\"two\">\n\
+                         <h2 id=\"two\" class=\"x\">Two</h2>\n<p>Second</p>\n\
+                         </section>\n";
+        assert_eq!(wrap_heading_sections(src), should_be);
+
+        let src = "<h1 id=\"title\">Title</h1>\n<p>No sections</p>\n";
+        assert_eq!(wrap_heading_sections(src), src);
+    }
+
+    #[test]
+    fn build_header_links_with_custom_separator() {
+        let markdown_config = Markdown {
+            id_separator: '_',
+            ..Markdown::default()
+        };
+        assert_eq!(
+            build_header_links(
+                "<h1>Hello World</h1><h2>Hello World</h2>",
+                None,
+                &markdown_config
+            ),
+            r##"<h1 id="hello_world"><a class="header" href="#hello_world">Hello World</a></h1><h2 id="hello_world_1"><a class="header" href="#hello_world_1">Hello World</a></h2>"##
+        );
+    }
+
+    #[test]
+    fn build_header_links_with_each_mode() {
+        let src = r#"<h2 class="note">Hello World</h2>"#;
+        let modes = [
+            (
+                HeaderLinkMode::WholeHeading,
+                r##"<h2 id="hello-world" class="note"><a class="header" href="#hello-world">Hello World</a></h2>"##,
+            ),
+            (
+                HeaderLinkMode::IconOnly,
+                r##"<h2 id="hello-world" class="note">Hello World<a class="header-anchor" href="#hello-world" aria-label="Link to this heading">#</a></h2>"##,
+            ),
+            (
+                HeaderLinkMode::None,
+                r##"<h2 id="hello-world" class="note">Hello World</h2>"##,
+            ),
+        ];
+        for (header_links, should_be) in modes {
+            let markdown_config = Markdown {
+                header_links,
+                ..Markdown::default()
+            };
+            assert_eq!(build_header_links(src, None, &markdown_config), should_be);
+        }
+    }
+
+    #[test]
+    fn wiki_links_point_to_header_ids() {
+        use crate::book::Chapter;
+
+        let mut book = Book::new();
+        book.push_item(Chapter::new(
+            "Getting Started",
+            "# Getting Started\n\n## Setup\n\n## Setup\n\n### Custom {#own-id}\n".to_string(),
+            "guide/start.md",
+            Vec::new(),
+        ));
+        book.push_item(Chapter::new_draft("Draft", Vec::new()));
+        let markdown_config = Markdown {
+            section_number_ids: true,
+            ..Markdown::default()
+        };
+        let html_config = HtmlConfig {
+            markdown: markdown_config.clone(),
+            ..HtmlConfig::default()
+        };
+        let links = wiki_links(&book, &html_config);
+        let from = Path::new("index.md");
+        assert_eq!(
+            links.resolve("getting started", from),
+            Ok("guide/start.md".to_string())
+        );
+        assert_eq!(
+            links.resolve("Custom", from),
+            Ok("guide/start.md#own-id".to_string())
+        );
+        assert_eq!(
+            links.resolve("Setup", from),
+            Err(utils::WikiLinkError::Ambiguous(vec![
+                "guide/start.md#sec-1-1-setup".to_string(),
+                "guide/start.md#sec-1-2-setup".to_string(),
+            ]))
+        );
+        assert_eq!(
+            links.resolve("Draft", from),
+            Err(utils::WikiLinkError::Missing)
+        );
+        // The IDs match the ones of the rendered headers.
+        let BookItem::Chapter(ch) = book.iter().next().unwrap() else {
+            unreachable!()
+        };
+        let html = build_header_links(
+            &utils::render_markdown(&ch.content, false),
+            None,
+            &markdown_config,
+        );
+        for id in ["sec-1-1-setup", "sec-1-2-setup", "own-id"] {
+            assert!(html.contains(&format!(r#"id="{id}""#)), "{html}");
+        }
+    }
+
+    #[test]
+    fn wiki_links_point_to_headers_with_footnote_references() {
+        use crate::book::Chapter;
+        use crate::config::FootnoteStyle;
+
+        let mut book = Book::new();
+        book.push_item(Chapter::new(
+            "Notes",
+            "# Notes\n\n## Q&A[^1]\n\n## Heading[^2]\n\n[^1]: First.\n\n[^2]: Second.\n"
+                .to_string(),
+            "notes.md",
+            Vec::new(),
+        ));
+        for footnote_style in [FootnoteStyle::Superscript, FootnoteStyle::Bracketed] {
+            let html_config = HtmlConfig {
+                markdown: Markdown {
+                    footnote_style,
+                    ..Markdown::default()
+                },
+                ..HtmlConfig::default()
+            };
+            let links = wiki_links(&book, &html_config);
+            let from = Path::new("index.md");
+            assert_eq!(
+                links.resolve("Heading", from),
+                Ok("notes.md#heading".to_string())
+            );
+            assert_eq!(links.resolve("Q&A", from), Ok("notes.md#qa".to_string()));
+
+            let BookItem::Chapter(ch) = book.iter().next().unwrap() else {
+                unreachable!()
+            };
+            let html = build_header_links(
+                &utils::render_markdown_with_options(&ch.content, false, &html_config.markdown),
+                None,
+                &html_config.markdown,
+            );
+            for id in ["heading", "qa"] {
+                assert!(html.contains(&format!(r#"<h2 id="{id}">"#)), "{html}");
+            }
+        }
+    }
+
+    #[test]
+    fn build_header_links_with_section_numbers() {
+        let src = "<h1>Intro</h1><h2>Setup</h2><h3>Linux</h3><h2>Setup</h2>\
+                   <h3 id=\"custom\">Windows</h3><h1>Usage</h1><h2>Setup</h2>";
+        let markdown_config = Markdown {
+            section_number_ids: true,
+            header_links: HeaderLinkMode::None,
+            ..Markdown::default()
+        };
+        assert_eq!(
+            build_header_links(src, None, &markdown_config),
+            "<h1 id=\"sec-1-intro\">Intro</h1><h2 id=\"sec-1-1-setup\">Setup</h2>\
+             <h3 id=\"sec-1-1-1-linux\">Linux</h3><h2 id=\"sec-1-2-setup\">Setup</h2>\
+             <h3 id=\"custom\">Windows</h3><h1 id=\"sec-2-usage\">Usage</h1>\
+             <h2 id=\"sec-2-1-setup\">Setup</h2>"
+        );
+
+        // Pages without a level one heading start numbering at their top level.
+        let src = "<h2>Setup</h2><h3>Linux</h3><h2>Usage</h2>";
+        assert_eq!(
+            build_header_links(src, Some("page"), &markdown_config),
+            "<h2 id=\"page:sec-1-setup\">Setup</h2><h3 id=\"page:sec-1-1-linux\">Linux</h3>\
+             <h2 id=\"page:sec-2-usage\">Usage</h2>"
+        );
+    }
+
+    #[test]
+    fn build_header_links_skips_reserved_ids() {
+        let markdown_config = Markdown {
+            reserved_ids: vec!["content".to_string()],
+            ..Markdown::default()
+        };
+        assert_eq!(
+            build_header_links("<h1>Content</h1>", None, &markdown_config),
+            r##"<h1 id="content-1"><a class="header" href="#content-1">Content</a></h1>"##
+        );
+    }
+
+    #[test]
+    fn build_header_links_ignores_footnote_references() {
+        let inputs = vec![
+            (
+                r##"<h2>Foo<sup class="footnote-reference"><a href="#1">1</a></sup></h2>"##,
+                r##"<h2 id="foo"><a class="header" href="#foo">Foo<sup class="footnote-reference"><a href="#1">1</a></sup></a></h2>"##,
+            ),
+            (
+                r##"<h2>Foo <sup class="footnote-reference"><a href="#note">2</a></sup>bar</h2>"##,
+                r##"<h2 id="foo-bar"><a class="header" href="#foo-bar">Foo <sup class="footnote-reference"><a href="#note">2</a></sup>bar</a></h2>"##,
+            ),
+        ];
+
+        for (src, should_be) in inputs {
+            let got = build_header_links(src, None, &Markdown::default());
+            assert_eq!(got, should_be);
+        }
+
+        let src = utils::render_markdown("## Foo[^1]\n\n[^1]: A note.", false);
+        let got = build_header_links(&src, Some("chapter"), &Markdown::default());
+        assert!(got.contains(r#"<h2 id="chapter:foo">"#), "{got}");
+    }
+
+    #[test]
+    fn build_header_links_ignores_bracketed_footnote_references() {
+        let markdown_config = Markdown {
+            footnote_style: crate::config::FootnoteStyle::Bracketed,
+            ..Markdown::default()
+        };
+        let src = utils::render_markdown_with_options(
+            "## Heading[^1]\n\n[^1]: A note.",
+            false,
+            &markdown_config,
+        );
+        assert!(
+            src.contains(r##"<a class="footnote-reference" href="#1">[1]</a>"##),
+            "{src}"
+        );
+        let got = build_header_links(&src, None, &markdown_config);
+        assert!(got.contains(r#"<h2 id="heading">"#), "{got}");
+        let got = build_header_links(&src, Some("chapter"), &markdown_config);
+        assert!(got.contains(r#"<h2 id="chapter:heading">"#), "{got}");
+    }
+
     #[test]
     fn add_playground() {
         let inputs = [
@@ -1220,6 +2343,7 @@ mod tests {
                     editable: true,
                     ..Playground::default()
                 },
+                "language-",
                 None,
             );
             assert_eq!(&*got, *should_be);
@@ -1244,6 +2368,7 @@ mod tests {
                     editable: true,
                     ..Playground::default()
                 },
+                "language-",
                 Some(RustEdition::E2015),
             );
             assert_eq!(&*got, *should_be);
@@ -1268,6 +2393,7 @@ mod tests {
                     editable: true,
                     ..Playground::default()
                 },
+                "language-",
                 Some(RustEdition::E2018),
             );
             assert_eq!(&*got, *should_be);
@@ -1292,6 +2418,7 @@ mod tests {
                     editable: true,
                     ..Playground::default()
                 },
+                "language-",
                 Some(RustEdition::E2021),
             );
             assert_eq!(&*got, *should_be);
@@ -1324,7 +2451,7 @@ mod tests {
            "<pre class=\"playground\"><code class=\"language-rust editable\">#![no_std]\nlet s = \"foo\";\n #[some_attr]</code></pre>",),
         ];
         for (src, should_be) in &inputs {
-            let got = hide_lines(src, &Code::default());
+            let got = hide_lines(src, &Code::default(), "language-");
             assert_eq!(&*got, *should_be);
         }
     }
@@ -1339,19 +2466,89 @@ mod tests {
             "<code class=\"language-python hidelines=!!!\">!!!hidden()\nnothidden():\n!!!    hidden()\n    !!!hidden()\n    nothidden()</code>",
             "<code class=\"language-python hidelines=!!!\"><span class=\"boring\">hidden()\n</span>nothidden():\n<span class=\"boring\">    hidden()\n</span><span class=\"boring\">    hidden()\n</span>    nothidden()\n</code>",),
         ];
+        let code_config = Code {
+            hidelines: {
+                let mut map = HashMap::new();
+                map.insert("python".to_string(), "~".to_string());
+                map
+            },
+            ..Default::default()
+        };
         for (src, should_be) in &inputs {
-            let got = hide_lines(
-                src,
-                &Code {
-                    hidelines: {
-                        let mut map = HashMap::new();
-                        map.insert("python".to_string(), "~".to_string());
-                        map
-                    },
-                },
-            );
+            let got = hide_lines(src, &code_config, "language-");
             assert_eq!(&*got, *should_be);
         }
+
+        // The language is looked up with the configured class prefix.
+        let src = "<code class=\"editable lang-python\">~hidden()\nnothidden()</code>";
+        assert_eq!(
+            hide_lines(src, &code_config, "lang-"),
+            "<code class=\"editable lang-python\"><span class=\"boring\">hidden()\n</span>nothidden()\n</code>"
+        );
+        assert_eq!(hide_lines(src, &code_config, "language-"), src);
+    }
+
+    #[test]
+    fn strip_consumed_code_block_attributes() {
+        let code_config = Code {
+            consumed_attributes: vec!["hidelines=".to_string(), "editable".to_string()],
+            consumed_attributes_as_data: true,
+            ..Default::default()
+        };
+        let inputs = [
+            (
+                r#"<code class="language-python no_run hidelines=~">x</code>"#,
+                r#"<code class="language-python no_run" data-hidelines="~">x</code>"#,
+            ),
+            (
+                r#"<code class="language-rust editable">x</code>"#,
+                r#"<code class="language-rust" data-editable>x</code>"#,
+            ),
+            (
+                r#"<code class="language-rust no_run">x</code>"#,
+                r#"<code class="language-rust no_run">x</code>"#,
+            ),
+        ];
+        for (src, should_be) in &inputs {
+            assert_eq!(strip_consumed_attributes(src, &code_config), *should_be);
+        }
+
+        let code_config = Code {
+            consumed_attributes_as_data: false,
+            ..code_config
+        };
+        assert_eq!(
+            strip_consumed_attributes(
+                r#"<code class="language-python no_run hidelines=~">x</code>"#,
+                &code_config
+            ),
+            r#"<code class="language-python no_run">x</code>"#
+        );
+    }
+
+    #[test]
+    fn add_start_line_attributes_to_code_blocks() {
+        let inputs = [
+            (
+                r#"<code class="language-rust start-line=100">x</code>"#,
+                r#"<code class="language-rust" data-start-line="100">x</code>"#,
+            ),
+            (
+                r#"<code class="start-line=7">x</code>"#,
+                r#"<code data-start-line="7">x</code>"#,
+            ),
+            (
+                r#"<code class="language-rust start-line=abc">x</code>"#,
+                r#"<code class="language-rust start-line=abc">x</code>"#,
+            ),
+            (
+                r#"<code class="language-rust">x</code>"#,
+                r#"<code class="language-rust">x</code>"#,
+            ),
+        ];
+        for (src, should_be) in &inputs {
+            assert_eq!(add_start_line_attributes(src), *should_be);
+        }
     }
 
     #[test]
@@ -7,7 +7,7 @@ use once_cell::sync::Lazy;
 use pulldown_cmark::*;
 
 use crate::book::{Book, BookItem};
-use crate::config::Search;
+use crate::config::{Markdown, Search};
 use crate::errors::*;
 use crate::theme::searcher;
 use crate::utils;
@@ -26,7 +26,12 @@ fn tokenize(text: &str) -> Vec<String> {
 }
 
 /// Creates all files required for search.
-pub fn create_files(search_config: &Search, destination: &Path, book: &Book) -> Result<()> {
+pub fn create_files(
+    search_config: &Search,
+    markdown_config: &Markdown,
+    destination: &Path,
+    book: &Book,
+) -> Result<()> {
     let mut index = IndexBuilder::new()
         .add_field_with_tokenizer("title", Box::new(&tokenize))
         .add_field_with_tokenizer("body", Box::new(&tokenize))
@@ -36,7 +41,13 @@ pub fn create_files(search_config: &Search, destination: &Path, book: &Book) ->
     let mut doc_urls = Vec::with_capacity(book.sections.len());
 
     for item in book.iter() {
-        render_item(&mut index, search_config, &mut doc_urls, item)?;
+        render_item(
+            &mut index,
+            search_config,
+            markdown_config,
+            &mut doc_urls,
+            item,
+        )?;
     }
 
     let index = write_to_json(index, search_config, doc_urls)?;
@@ -62,12 +73,14 @@ pub fn create_files(search_config: &Search, destination: &Path, book: &Book) ->
 }
 
 /// Uses the given arguments to construct a search document, then inserts it to the given index.
+#[allow(clippy::too_many_arguments)]
 fn add_doc(
     index: &mut Index,
     doc_urls: &mut Vec<String>,
     anchor_base: &str,
     heading: &str,
     id_counter: &mut HashMap<String, usize>,
+    id_style: utils::IdStyle,
     section_id: &Option<CowStr<'_>>,
     items: &[&str],
 ) {
@@ -78,7 +91,9 @@ fn add_doc(
             // In the case where a chapter has no heading, don't set a section id.
             None
         } else {
-            Some(utils::unique_id_from_content(heading, id_counter))
+            Some(utils::unique_id_from_content_with_style(
+                heading, id_counter, id_style,
+            ))
         }
     });
 
@@ -95,10 +110,20 @@ fn add_doc(
     index.add_doc(&doc_ref, items);
 }
 
+/// The text the ID of a heading is generated from, starting with `sec` and
+/// its section number if there is one.
+fn id_content(heading: &str, section_number: Option<&str>) -> String {
+    match section_number {
+        Some(number) if !heading.is_empty() => format!("sec {} {}", number, heading),
+        _ => heading.to_string(),
+    }
+}
+
 /// Renders markdown into flat unformatted text and adds it to the search index.
 fn render_item(
     index: &mut Index,
     search_config: &Search,
+    markdown_config: &Markdown,
     doc_urls: &mut Vec<String>,
     item: &BookItem,
 ) -> Result<()> {
@@ -117,7 +142,12 @@ fn render_item(
         .with_context(|| "Could not convert HTML path to str")?;
     let anchor_base = utils::fs::normalize_path(filepath);
 
-    let mut p = utils::new_cmark_parser(&chapter.content, false).peekable();
+    let content = if markdown_config.front_matter {
+        utils::split_front_matter(&chapter.content).1
+    } else {
+        &chapter.content
+    };
+    let mut p = utils::new_cmark_parser(content, false).peekable();
 
     let mut in_heading = false;
     let max_section_depth = u32::from(search_config.heading_split_level);
@@ -129,8 +159,17 @@ fn render_item(
 
     breadcrumbs.push(chapter.name.clone());
 
-    let mut id_counter = HashMap::new();
+    let mut id_counter = utils::id_counter_with_reserved(&markdown_config.reserved_ids);
+    let mut section_counters = [0; 6];
+    let mut section_number = None;
     while let Some(event) = p.next() {
+        // Every heading is counted, so that the numbers match the page.
+        let heading_number = match &event {
+            Event::Start(Tag::Heading { level, .. }) if markdown_config.section_number_ids => Some(
+                utils::next_section_number(&mut section_counters, *level as usize),
+            ),
+            _ => None,
+        };
         match event {
             Event::Start(Tag::Heading { level, id, .. }) if level as u32 <= max_section_depth => {
                 if !heading.is_empty() {
@@ -140,8 +179,9 @@ fn render_item(
                         index,
                         doc_urls,
                         &anchor_base,
-                        &heading,
+                        &id_content(&heading, section_number.as_deref()),
                         &mut id_counter,
+                        markdown_config.into(),
                         &section_id,
                         &[&heading, &body, &breadcrumbs.join(" » ")],
                     );
@@ -151,6 +191,7 @@ fn render_item(
                 }
 
                 section_id = id;
+                section_number = heading_number;
                 in_heading = true;
             }
             Event::End(TagEnd::Heading(level)) if level as u32 <= max_section_depth => {
@@ -224,8 +265,9 @@ fn render_item(
             index,
             doc_urls,
             &anchor_base,
-            &heading,
+            &id_content(&heading, section_number.as_deref()),
             &mut id_counter,
+            markdown_config.into(),
             &section_id,
             &[title, &body, &breadcrumbs.join(" » ")],
         );
@@ -14,6 +14,7 @@ window.editors = [];
             showPrintMargin: false,
             showLineNumbers: display_line_numbers,
             showGutter: display_line_numbers,
+            firstLineNumber: Number(editable.dataset.startLine) || 1,
             maxLines: Infinity,
             fontSize: "0.875em" // please adjust the font size of the code in general.css
         });
@@ -0,0 +1,328 @@
+//! Looking up values in JSON and YAML files by their path.
+
+use crate::errors::*;
+use serde_json::{Map, Number, Value};
+
+/// A line of a YAML document, with its indentation and comment removed.
+struct Line<'a> {
+    number: usize,
+    indent: usize,
+    text: &'a str,
+}
+
+/// Parses a YAML document into the equivalent JSON value.
+///
+/// Only the subset of YAML that configuration files commonly use is
+/// understood: mappings and sequences nested by indentation, `[a, b]` and
+/// `{a: 1}` collections of scalars on a single line, comments, and plain,
+/// single-quoted or double-quoted scalars. Values starting with `|` or `>`
+/// block scalars, `&` anchors, `*` aliases or `!` tags are an error, as are
+/// other documents that don't fit this subset.
+pub(crate) fn parse_yaml(text: &str) -> Result<Value> {
+    let mut lines: Vec<Line<'_>> = text
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let text = strip_comment(line).trim_end();
+            let content = text.trim_start();
+            (!content.is_empty() && content != "---").then(|| Line {
+                number: index + 1,
+                indent: text.len() - content.len(),
+                text: content,
+            })
+        })
+        .collect();
+    if lines.is_empty() {
+        return Ok(Value::Null);
+    }
+    let mut pos = 0;
+    let indent = lines[0].indent;
+    let value = parse_node(&mut lines, &mut pos, indent)?;
+    if let Some(line) = lines.get(pos) {
+        bail!("Unexpected indentation on line {}", line.number);
+    }
+    Ok(value)
+}
+
+/// The `line` without a `#` comment, which starts the line or follows
+/// whitespace outside of quotes.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (index, ch) in line.char_indices() {
+        match quote {
+            Some(q) if ch == q => quote = None,
+            Some(_) => {}
+            None if ch == '"' || ch == '\'' => quote = Some(ch),
+            None if ch == '#' && previous.is_whitespace() => return &line[..index],
+            None => {}
+        }
+        previous = ch;
+    }
+    line
+}
+
+fn is_sequence_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+/// Parses the sequence or mapping whose first line is at `pos`.
+fn parse_node(lines: &mut [Line<'_>], pos: &mut usize, indent: usize) -> Result<Value> {
+    if is_sequence_item(lines[*pos].text) {
+        parse_sequence(lines, pos, indent)
+    } else {
+        parse_mapping(lines, pos, indent)
+    }
+}
+
+fn parse_sequence(lines: &mut [Line<'_>], pos: &mut usize, indent: usize) -> Result<Value> {
+    let mut items = Vec::new();
+    while let Some(line) = lines.get(*pos) {
+        if line.indent != indent || !is_sequence_item(line.text) {
+            break;
+        }
+        let text = line.text;
+        let rest = text[1..].trim_start();
+        if rest.is_empty() {
+            *pos += 1;
+            items.push(parse_nested(lines, pos, indent, false)?);
+        } else if is_sequence_item(rest) || split_key(rest).is_some() {
+            // A nested collection starting on the line of the item continues
+            // at the column of its first entry.
+            let line = &mut lines[*pos];
+            line.indent += line.text.len() - rest.len();
+            line.text = rest;
+            let indent = line.indent;
+            items.push(parse_node(lines, pos, indent)?);
+        } else {
+            let number = line.number;
+            *pos += 1;
+            items.push(parse_flow(rest, number)?);
+        }
+    }
+    Ok(Value::Array(items))
+}
+
+fn parse_mapping(lines: &mut [Line<'_>], pos: &mut usize, indent: usize) -> Result<Value> {
+    let mut map = Map::new();
+    while let Some(line) = lines.get(*pos) {
+        if line.indent != indent || is_sequence_item(line.text) {
+            break;
+        }
+        let text = line.text;
+        let Some((key, value)) = split_key(text) else {
+            bail!("Expected `key: value` on line {}", line.number);
+        };
+        let number = line.number;
+        *pos += 1;
+        let value = if value.is_empty() {
+            parse_nested(lines, pos, indent, true)?
+        } else {
+            parse_flow(value, number)?
+        };
+        map.insert(key, value);
+    }
+    if let Some(line) = lines.get(*pos).filter(|line| line.indent > indent) {
+        bail!("Unexpected indentation on line {}", line.number);
+    }
+    Ok(Value::Object(map))
+}
+
+/// Parses the value nested under a key or sequence item without a value on
+/// its own line, which is `null` if nothing is nested.
+///
+/// The sequence of a key may be at the same indentation as the key.
+fn parse_nested(
+    lines: &mut [Line<'_>],
+    pos: &mut usize,
+    indent: usize,
+    sequence_may_align: bool,
+) -> Result<Value> {
+    match lines.get(*pos) {
+        Some(next) if next.indent > indent => {
+            let indent = next.indent;
+            parse_node(lines, pos, indent)
+        }
+        Some(next)
+            if sequence_may_align && next.indent == indent && is_sequence_item(next.text) =>
+        {
+            parse_sequence(lines, pos, indent)
+        }
+        _ => Ok(Value::Null),
+    }
+}
+
+/// Splits a `key: value` entry of a mapping, unquoting the key.
+fn split_key(text: &str) -> Option<(String, &str)> {
+    let (key, rest) = match text.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let end = text[1..].find(quote)? + 1;
+            (text[1..end].to_string(), &text[end + 1..])
+        }
+        _ => {
+            let end = text
+                .find(": ")
+                .or_else(|| text.strip_suffix(':').map(str::len))?;
+            (text[..end].trim_end().to_string(), &text[end..])
+        }
+    };
+    let value = rest.strip_prefix(':')?;
+    (value.is_empty() || value.starts_with(' ')).then(|| (key, value.trim()))
+}
+
+/// Parses a scalar, or a `[a, b]` or `{a: 1}` collection of scalars, on
+/// line `number`.
+fn parse_flow(text: &str, number: usize) -> Result<Value> {
+    if let Some(c) = text.chars().next().filter(|c| "|>&*!".contains(*c)) {
+        bail!(
+            "Unsupported YAML value starting with `{}` on line {}",
+            c,
+            number
+        );
+    }
+    fn items(inner: &str) -> Vec<&str> {
+        inner
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .collect()
+    }
+    if let Some(inner) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        return Ok(Value::Array(
+            items(inner).into_iter().map(parse_scalar).collect(),
+        ));
+    }
+    if let Some(inner) = text.strip_prefix('{').and_then(|t| t.strip_suffix('}')) {
+        let entries = items(inner)
+            .into_iter()
+            .map(|entry| match split_key(entry) {
+                Some((key, value)) => (key, parse_scalar(value)),
+                None => (entry.to_string(), Value::Null),
+            });
+        return Ok(Value::Object(entries.collect()));
+    }
+    Ok(parse_scalar(text))
+}
+
+fn parse_scalar(text: &str) -> Value {
+    if text.starts_with('"') {
+        if let Ok(s) = serde_json::from_str::<String>(text) {
+            return Value::String(s);
+        }
+    }
+    if let Some(s) = text.strip_prefix('\'').and_then(|t| t.strip_suffix('\'')) {
+        return Value::String(s.replace("''", "'"));
+    }
+    match text {
+        "null" | "Null" | "NULL" | "~" => Value::Null,
+        "true" | "True" | "TRUE" => Value::Bool(true),
+        "false" | "False" | "FALSE" => Value::Bool(false),
+        _ => {
+            if let Ok(i) = text.parse::<i64>() {
+                Value::Number(i.into())
+            } else if let Some(n) = text
+                .starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.')
+                .then(|| text.parse::<f64>().ok())
+                .flatten()
+                .and_then(Number::from_f64)
+            {
+                Value::Number(n)
+            } else {
+                Value::String(text.to_string())
+            }
+        }
+    }
+}
+
+/// The value at the dotted `path` in `value`, like `server.port` or
+/// `items.0.name`, where numbers index into arrays.
+pub(crate) fn value_at_path<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(value, |value, key| match value {
+        Value::Object(map) => map.get(key),
+        Value::Array(items) => items.get(key.parse::<usize>().ok()?),
+        _ => None,
+    })
+}
+
+/// The text of a value: strings without quotes, other scalars as they are
+/// written in JSON, and arrays and objects as pretty-printed JSON.
+pub(crate) fn value_to_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(_) | Value::Object(_) => {
+            serde_json::to_string_pretty(value).expect("a JSON value can be serialized")
+        }
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn yaml_mappings_and_sequences() {
+        let yaml = "\
+# Server settings
+server:
+  host: \"localhost\"   # a comment
+  port: 8080
+  tls: false
+items:
+- name: first
+  tags: [a, 'b c']
+-   name: second
+    ratio: 0.5
+empty:
+quoted key: 'it''s'
+url: https://example.com/#anchor
+";
+        assert_eq!(
+            parse_yaml(yaml).unwrap(),
+            json!({
+                "server": {"host": "localhost", "port": 8080, "tls": false},
+                "items": [
+                    {"name": "first", "tags": ["a", "b c"]},
+                    {"name": "second", "ratio": 0.5},
+                ],
+                "empty": null,
+                "quoted key": "it's",
+                "url": "https://example.com/#anchor",
+            })
+        );
+    }
+
+    #[test]
+    fn yaml_errors() {
+        let err = parse_yaml("server:\n  port: 1\n    host: x\n").unwrap_err();
+        assert_eq!(err.to_string(), "Unexpected indentation on line 3");
+        let err = parse_yaml("server:\n  just text\n").unwrap_err();
+        assert_eq!(err.to_string(), "Expected `key: value` on line 2");
+        for (text, c) in [
+            ("text: |\n  line\n", '|'),
+            ("text: >-\n  line\n", '>'),
+            ("base: &base\n  port: 1\n", '&'),
+            ("other: *base\n", '*'),
+            ("- !!str 1\n", '!'),
+        ] {
+            let err = parse_yaml(text).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("Unsupported YAML value starting with `{c}` on line 1")
+            );
+        }
+    }
+
+    #[test]
+    fn values_at_paths() {
+        let value = json!({"server": {"port": 8080}, "items": [{"name": "first"}]});
+        assert_eq!(value_at_path(&value, "server.port"), Some(&json!(8080)));
+        assert_eq!(value_at_path(&value, "items.0.name"), Some(&json!("first")));
+        assert_eq!(value_at_path(&value, "items.1.name"), None);
+        assert_eq!(value_at_path(&value, "server.port.x"), None);
+        assert_eq!(value_to_text(&json!("first")), "first");
+        assert_eq!(value_to_text(&json!(0.5)), "0.5");
+        assert_eq!(value_to_text(&json!([1])), "[\n  1\n]");
+    }
+}
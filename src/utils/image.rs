@@ -0,0 +1,161 @@
+//! Reading the intrinsic dimensions of images from their file headers.
+
+use std::fs;
+use std::path::Path;
+
+/// Returns the width and height of the PNG, JPEG, GIF, WebP, or AVIF image
+/// at `path`.
+///
+/// Returns `None` if the file can't be read or isn't one of these formats.
+pub(crate) fn dimensions(path: &Path) -> Option<(u32, u32)> {
+    let data = fs::read(path).ok()?;
+    png_dimensions(&data)
+        .or_else(|| gif_dimensions(&data))
+        .or_else(|| webp_dimensions(&data))
+        .or_else(|| jpeg_dimensions(&data))
+        .or_else(|| avif_dimensions(&data))
+}
+
+fn be_u16(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at + 2)?;
+    Some(u32::from(u16::from_be_bytes([bytes[0], bytes[1]])))
+}
+
+fn le_u16(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at + 2)?;
+    Some(u32::from(u16::from_le_bytes([bytes[0], bytes[1]])))
+}
+
+fn le_u24(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at + 3)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]))
+}
+
+fn png_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    if !data.starts_with(b"\x89PNG\r\n\x1a\n") || data.get(12..16)? != b"IHDR" {
+        return None;
+    }
+    let width = u32::from_be_bytes(data.get(16..20)?.try_into().ok()?);
+    let height = u32::from_be_bytes(data.get(20..24)?.try_into().ok()?);
+    Some((width, height))
+}
+
+fn gif_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    if !data.starts_with(b"GIF87a") && !data.starts_with(b"GIF89a") {
+        return None;
+    }
+    Some((le_u16(data, 6)?, le_u16(data, 8)?))
+}
+
+fn webp_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    if !data.starts_with(b"RIFF") || data.get(8..12)? != b"WEBP" {
+        return None;
+    }
+    match data.get(12..16)? {
+        // Lossy: the frame header follows a three byte start code.
+        b"VP8 " => {
+            if data.get(23..26)? != b"\x9d\x01\x2a" {
+                return None;
+            }
+            Some((le_u16(data, 26)? & 0x3fff, le_u16(data, 28)? & 0x3fff))
+        }
+        // Lossless: 14 bits each for width and height, minus one.
+        b"VP8L" => {
+            if *data.get(20)? != 0x2f {
+                return None;
+            }
+            let bits = u32::from_le_bytes(data.get(21..25)?.try_into().ok()?);
+            Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+        }
+        // Extended: 24 bits each for width and height, minus one.
+        b"VP8X" => Some((le_u24(data, 24)? + 1, le_u24(data, 27)? + 1)),
+        _ => None,
+    }
+}
+
+fn jpeg_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    if !data.starts_with(b"\xff\xd8") {
+        return None;
+    }
+    let mut i = 2;
+    loop {
+        if *data.get(i)? != 0xff {
+            return None;
+        }
+        // Skip fill bytes in front of the marker.
+        while *data.get(i)? == 0xff {
+            i += 1;
+        }
+        let marker = *data.get(i)?;
+        match marker {
+            // Markers without a segment.
+            0x01 | 0xd0..=0xd8 => i += 1,
+            // Start of frame, except for DHT, JPG, and DAC.
+            0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                let height = be_u16(data, i + 4)?;
+                let width = be_u16(data, i + 6)?;
+                return Some((width, height));
+            }
+            _ => i += 1 + be_u16(data, i + 1)? as usize,
+        }
+    }
+}
+
+fn avif_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    if data.get(4..8)? != b"ftyp" || !matches!(data.get(8..12)?, b"avif" | b"avis") {
+        return None;
+    }
+    // The first image spatial extents property, after its version and flags.
+    let ispe = data.windows(4).position(|window| window == b"ispe")?;
+    let width = u32::from_be_bytes(data.get(ispe + 8..ispe + 12)?.try_into().ok()?);
+    let height = u32::from_be_bytes(data.get(ispe + 12..ispe + 16)?.try_into().ok()?);
+    Some((width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn png() {
+        let data = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\x01\x2c\0\0\0\x96\x08\x06\0\0\0";
+        assert_eq!(png_dimensions(data), Some((300, 150)));
+        assert_eq!(png_dimensions(b"\x89PNG\r\n\x1a\n"), None);
+    }
+
+    #[test]
+    fn gif() {
+        assert_eq!(gif_dimensions(b"GIF89a\x2c\x01\x96\0"), Some((300, 150)));
+        assert_eq!(gif_dimensions(b"GIF90a\x2c\x01\x96\0"), None);
+    }
+
+    #[test]
+    fn webp() {
+        let lossy = b"RIFF\0\0\0\0WEBPVP8 \0\0\0\0\0\0\0\x9d\x01\x2a\x2c\x01\x96\0";
+        assert_eq!(webp_dimensions(lossy), Some((300, 150)));
+        // (300 - 1) | (150 - 1) << 14
+        let lossless = b"RIFF\0\0\0\0WEBPVP8L\0\0\0\0\x2f\x2b\x41\x25\0";
+        assert_eq!(webp_dimensions(lossless), Some((300, 150)));
+        let extended = b"RIFF\0\0\0\0WEBPVP8X\0\0\0\0\0\0\0\0\x2b\x01\0\x95\0\0";
+        assert_eq!(webp_dimensions(extended), Some((300, 150)));
+    }
+
+    #[test]
+    fn jpeg() {
+        let data = b"\xff\xd8\xff\xe0\0\x04\0\0\xff\xc0\0\x11\x08\0\x96\x01\x2c\x03";
+        assert_eq!(jpeg_dimensions(data), Some((300, 150)));
+        assert_eq!(jpeg_dimensions(b"\xff\xd8\xff\xe0\0\x04\0\0"), None);
+    }
+
+    #[test]
+    fn avif() {
+        let data = b"\0\0\0\x14ftypavif\0\0\0\0mif1\0\0\0\x14ispe\0\0\0\0\0\0\x01\x2c\0\0\0\x96";
+        assert_eq!(avif_dimensions(data), Some((300, 150)));
+        assert_eq!(avif_dimensions(b"\0\0\0\x14ftypavif\0\0\0\0mif1"), None);
+    }
+
+    #[test]
+    fn unreadable_file() {
+        assert_eq!(dimensions(Path::new("does-not-exist.png")), None);
+    }
+}
@@ -1,23 +1,38 @@
 #![allow(missing_docs)] // FIXME: Document this
 
+pub(crate) mod data;
 pub mod fs;
+pub(crate) mod image;
+mod sanitize;
 mod string;
 pub(crate) mod toml_ext;
+mod wiki;
+use crate::config::{
+    EmojiIds, EmptyCodeBlocks, FootnoteStyle, InternalLinkStyle, Markdown, StrikethroughElement,
+};
 use crate::errors::Error;
 use log::error;
 use once_cell::sync::Lazy;
-use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, LinkType, Options, Parser, Tag, TagEnd};
+use pulldown_cmark::{
+    html, CodeBlockKind, CowStr, Event, HeadingLevel, LinkType, Options, Parser, Tag, TagEnd,
+};
 use regex::Regex;
 
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fmt::Write;
+use std::ops::Range;
 use std::path::{Component, Path, PathBuf};
 
 pub use self::string::{
-    take_anchored_lines, take_lines, take_rustdoc_include_anchored_lines,
-    take_rustdoc_include_lines,
+    expand_tabs, strip_comment_lines, strip_hidden_lines, take_anchored_lines, take_lines,
+    take_nth_anchored_lines, take_regex_region, take_rustdoc_include_anchored_lines,
+    take_rustdoc_include_lines, take_rustdoc_include_nth_anchored_lines, trim_blank_lines,
+    trim_trailing_whitespace,
 };
+pub use self::wiki::{link_wiki_references, WikiLinkError, WikiLinks};
+
+pub(crate) use self::sanitize::is_safe_url;
 
 /// Replaces multiple consecutive whitespace characters with a single space character.
 pub fn collapse_whitespace(text: &str) -> Cow<'_, str> {
@@ -25,21 +40,280 @@ pub fn collapse_whitespace(text: &str) -> Cow<'_, str> {
     RE.replace_all(text, " ")
 }
 
+/// How generated IDs are spelled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IdStyle {
+    /// Replaces whitespace and separates the counter of duplicate IDs.
+    pub separator: char,
+    /// Lowercase ASCII letters. Other letters always keep their case.
+    pub lowercase: bool,
+    /// Spell accented Latin letters with their ASCII base letters.
+    pub transliterate: bool,
+    /// How emoji are spelled.
+    pub emoji: EmojiIds,
+}
+
+impl Default for IdStyle {
+    fn default() -> IdStyle {
+        IdStyle {
+            separator: '-',
+            lowercase: true,
+            transliterate: false,
+            emoji: EmojiIds::Strip,
+        }
+    }
+}
+
+impl From<&Markdown> for IdStyle {
+    fn from(options: &Markdown) -> IdStyle {
+        IdStyle {
+            separator: options.id_separator,
+            lowercase: options.lowercase_ids,
+            transliterate: options.transliterate_ids,
+            emoji: options.emoji_ids,
+        }
+    }
+}
+
 /// Convert the given string to a valid HTML element ID.
 /// The only restriction is that the ID must not contain any ASCII whitespace.
 pub fn normalize_id(content: &str) -> String {
-    content
-        .chars()
-        .filter_map(|ch| {
-            if ch.is_alphanumeric() || ch == '_' || ch == '-' {
-                Some(ch.to_ascii_lowercase())
-            } else if ch.is_whitespace() {
-                Some('-')
+    normalize_id_with_style(content, IdStyle::default())
+}
+
+/// Like [`normalize_id`], but also returns the ID percent-encoded with
+/// [`percent_encode_id`], for use in the fragment of a link to the element.
+pub fn normalize_id_and_href(content: &str) -> (String, String) {
+    let id = normalize_id(content);
+    let href = percent_encode_id(&id).into_owned();
+    (id, href)
+}
+
+/// Percent-encodes the characters of the `id` that can't be written as they
+/// are in the fragment of a URL, like non-ASCII characters, so that `中文`
+/// becomes `%E4%B8%AD%E6%96%87`.
+pub fn percent_encode_id(id: &str) -> Cow<'_, str> {
+    let needs_encoding =
+        |byte: u8| !byte.is_ascii_graphic() || matches!(byte, b'"' | b'<' | b'>' | b'`' | b'%');
+    if !id.bytes().any(needs_encoding) {
+        return Cow::Borrowed(id);
+    }
+    let mut encoded = String::with_capacity(id.len() * 3);
+    for byte in id.bytes() {
+        if needs_encoding(byte) {
+            write!(encoded, "%{byte:02X}").unwrap();
+        } else {
+            encoded.push(char::from(byte));
+        }
+    }
+    Cow::Owned(encoded)
+}
+
+/// Like [`normalize_id`], but spelled according to `style`.
+pub fn normalize_id_with_style(content: &str, style: IdStyle) -> String {
+    let content = replace_emoji(content, style.emoji);
+    let mut id = String::with_capacity(content.len());
+    for ch in content.chars() {
+        if let Some(ascii) = transliterate_latin(ch).filter(|_| style.transliterate) {
+            id.extend(ascii.chars().map(|ch| {
+                if style.lowercase {
+                    ch.to_ascii_lowercase()
+                } else {
+                    ch
+                }
+            }));
+        } else if ch.is_alphanumeric() || ch == '_' || ch == '-' {
+            id.push(if style.lowercase {
+                ch.to_ascii_lowercase()
             } else {
-                None
+                ch
+            });
+        } else if ch.is_whitespace() {
+            id.push(style.separator);
+        }
+    }
+    id
+}
+
+/// Replaces the emoji in `content` according to `style`, together with the
+/// whitespace around them, so that they leave no stray separators in an ID.
+///
+/// A run of emoji between words becomes a single space, or its names
+/// surrounded by spaces, while one at the start or end is removed, or
+/// replaced by its names.
+fn replace_emoji(content: &str, style: EmojiIds) -> Cow<'_, str> {
+    if !content.chars().any(is_emoji) {
+        return Cow::Borrowed(content);
+    }
+    let mut replaced = String::with_capacity(content.len());
+    // Whether the text before the last run of emoji needs a space between it
+    // and the next word.
+    let mut needs_space = false;
+    let mut chars = content.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if !is_emoji(ch) {
+            if needs_space {
+                replaced.push(' ');
+                needs_space = false;
             }
-        })
-        .collect::<String>()
+            replaced.push(ch);
+            continue;
+        }
+        replaced.truncate(replaced.trim_end().len());
+        needs_space = needs_space || !replaced.is_empty();
+        let mut run = vec![ch];
+        while let Some(next) = chars.next_if(|&next| is_emoji(next) || is_emoji_modifier(next)) {
+            run.push(next);
+        }
+        if style == EmojiIds::Shortcode {
+            for name in run.into_iter().filter_map(emoji_name) {
+                if needs_space {
+                    replaced.push(' ');
+                }
+                replaced.push_str(name);
+                needs_space = true;
+            }
+        }
+        // Skip the whitespace after the emoji.
+        while chars.next_if(|next| next.is_whitespace()).is_some() {}
+    }
+    Cow::Owned(replaced)
+}
+
+/// Whether `ch` is a pictographic emoji or symbol.
+fn is_emoji(ch: char) -> bool {
+    matches!(ch,
+        '\u{2300}'..='\u{23FF}'
+        | '\u{2600}'..='\u{27BF}'
+        | '\u{2B00}'..='\u{2BFF}'
+        | '\u{1F000}'..='\u{1FAFF}'
+    )
+}
+
+/// Whether `ch` modifies or joins the emoji before it, like a variation
+/// selector or the zero width joiner.
+fn is_emoji_modifier(ch: char) -> bool {
+    matches!(
+        ch,
+        '\u{200D}' | '\u{20E3}' | '\u{FE0E}' | '\u{FE0F}' | '\u{E0020}'..='\u{E007F}'
+    )
+}
+
+/// The name of a common emoji, based on its GitHub shortcode, or `None` for
+/// any other character.
+fn emoji_name(ch: char) -> Option<&'static str> {
+    Some(match ch {
+        '🚀' => "rocket",
+        '✨' => "sparkles",
+        '🎉' => "tada",
+        '🐛' => "bug",
+        '🔥' => "fire",
+        '⚠' => "warning",
+        '✅' => "white check mark",
+        '❌' => "x",
+        '❓' => "question",
+        '❗' => "exclamation",
+        '📝' => "memo",
+        '📖' => "book",
+        '📚' => "books",
+        '📄' => "page facing up",
+        '📁' => "file folder",
+        '📦' => "package",
+        '📌' => "pushpin",
+        '🔗' => "link",
+        '💡' => "bulb",
+        '🔧' => "wrench",
+        '🔨' => "hammer",
+        '🛠' => "hammer and wrench",
+        '⚙' => "gear",
+        '🔒' => "lock",
+        '🔑' => "key",
+        '🔍' => "mag",
+        '🚧' => "construction",
+        '🚨' => "rotating light",
+        '💥' => "boom",
+        '⚡' => "zap",
+        '⭐' => "star",
+        '🌟' => "star2",
+        '❤' => "heart",
+        '👍' => "thumbsup",
+        '👎' => "thumbsdown",
+        '👋' => "wave",
+        '🙏' => "pray",
+        '🤔' => "thinking",
+        '🎨' => "art",
+        '🎯' => "dart",
+        '🏁' => "checkered flag",
+        '🧪' => "test tube",
+        '🧩' => "jigsaw",
+        '🧹' => "broom",
+        '♻' => "recycle",
+        '🗑' => "wastebasket",
+        '📈' => "chart with upwards trend",
+        '🌐' => "globe with meridians",
+        '💻' => "computer",
+        '📱' => "iphone",
+        '🔔' => "bell",
+        '📣' => "mega",
+        '⏰' => "alarm clock",
+        '📅' => "date",
+        '🦀' => "crab",
+        '🐍' => "snake",
+        _ => return None,
+    })
+}
+
+/// The ASCII spelling of an accented Latin letter, or `None` for any other
+/// character.
+fn transliterate_latin(ch: char) -> Option<&'static str> {
+    Some(match ch {
+        'À'..='Å' | 'Ā' | 'Ă' | 'Ą' => "A",
+        'à'..='å' | 'ā' | 'ă' | 'ą' => "a",
+        'Æ' => "AE",
+        'æ' => "ae",
+        'Ç' | 'Ć' | 'Ĉ' | 'Ċ' | 'Č' => "C",
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => "c",
+        'Ð' | 'Ď' | 'Đ' => "D",
+        'ð' | 'ď' | 'đ' => "d",
+        'È'..='Ë' | 'Ē' | 'Ĕ' | 'Ė' | 'Ę' | 'Ě' => "E",
+        'è'..='ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => "e",
+        'Ĝ' | 'Ğ' | 'Ġ' | 'Ģ' => "G",
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => "g",
+        'Ĥ' | 'Ħ' => "H",
+        'ĥ' | 'ħ' => "h",
+        'Ì'..='Ï' | 'Ĩ' | 'Ī' | 'Ĭ' | 'Į' | 'İ' => "I",
+        'ì'..='ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => "i",
+        'Ĵ' => "J",
+        'ĵ' => "j",
+        'Ķ' => "K",
+        'ķ' => "k",
+        'Ĺ' | 'Ļ' | 'Ľ' | 'Ŀ' | 'Ł' => "L",
+        'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => "l",
+        'Ñ' | 'Ń' | 'Ņ' | 'Ň' => "N",
+        'ñ' | 'ń' | 'ņ' | 'ň' => "n",
+        'Ò'..='Ö' | 'Ø' | 'Ō' | 'Ŏ' | 'Ő' => "O",
+        'ò'..='ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => "o",
+        'Œ' => "OE",
+        'œ' => "oe",
+        'Ŕ' | 'Ŗ' | 'Ř' => "R",
+        'ŕ' | 'ŗ' | 'ř' => "r",
+        'Ś' | 'Ŝ' | 'Ş' | 'Š' => "S",
+        'ś' | 'ŝ' | 'ş' | 'š' => "s",
+        'ß' => "ss",
+        'Ţ' | 'Ť' | 'Ŧ' => "T",
+        'ţ' | 'ť' | 'ŧ' => "t",
+        'Þ' => "TH",
+        'þ' => "th",
+        'Ù'..='Ü' | 'Ũ' | 'Ū' | 'Ŭ' | 'Ů' | 'Ű' | 'Ų' => "U",
+        'ù'..='ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => "u",
+        'Ŵ' => "W",
+        'ŵ' => "w",
+        'Ý' | 'Ÿ' | 'Ŷ' => "Y",
+        'ý' | 'ÿ' | 'ŷ' => "y",
+        'Ź' | 'Ż' | 'Ž' => "Z",
+        'ź' | 'ż' | 'ž' => "z",
+        _ => return None,
+    })
 }
 
 /// Generate an ID for use with anchors which is derived from a "normalised"
@@ -47,6 +321,16 @@ pub fn normalize_id(content: &str) -> String {
 // This function should be made private when the deprecation expires.
 #[deprecated(since = "0.4.16", note = "use unique_id_from_content instead")]
 pub fn id_from_content(content: &str) -> String {
+    id_from_content_with_style(content, IdStyle::default())
+}
+
+/// Like [`id_from_content`], but spelled according to `style`.
+pub(crate) fn id_from_content_with_style(content: &str, style: IdStyle) -> String {
+    normalize_id_with_style(&heading_text(content), style)
+}
+
+/// The text of the HTML `content` of a heading that IDs are generated from.
+fn heading_text(content: &str) -> String {
     let mut content = content.to_string();
 
     // Skip any tags or html-encoded stuff
@@ -58,8 +342,26 @@ pub fn id_from_content(content: &str) -> String {
     }
 
     // Remove spaces and hashes indicating a header
-    let trimmed = content.trim().trim_start_matches('#').trim();
-    normalize_id(trimmed)
+    content.trim().trim_start_matches('#').trim().to_string()
+}
+
+/// The ID that GitHub generates for a heading with the HTML `content`, like
+/// `q--a` for `Q &amp; A`.
+///
+/// Unlike [`normalize_id`], it lowercases all letters, keeps nothing but
+/// letters, digits, `_` and `-`, and turns every space into a `-`, even one
+/// next to a removed character. GitHub appends `-1`, `-2`, and so on to
+/// repeated IDs on a page.
+pub fn github_slug(content: &str) -> String {
+    heading_text(content)
+        .chars()
+        .flat_map(char::to_lowercase)
+        .filter_map(|ch| match ch {
+            ' ' => Some('-'),
+            ch if ch.is_alphanumeric() || ch == '_' || ch == '-' => Some(ch),
+            _ => None,
+        })
+        .collect()
 }
 
 /// Generate an ID for use with anchors which is derived from a "normalised"
@@ -68,28 +370,66 @@ pub fn id_from_content(content: &str) -> String {
 /// Each ID returned will be unique, if the same `id_counter` is provided on
 /// each call.
 pub fn unique_id_from_content(content: &str, id_counter: &mut HashMap<String, usize>) -> String {
-    let id = {
-        #[allow(deprecated)]
-        id_from_content(content)
-    };
+    unique_id_from_content_with_style(content, id_counter, IdStyle::default())
+}
+
+/// Like [`unique_id_from_content`], but spelled according to `style`. Its
+/// separator is also used in front of the counter of duplicate IDs.
+pub fn unique_id_from_content_with_style(
+    content: &str,
+    id_counter: &mut HashMap<String, usize>,
+    style: IdStyle,
+) -> String {
+    let id = id_from_content_with_style(content, style);
 
     // If we have headers with the same normalized id, append an incrementing counter
     let id_count = id_counter.entry(id.clone()).or_insert(0);
     let unique_id = match *id_count {
         0 => id,
-        id_count => format!("{}-{}", id, id_count),
+        id_count => format!("{}{}{}", id, style.separator, id_count),
     };
     *id_count += 1;
     unique_id
 }
 
+/// Counts a heading of `level` in `counters`, which holds the number of
+/// headings seen so far on each level, and returns its section number.
+///
+/// The number is separated by spaces, like `1 2` for the second `<h2>` after
+/// the first `<h1>`, so that it can be put in front of the content an ID is
+/// generated from. Levels above the first heading of the page are left out.
+pub(crate) fn next_section_number(counters: &mut [usize; 6], level: usize) -> String {
+    let level = level.clamp(1, counters.len());
+    counters[level - 1] += 1;
+    counters[level..].fill(0);
+    let number: Vec<_> = counters[..level]
+        .iter()
+        .skip_while(|count| **count == 0)
+        .map(ToString::to_string)
+        .collect();
+    number.join(" ")
+}
+
+/// Creates an `id_counter` for [`unique_id_from_content`] in which the
+/// `reserved` IDs are already in use.
+///
+/// This keeps generated IDs from colliding with fixed IDs of the theme, for
+/// example a heading "Content" gets the ID `content-1` if `content` is reserved.
+pub fn id_counter_with_reserved<I, S>(reserved: I) -> HashMap<String, usize>
+where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    reserved.into_iter().map(|id| (id.into(), 1)).collect()
+}
+
 /// Improve the path to try remove and solve .. token,
 /// This assumes that `a/b/../c` is `a/c`.
 ///
 /// This function ensures a given path ending with '/' will also
 /// end with '/' after normalization.
 /// https://stackoverflow.com/a/68233480
-fn normalize_path<P: AsRef<Path>>(path: P) -> String {
+pub(crate) fn normalize_path<P: AsRef<Path>>(path: P) -> String {
     let ends_with_slash = path.as_ref().to_str().map_or(false, |s| s.ends_with('/'));
     let mut normalized = PathBuf::new();
     for component in path.as_ref().components() {
@@ -111,17 +451,40 @@ fn normalize_path<P: AsRef<Path>>(path: P) -> String {
     normalized.to_str().unwrap().replace("\\", "/").to_string()
 }
 
-/// Converts a relative URL path to a reference ID for the print page.
-fn normalize_print_page_id(mut path: String) -> String {
-    path = path
-        .replace("/", "-")
-        .replace(".html#", "-")
-        .replace("#", "-")
-        .to_ascii_lowercase();
-    if path.ends_with(".html") {
-        path.truncate(path.len() - 5);
+/// Converts a relative URL path, possibly with a fragment, to a reference ID
+/// for the print page.
+///
+/// Directory separators are kept and the fragment is joined with a `:`, see
+/// [`print_element_id`]. Since `%` and `:` in the path are percent-encoded,
+/// paths like `a/b.html`, `a--b.html`, `a.html#b` and `a-b.html` all get
+/// different IDs.
+pub(crate) fn normalize_print_page_id(path: String) -> String {
+    let path = path.to_ascii_lowercase();
+    let (page, fragment) = match path.split_once('#') {
+        Some((page, fragment)) => (page, Some(fragment)),
+        None => (path.as_str(), None),
+    };
+    let page = page.strip_suffix(".html").unwrap_or(page);
+    let page = page.replace('%', "%25").replace(':', "%3a");
+    match fragment {
+        Some(fragment) => print_element_id(&page, fragment),
+        None => page,
     }
-    path
+}
+
+/// The ID on the print page of the element `id` of the page `page_id`, from
+/// [`normalize_print_page_id`], like `intro:setup`.
+pub(crate) fn print_element_id(page_id: &str, id: &str) -> String {
+    format!("{page_id}:{id}")
+}
+
+// Schemes are case-insensitive, so `MAILTO:` is as external as `mailto:`.
+static SCHEME_LINK: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?i)^[a-z][a-z0-9+.-]*:").unwrap());
+
+/// Whether `dest` points outside of the book, either with a scheme like
+/// `https:` or as a protocol-relative URL like `//example.com/`.
+pub(crate) fn is_external_link(dest: &str) -> bool {
+    SCHEME_LINK.is_match(dest) || dest.starts_with("//")
 }
 
 /// Fix links to the correct location.
@@ -134,8 +497,8 @@ fn adjust_links<'a>(
     event: Event<'a>,
     path: Option<&Path>,
     redirects: &HashMap<String, String>,
+    options: &Markdown,
 ) -> Event<'a> {
-    static SCHEME_LINK: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-z][a-z0-9+.-]*:").unwrap());
     static HTML_MD_LINK: Lazy<Regex> =
         Lazy::new(|| Regex::new(r"(?P<link>.*)\.(html|md)(?P<anchor>#.*)?").unwrap());
 
@@ -225,7 +588,7 @@ fn adjust_links<'a>(
     /// Fix resource links like img to the correct location.
     fn fix_resource_links<'a>(dest: CowStr<'a>, path: Option<&Path>) -> CowStr<'a> {
         // Don't modify links with schemes like `https`.
-        if SCHEME_LINK.is_match(&dest) {
+        if is_external_link(&dest) {
             return dest;
         }
 
@@ -239,12 +602,29 @@ fn adjust_links<'a>(
         dest: CowStr<'a>,
         path: Option<&Path>,
         redirects: &HashMap<String, String>,
+        options: &Markdown,
         link_type: LinkType,
     ) -> CowStr<'a> {
         if link_type == LinkType::Email {
             return dest;
         }
-        fix_a_links(dest, path, redirects)
+        fix_a_links(dest, path, redirects, options)
+    }
+
+    /// Points a link to a directory, or to its `README.md` or `index.md`, at
+    /// the index page of that directory.
+    fn index_page_link(dest: &str, index_page: &str) -> Option<String> {
+        let (link, anchor) = dest.split_at(dest.find('#').unwrap_or(dest.len()));
+        let dir = if link.ends_with('/') {
+            link
+        } else {
+            let (dir, file) = link.split_at(link.rfind('/').map_or(0, |i| i + 1));
+            if !file.eq_ignore_ascii_case("readme.md") && file != "index.md" {
+                return None;
+            }
+            dir
+        };
+        Some(format!("{dir}{index_page}.html{anchor}"))
     }
 
     /// Adjust markdown file to correct point in the html file.
@@ -252,8 +632,9 @@ fn adjust_links<'a>(
         dest: CowStr<'a>,
         path: Option<&Path>,
         redirects: &HashMap<String, String>,
+        options: &Markdown,
     ) -> CowStr<'a> {
-        if dest.starts_with('#') {
+        if let Some(fragment) = dest.strip_prefix('#') {
             // Fragment-only link.
             return match path {
                 Some(path) => {
@@ -261,28 +642,46 @@ fn adjust_links<'a>(
                     if base.ends_with(".md") {
                         base.truncate(base.len() - 3);
                     }
-                    format!(
-                        "#{}{}",
-                        normalize_print_page_id(normalize_path(base)),
-                        dest.replace("#", "-")
-                    )
-                    .into()
+                    let page_id = normalize_print_page_id(normalize_path(base));
+                    format!("#{}", print_element_id(&page_id, fragment)).into()
                 }
                 None => dest,
             };
         }
 
         // Don't modify links with schemes like `https`.
-        if SCHEME_LINK.is_match(&dest) {
+        if is_external_link(&dest) {
             return dest;
         }
 
+        let dest = match index_page_link(&dest, &options.index_page) {
+            Some(link) => CowStr::from(link),
+            None => dest,
+        };
+
         // This is a relative link, adjust it as necessary.
         let mut fixed_link = add_base(path);
 
         if let Some(caps) = HTML_MD_LINK.captures(&dest) {
-            fixed_link.push_str(&caps["link"]);
-            fixed_link.push_str(".html");
+            let link = &caps["link"];
+            // Links on the print page become anchors, which need the `.html`.
+            if path.is_none() && options.internal_link_style == InternalLinkStyle::TrailingSlash {
+                // An index page is served as its directory.
+                match link
+                    .strip_suffix(options.index_page.as_str())
+                    .filter(|dir| dir.is_empty() || dir.ends_with('/'))
+                {
+                    Some("") => fixed_link.push_str("./"),
+                    Some(dir) => fixed_link.push_str(dir),
+                    None => {
+                        fixed_link.push_str(link);
+                        fixed_link.push('/');
+                    }
+                }
+            } else {
+                fixed_link.push_str(link);
+                fixed_link.push_str(".html");
+            }
             if let Some(anchor) = caps.name("anchor") {
                 fixed_link.push_str(anchor.as_str());
             }
@@ -308,6 +707,7 @@ fn adjust_links<'a>(
         html: CowStr<'a>,
         path: Option<&Path>,
         redirects: &HashMap<String, String>,
+        options: &Markdown,
     ) -> CowStr<'a> {
         // This is a terrible hack, but should be reasonably reliable. Nobody
         // should ever parse a tag with a regex. However, there isn't anything
@@ -342,10 +742,9 @@ fn adjust_links<'a>(
                             if base.ends_with(".md") {
                                 base.truncate(base.len() - 3);
                             }
-                            format!(
-                                "{}-{}",
-                                normalize_print_page_id(normalize_path(base)),
-                                origin_name.to_string()
+                            print_element_id(
+                                &normalize_print_page_id(normalize_path(base)),
+                                origin_name,
                             )
                         }
                         None => origin_name.to_string(),
@@ -355,7 +754,7 @@ fn adjust_links<'a>(
 
         A_LINK
             .replace_all(&a_name_fixed_html, |caps: &regex::Captures<'_>| {
-                let fixed = fix_a_links(caps[2].into(), path, &redirects);
+                let fixed = fix_a_links(caps[2].into(), path, &redirects, options);
                 format!("{}{}\"", &caps[1], fixed)
             })
             .into_owned()
@@ -370,7 +769,7 @@ fn adjust_links<'a>(
             id,
         }) => Event::Start(Tag::Link {
             link_type,
-            dest_url: fix_a_links_with_type(dest_url, path, redirects, link_type),
+            dest_url: fix_a_links_with_type(dest_url, path, redirects, options, link_type),
             title,
             id,
         }),
@@ -385,8 +784,8 @@ fn adjust_links<'a>(
             title,
             id,
         }),
-        Event::Html(html) => Event::Html(fix_html(html, path, redirects)),
-        Event::InlineHtml(html) => Event::InlineHtml(fix_html(html, path, redirects)),
+        Event::Html(html) => Event::Html(fix_html(html, path, redirects, options)),
+        Event::InlineHtml(html) => Event::InlineHtml(fix_html(html, path, redirects, options)),
         _ => event,
     }
 }
@@ -402,7 +801,78 @@ pub fn render_markdown_with_path(
     smart_punctuation: bool,
     path: Option<&Path>,
 ) -> String {
-    render_markdown_with_path_and_redirects(text, smart_punctuation, path, &HashMap::new())
+    render_markdown_with_path_and_redirects(
+        text,
+        smart_punctuation,
+        path,
+        &HashMap::new(),
+        &Markdown::default(),
+        None,
+    )
+}
+
+/// Renders markdown to HTML, using the given `[output.html.markdown]` options.
+pub fn render_markdown_with_options(
+    text: &str,
+    smart_punctuation: bool,
+    options: &Markdown,
+) -> String {
+    render_markdown_with_path_and_redirects(
+        text,
+        smart_punctuation,
+        None,
+        &HashMap::new(),
+        options,
+        None,
+    )
+}
+
+/// Renders markdown to HTML like [`render_markdown_with_options`], passing
+/// every event through `transform` on the way, which replaces it with any
+/// number of events, including none.
+///
+/// The transform sees the events in document order, after mdBook's own
+/// adjustments of single events, like resolving links to `.md` files and
+/// wrapping tables, and before the passes over the whole page, like custom
+/// quotes, figures and the rewriting of footnotes. The text of inline
+/// footnotes is already turned into footnote references and definitions.
+///
+/// Since the events borrow from text that may only live during rendering,
+/// the transform has to accept events of any lifetime. Pass a closure
+/// directly, as below, or a function, rather than a closure stored in a
+/// variable first.
+///
+/// ```rust
+/// # use mdbook::config::Markdown;
+/// # use mdbook::utils::render_markdown_with_transform;
+/// use pulldown_cmark::Event;
+///
+/// let html = render_markdown_with_transform("Hi!", false, &Markdown::default(), |event| {
+///     match event {
+///         Event::Text(text) => vec![Event::Text(text.replace('!', "?").into())],
+///         event => vec![event],
+///     }
+/// });
+/// assert_eq!(html, "<p>Hi?</p>\n");
+/// ```
+pub fn render_markdown_with_transform<F>(
+    text: &str,
+    smart_punctuation: bool,
+    options: &Markdown,
+    mut transform: F,
+) -> String
+where
+    F: FnMut(Event<'_>) -> Vec<Event<'_>>,
+{
+    render_markdown_impl(
+        text,
+        smart_punctuation,
+        None,
+        &HashMap::new(),
+        options,
+        None,
+        Some(&mut transform),
+    )
 }
 
 pub fn new_cmark_parser(text: &str, smart_punctuation: bool) -> Parser<'_> {
@@ -427,139 +897,3720 @@ pub fn new_cmark_parser(text: &str, smart_punctuation: bool) -> Parser<'_> {
 ///
 /// `redirects` is also only for the print page. It's for adjusting links to
 /// a redirected location to go to the correct spot on the `print.html` page.
+///
+/// `options` are the `[output.html.markdown]` settings controlling optional
+/// rendering behavior.
+///
+/// `endnotes` is also only for the print page. If set, the footnotes are
+/// collected there to be listed at the end of the book, instead of being
+/// rendered on the page.
 pub(crate) fn render_markdown_with_path_and_redirects(
     text: &str,
     smart_punctuation: bool,
     path: Option<&Path>,
     redirects: &HashMap<String, String>,
+    options: &Markdown,
+    endnotes: Option<&mut Endnotes>,
+) -> String {
+    render_markdown_impl(
+        text,
+        smart_punctuation,
+        path,
+        redirects,
+        options,
+        endnotes,
+        None,
+    )
+}
+
+/// A transformation of the events of a page, see
+/// [`render_markdown_with_transform`].
+type EventTransform<'t> = dyn FnMut(Event<'_>) -> Vec<Event<'_>> + 't;
+
+fn render_markdown_impl(
+    text: &str,
+    smart_punctuation: bool,
+    path: Option<&Path>,
+    redirects: &HashMap<String, String>,
+    options: &Markdown,
+    endnotes: Option<&mut Endnotes>,
+    transform: Option<&mut EventTransform<'_>>,
 ) -> String {
     let mut s = String::with_capacity(text.len() * 3 / 2);
-    let p = new_cmark_parser(text, smart_punctuation);
+    let text = if options.inline_footnotes {
+        expand_inline_footnotes(text)
+    } else {
+        Cow::Borrowed(text)
+    };
+    // Custom quotes replace the ones of the parser's smart punctuation.
+    let p = new_cmark_parser(&text, smart_punctuation && options.quotes.is_none());
+    // Whether each currently open strikethrough was written with single
+    // tildes and is rendered as subscript instead.
+    let mut subscripts = Vec::new();
+    let mut sanitizer = options.sanitize_html.then(|| {
+        sanitize::HtmlSanitizer::new(
+            &options.sanitize_html_tags,
+            &options.sanitize_html_attributes,
+        )
+    });
     let events = p
+        .into_offset_iter()
+        .flat_map(|(event, range)| {
+            let events = match &mut sanitizer {
+                Some(sanitizer) => sanitizer.sanitize(event),
+                None => vec![event],
+            };
+            events.into_iter().map(move |event| (event, range.clone()))
+        })
+        .map(|(event, range)| match event {
+            Event::Start(Tag::Strikethrough) if options.subscript_superscript => {
+                let single = !text[range].starts_with("~~");
+                subscripts.push(single);
+                if single {
+                    Event::InlineHtml("<sub>".into())
+                } else {
+                    event
+                }
+            }
+            Event::End(TagEnd::Strikethrough) if subscripts.pop() == Some(true) => {
+                Event::InlineHtml("</sub>".into())
+            }
+            event => event,
+        })
         .map(clean_codeblock_headers)
-        .map(|event| adjust_links(event, path, &redirects))
+        .map(|event| adjust_links(event, path, &redirects, options))
+        .map(|event| convert_comment_directives(event, &options.comment_directives))
         .flat_map(|event| {
-            let (a, b) = wrap_tables(event);
+            let (a, b) = wrap_tables(event, options);
             a.into_iter().chain(b)
         });
 
-    html::push_html(&mut s, events);
+    let mut events: Vec<_> = match transform {
+        Some(transform) => events.flat_map(transform).collect(),
+        None => events.collect(),
+    };
+    if let Some(quotes) = &options.quotes {
+        events = render_quotes(events, quotes);
+    }
+    if options.single_h1 {
+        events = demote_extra_h1(events, options.page_title_is_h1);
+    }
+    if options.image_galleries {
+        events = wrap_image_galleries(events);
+    }
+    if options.captioned_figures {
+        events = wrap_captioned_figures(events);
+    }
+    if options.paragraph_ids || options.sequential_paragraph_ids {
+        events = add_paragraph_ids(events, options);
+    }
+    if options.collapse_footnote_references {
+        events = collapse_footnote_references(events);
+    }
+    if let Some(endnotes) = endnotes {
+        events = collect_endnotes(events, endnotes, options.footnote_style);
+    } else if options.sidenotes {
+        events = render_sidenotes(events, options.footnote_style);
+    } else if options.footnote_sections {
+        events = render_footnote_sections(events, options.footnote_style);
+    } else if options.footnote_style != FootnoteStyle::Superscript {
+        events = render_footnote_references(events, options.footnote_style);
+    }
+    if let Some(template) = &options.issue_link_template {
+        events = link_issue_references(events, template);
+    }
+    if let Some(schemes) = &options.autolink_schemes {
+        events = filter_autolink_schemes(events, schemes);
+    }
+    if options.external_links_new_tab
+        || !options.external_link_rel.is_empty()
+        || options.hreflang_links
+    {
+        events = add_link_attributes(events, options);
+    }
+    if options.keyboard_keys {
+        events = render_keyboard_keys(events);
+    }
+    if options.highlights {
+        events = render_highlights(events);
+    }
+    if options.subscript_superscript {
+        events = render_subscript_superscript(events);
+    }
+    if options.time_elements {
+        events = render_time_elements(events);
+    }
+    if options.strikethrough_element == StrikethroughElement::S {
+        events = render_strikethrough_as_s(events);
+    }
+    if options.abbreviations {
+        events = render_abbreviations(events);
+    }
+    if options.interactive_tasklists {
+        events = render_interactive_tasklists(events);
+    }
+    if options.details_blockquotes {
+        events = render_details_blockquotes(events);
+    }
+    if options.detect_code_languages {
+        events = detect_code_languages(events, options.detect_code_languages_confidence);
+    }
+    if options.empty_code_blocks != EmptyCodeBlocks::Keep {
+        events = handle_empty_code_blocks(
+            events,
+            options.empty_code_blocks,
+            &options.empty_code_block_placeholder,
+        );
+    }
+    if let Some(class) = &options.thematic_break_class {
+        events = add_thematic_break_class(events, class);
+    }
+    if options.code_copy_buttons {
+        events = add_code_copy_buttons(events, &options.code_copy_buttons_exclude);
+    }
+    if options.code_class_prefix != "language-" {
+        events = prefix_code_block_languages(events, &options.code_class_prefix);
+    }
+
+    html::push_html(&mut s, events.into_iter());
+    if let Some(max_width) = &options.max_image_width {
+        s = limit_image_width(&s, max_width);
+    }
     s
 }
 
-/// Wraps tables in a `.table-wrapper` class to apply overflow-x rules to.
-fn wrap_tables(event: Event<'_>) -> (Option<Event<'_>>, Option<Event<'_>>) {
-    match event {
-        Event::Start(Tag::Table(_)) => (
-            Some(Event::Html(r#"<div class="table-wrapper">"#.into())),
-            Some(event),
-        ),
-        Event::End(TagEnd::Table) => (Some(event), Some(Event::Html(r#"</div>"#.into()))),
-        _ => (Some(event), None),
-    }
+/// Adds an inline style limiting the width of `<img>` elements to
+/// `max_width` to the images in `html`, written in markdown or raw HTML.
+///
+/// Images that already have a `width`, `height` or `style` are left alone.
+fn limit_image_width(html: &str, max_width: &str) -> String {
+    static IMG: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(?s)(<img\s.*?)(\s*/?>)"#).unwrap());
+    static SIZED: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r#"(?i)\s(?:width|height|style)\s*="#).unwrap());
+
+    let style = format!(
+        r#" style="max-width:{};height:auto""#,
+        special_escape(max_width)
+    );
+    IMG.replace_all(html, |caps: &regex::Captures<'_>| {
+        if SIZED.is_match(&caps[1]) {
+            caps[0].to_string()
+        } else {
+            format!("{}{style}{}", &caps[1], &caps[2])
+        }
+    })
+    .into_owned()
 }
 
-fn clean_codeblock_headers(event: Event<'_>) -> Event<'_> {
-    match event {
-        Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref info))) => {
-            let info: String = info
-                .chars()
-                .map(|x| match x {
-                    ' ' | '\t' => ',',
-                    _ => x,
-                })
-                .filter(|ch| !ch.is_whitespace())
+/// Expands key sequences like `[[Ctrl+C]]` in text into
+/// `<kbd>Ctrl</kbd>+<kbd>C</kbd>`.
+///
+/// Code spans and code blocks are left untouched, and a `[[` that isn't
+/// followed by a key sequence and `]]` is kept as it is.
+fn render_keyboard_keys(events: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    static KEYS: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"\[\[([^\[\]+\s]+(?:\+[^\[\]+\s]+)*)\]\]").unwrap());
+
+    replace_text_matches(
+        events,
+        &KEYS,
+        |_| false,
+        |_, caps| {
+            let keys: Vec<_> = caps[1]
+                .split('+')
+                .map(|key| format!("<kbd>{}</kbd>", special_escape(key)))
                 .collect();
+            Some(vec![Event::InlineHtml(keys.join("+").into())])
+        },
+    )
+}
 
-            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(CowStr::from(info))))
+/// Replaces straight quotes in text with the given opening and closing
+/// primary and secondary quotes, in this order.
+///
+/// Quotes nested in a primary quote use the secondary quotes, and the other
+/// way around. A `'` that doesn't open or close a quote, like in `don't`, is
+/// rendered as an apostrophe. Code is left untouched.
+fn render_quotes<'a>(events: Vec<Event<'a>>, quotes: &[String; 4]) -> Vec<Event<'a>> {
+    // The straight quotes that are currently open, innermost last.
+    let mut open: Vec<char> = Vec::new();
+    // The character before the current position in the block.
+    let mut previous: Option<char> = None;
+    let mut in_code_block = false;
+    let mut result = Vec::with_capacity(events.len());
+    for event in join_adjacent_text(events) {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(TagEnd::CodeBlock) => in_code_block = false,
+            Event::Start(Tag::Paragraph | Tag::Heading { .. } | Tag::Item | Tag::TableCell)
+            | Event::End(
+                TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::Item | TagEnd::TableCell,
+            ) => {
+                open.clear();
+                previous = None;
+            }
+            Event::SoftBreak | Event::HardBreak => previous = Some(' '),
+            Event::Code(_) | Event::InlineHtml(_) => previous = Some('x'),
+            Event::Text(text) if !in_code_block => {
+                let mut replaced = String::with_capacity(text.len());
+                let mut chars = text.chars().peekable();
+                while let Some(ch) = chars.next() {
+                    if ch != '"' && ch != '\'' {
+                        replaced.push(ch);
+                        previous = Some(ch);
+                        continue;
+                    }
+                    let next = chars.peek().copied();
+                    let opens = previous
+                        .map_or(true, |p| p.is_whitespace() || "([{-–—/".contains(p))
+                        && next.is_some_and(|n| !n.is_whitespace());
+                    // Quotes alternate between primary and secondary when nested.
+                    let level = |depth: usize| if depth % 2 == 0 { 0 } else { 2 };
+                    if ch == '\''
+                        && previous.is_some_and(char::is_alphanumeric)
+                        && next.is_some_and(char::is_alphanumeric)
+                    {
+                        replaced.push('’');
+                    } else if opens {
+                        replaced.push_str(&quotes[level(open.len())]);
+                        open.push(ch);
+                    } else if let Some(depth) = open.iter().rposition(|&quote| quote == ch) {
+                        open.truncate(depth);
+                        replaced.push_str(&quotes[level(depth) + 1]);
+                    } else if ch == '"' {
+                        replaced.push_str(&quotes[1]);
+                    } else {
+                        replaced.push('’');
+                    }
+                    previous = Some(ch);
+                }
+                result.push(Event::Text(replaced.into()));
+                continue;
+            }
+            _ => {}
         }
-        _ => event,
+        result.push(event);
     }
+    result
 }
 
-/// Prints a "backtrace" of some `Error`.
-pub fn log_backtrace(e: &Error) {
-    error!("Error: {}", e);
+/// Renders text written as `==text==` as `<mark>text</mark>`.
+///
+/// Only balanced markers within a single run of text are converted, so a
+/// highlight can't span a link, emphasis, or code span. Code is left
+/// untouched, and a `==` without a partner is kept as it is.
+fn render_highlights(events: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    static HIGHLIGHT: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"==([^=\s](?:[^=]*[^=\s])?)==").unwrap());
 
-    for cause in e.chain().skip(1) {
-        error!("\tCaused By: {}", cause);
-    }
+    replace_text_matches(
+        events,
+        &HIGHLIGHT,
+        |_| false,
+        |_, caps| {
+            Some(vec![
+                Event::InlineHtml("<mark>".into()),
+                Event::Text(caps[1].to_string().into()),
+                Event::InlineHtml("</mark>".into()),
+            ])
+        },
+    )
 }
 
-pub(crate) fn bracket_escape(mut s: &str) -> String {
-    let mut escaped = String::with_capacity(s.len());
-    let needs_escape: &[char] = &['<', '>'];
-    while let Some(next) = s.find(needs_escape) {
-        escaped.push_str(&s[..next]);
-        match s.as_bytes()[next] {
-            b'<' => escaped.push_str("&lt;"),
-            b'>' => escaped.push_str("&gt;"),
-            _ => unreachable!(),
-        }
-        s = &s[next + 1..];
+/// Renders text written as `~text~` as `<sub>text</sub>` and `^text^` as
+/// `<sup>text</sup>`.
+///
+/// Only markers around text without whitespace within a single run of text
+/// are converted, and markers next to another of the same kind, like `~~`,
+/// are kept. A `^[` doesn't start superscript, so that inline footnotes are
+/// left alone. Single tildes that the parser reads as strikethrough are
+/// rendered as subscript while parsing.
+fn render_subscript_superscript(events: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    static MARKED: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"~([^~\s]+)~|\^([^\^\s\[][^\^\s]*)\^").unwrap());
+
+    fn is_marked(text: &str, whole: regex::Match<'_>) -> bool {
+        let marker = text[whole.start()..].chars().next().unwrap();
+        !text[..whole.start()].ends_with(marker) && !text[whole.end()..].starts_with(marker)
     }
-    escaped.push_str(s);
-    escaped
+
+    replace_text_matches(
+        events,
+        &MARKED,
+        |_| false,
+        |text, caps| {
+            if !is_marked(text, caps.get(0).unwrap()) {
+                return None;
+            }
+            let (tag, content) = match caps.get(1) {
+                Some(content) => ("sub", content.as_str()),
+                None => ("sup", &caps[2]),
+            };
+            Some(vec![
+                Event::InlineHtml(format!("<{tag}>").into()),
+                Event::Text(content.to_string().into()),
+                Event::InlineHtml(format!("</{tag}>").into()),
+            ])
+        },
+    )
 }
 
-#[cfg(test)]
-mod tests {
-    use super::bracket_escape;
+/// Renders struck through text as `<s>` instead of `<del>`.
+fn render_strikethrough_as_s(events: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    events
+        .into_iter()
+        .map(|event| match event {
+            Event::Start(Tag::Strikethrough) => Event::InlineHtml("<s>".into()),
+            Event::End(TagEnd::Strikethrough) => Event::InlineHtml("</s>".into()),
+            event => event,
+        })
+        .collect()
+}
 
-    mod render_markdown {
-        use super::super::render_markdown;
+/// Wraps ISO 8601 dates like `2024-01-15` in text in
+/// `<time datetime="2024-01-15">` elements.
+///
+/// Only valid dates are wrapped, and only if they aren't part of a longer
+/// word or number, like `2024-01-15-1`. Code is left untouched.
+fn render_time_elements(events: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    static DATE: Lazy<Regex> = Lazy::new(|| Regex::new(r"(\d{4})-(\d{2})-(\d{2})").unwrap());
 
-        #[test]
-        fn preserves_external_links() {
-            assert_eq!(
-                render_markdown("[example](https://www.rust-lang.org/)", false),
-                "<p><a href=\"https://www.rust-lang.org/\">example</a></p>\n"
-            );
-        }
+    fn is_date(text: &str, caps: &regex::Captures<'_>) -> bool {
+        let whole = caps.get(0).unwrap();
+        let is_word_char = |c: char| c.is_alphanumeric() || c == '-' || c == '_';
+        let standalone = !text[..whole.start()].ends_with(is_word_char)
+            && !text[whole.end()..].starts_with(is_word_char);
+        let (year, month, day) = (caps[1].parse(), caps[2].parse(), caps[3].parse());
+        standalone
+            && matches!((year, month, day), (Ok(year), Ok(month), Ok(day))
+                if chrono::NaiveDate::from_ymd_opt(year, month, day).is_some())
+    }
 
-        #[test]
-        fn it_can_adjust_markdown_links() {
-            assert_eq!(
-                render_markdown("[example](example.md)", false),
-                "<p><a href=\"example.html\">example</a></p>\n"
-            );
-            assert_eq!(
-                render_markdown("[example_anchor](example.md#anchor)", false),
-                "<p><a href=\"example.html#anchor\">example_anchor</a></p>\n"
-            );
+    replace_text_matches(
+        events,
+        &DATE,
+        |_| false,
+        |text, caps| {
+            is_date(text, caps).then(|| {
+                vec![Event::InlineHtml(
+                    format!(r#"<time datetime="{0}">{0}</time>"#, &caps[0]).into(),
+                )]
+            })
+        },
+    )
+}
 
-            // this anchor contains 'md' inside of it
-            assert_eq!(
-                render_markdown("[phantom data](foo.html#phantomdata)", false),
-                "<p><a href=\"foo.html#phantomdata\">phantom data</a></p>\n"
-            );
-        }
+/// Links references to issues like `#123` to the URL `template`, with `{n}`
+/// replaced by the issue number.
+///
+/// References in code, links, and headings, whose content is already a
+/// link, are left as they are.
+fn link_issue_references<'a>(events: Vec<Event<'a>>, template: &str) -> Vec<Event<'a>> {
+    static ISSUE: Lazy<Regex> = Lazy::new(|| Regex::new(r"#(\d+)").unwrap());
 
-        #[test]
-        fn it_can_wrap_tables() {
-            let src = r#"
-| Original        | Punycode        | Punycode + Encoding |
-|-----------------|-----------------|---------------------|
-| føø             | f-5gaa          | f_5gaa              |
-"#;
-            let out = r#"
-<div class="table-wrapper"><table><thead><tr><th>Original</th><th>Punycode</th><th>Punycode + Encoding</th></tr></thead><tbody>
-<tr><td>føø</td><td>f-5gaa</td><td>f_5gaa</td></tr>
-</tbody></table>
-</div>
-"#.trim();
-            assert_eq!(render_markdown(src, false), out);
-        }
+    fn is_reference(text: &str, whole: regex::Match<'_>) -> bool {
+        let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+        !text[..whole.start()].ends_with(is_word_char)
+            && !text[whole.end()..].starts_with(is_word_char)
+    }
 
-        #[test]
-        fn it_can_keep_quotes_straight() {
-            assert_eq!(render_markdown("'one'", false), "<p>'one'</p>\n");
-        }
+    replace_text_matches(
+        events,
+        &ISSUE,
+        |tag| {
+            matches!(
+                tag,
+                Tag::Link { .. } | Tag::Image { .. } | Tag::Heading { .. }
+            )
+        },
+        |text, caps| {
+            let whole = caps.get(0).unwrap();
+            is_reference(text, whole).then(|| {
+                vec![
+                    Event::Start(Tag::Link {
+                        link_type: LinkType::Inline,
+                        dest_url: template.replace("{n}", &caps[1]).into(),
+                        title: "".into(),
+                        id: "".into(),
+                    }),
+                    Event::Text(whole.as_str().to_string().into()),
+                    Event::End(TagEnd::Link),
+                ]
+            })
+        },
+    )
+}
 
-        #[test]
-        fn it_can_make_quotes_curly_except_when_they_are_in_code() {
-            let input = r#"
-'one'
+/// Gives paragraphs that end with `{#id}` that ID with `paragraph-ids`,
+/// removing the marker from the text, and numbers the other paragraphs
+/// `p-1`, `p-2`, and so on with `sequential-paragraph-ids`.
+///
+/// The IDs of markers are spelled like generated heading IDs and made unique
+/// on the page, without colliding with the `reserved-ids`. Paragraphs with a
+/// marker are counted by the sequence as well, so adding a marker doesn't
+/// change the IDs of the paragraphs after it.
+fn add_paragraph_ids<'a>(events: Vec<Event<'a>>, options: &Markdown) -> Vec<Event<'a>> {
+    static ID_MARKER: Lazy<Regex> = Lazy::new(|| Regex::new(r"\s*\{#([^\s{}]+)\}\s*$").unwrap());
+
+    let mut id_counter = id_counter_with_reserved(&options.reserved_ids);
+    let mut out = Vec::with_capacity(events.len());
+    let mut paragraph_start = None;
+    let mut paragraph_number = None;
+    let mut numbered = 0;
+    // The level and text of the heading being read, and the level of the
+    // heading of the section that paragraphs are numbered in, if any.
+    let mut heading: Option<(HeadingLevel, Option<String>, String)> = None;
+    let mut section = None;
+    for event in join_adjacent_text(events) {
+        match event {
+            Event::Start(Tag::Heading { level, ref id, .. }) => {
+                if section.is_some_and(|section| level <= section) {
+                    section = None;
+                }
+                heading = Some((level, id.as_deref().map(str::to_string), String::new()));
+                out.push(event);
+            }
+            Event::Text(ref text) | Event::Code(ref text) if heading.is_some() => {
+                if let Some((_, _, title)) = &mut heading {
+                    title.push_str(text);
+                }
+                out.push(event);
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let (Some((level, id, title)), Some(target)) =
+                    (heading.take(), &options.sequential_paragraph_ids_section)
+                {
+                    if id.as_deref() == Some(target.as_str())
+                        || collapse_whitespace(title.trim()).to_lowercase()
+                            == collapse_whitespace(target.trim()).to_lowercase()
+                    {
+                        section = Some(level);
+                    }
+                }
+                out.push(event);
+            }
+            Event::Start(Tag::Paragraph) => {
+                paragraph_start = Some(out.len());
+                paragraph_number = None;
+                if options.sequential_paragraph_ids
+                    && (options.sequential_paragraph_ids_section.is_none() || section.is_some())
+                {
+                    numbered += 1;
+                    paragraph_number = Some(numbered);
+                }
+                out.push(event);
+            }
+            Event::End(TagEnd::Paragraph) => {
+                let Some(start) = paragraph_start.take() else {
+                    out.push(event);
+                    continue;
+                };
+                if let Some(Event::Text(text)) = out.last().filter(|_| options.paragraph_ids) {
+                    if let Some(caps) = ID_MARKER.captures(text) {
+                        let visible = text[..caps.get(0).unwrap().start()].to_string();
+                        let id = unique_id_from_content_with_style(
+                            &caps[1],
+                            &mut id_counter,
+                            options.into(),
+                        );
+                        out.pop();
+                        if !visible.is_empty() {
+                            out.push(Event::Text(visible.into()));
+                        } else if matches!(out.last(), Some(Event::SoftBreak)) {
+                            // The marker was on a line of its own.
+                            out.pop();
+                        }
+                        out[start] =
+                            Event::Html(format!(r#"<p id="{}">"#, special_escape(&id)).into());
+                        out.push(Event::Html("</p>\n".into()));
+                        continue;
+                    }
+                }
+                if let Some(number) = paragraph_number {
+                    out[start] = Event::Html(format!(r#"<p id="p-{number}">"#).into());
+                    out.push(Event::Html("</p>\n".into()));
+                    continue;
+                }
+                out.push(event);
+            }
+            event => out.push(event),
+        }
+    }
+    out
+}
+
+/// Replaces the matches of `re` in text with the events that `replace`
+/// returns for them, given the whole text and the match, or keeps a match as
+/// it is if it returns `None`.
+///
+/// Text in code blocks, and in the elements whose start tag `skip` returns
+/// `true` for, is left untouched. Since the parser splits text at some
+/// punctuation, adjacent text is joined first.
+fn replace_text_matches<'a>(
+    events: Vec<Event<'a>>,
+    re: &Regex,
+    skip: impl Fn(&Tag<'_>) -> bool,
+    mut replace: impl FnMut(&str, &regex::Captures<'_>) -> Option<Vec<Event<'a>>>,
+) -> Vec<Event<'a>> {
+    let joined = join_adjacent_text(events);
+
+    // The ends of the skipped elements that are open.
+    let mut skipping = Vec::new();
+    let mut events = Vec::with_capacity(joined.len());
+    for event in joined {
+        match event {
+            Event::Start(ref tag) if matches!(tag, Tag::CodeBlock(_)) || skip(tag) => {
+                skipping.push(tag.to_end());
+            }
+            Event::End(ref end) if skipping.last() == Some(end) => {
+                skipping.pop();
+            }
+            Event::Text(ref text) if skipping.is_empty() && re.is_match(text) => {
+                let mut last = 0;
+                for caps in re.captures_iter(text) {
+                    let whole = caps.get(0).unwrap();
+                    let Some(replacement) = replace(text, &caps) else {
+                        continue;
+                    };
+                    if whole.start() > last {
+                        events.push(Event::Text(text[last..whole.start()].to_string().into()));
+                    }
+                    events.extend(replacement);
+                    last = whole.end();
+                }
+                if last < text.len() {
+                    events.push(Event::Text(text[last..].to_string().into()));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        events.push(event);
+    }
+    events
+}
+
+/// Merges adjacent text events into a single one.
+fn join_adjacent_text(events: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    let mut joined: Vec<Event<'_>> = Vec::with_capacity(events.len());
+    for event in events {
+        match (joined.last_mut(), event) {
+            (Some(Event::Text(previous)), Event::Text(text)) => {
+                *previous = format!("{}{}", previous, text).into();
+            }
+            (_, event) => joined.push(event),
+        }
+    }
+    joined
+}
+
+/// Renders abbreviations defined with `*[HTML]: HyperText Markup Language`
+/// as `<abbr title="HyperText Markup Language">HTML</abbr>`.
+///
+/// Paragraphs that only hold definitions are removed. Abbreviations are only
+/// matched as whole words, and code, links, and images are left untouched.
+fn render_abbreviations(events: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    static DEFINITION: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"^\*\[([^\[\]]+)\]:[ \t]*(.*)$").unwrap());
+
+    // The parser splits text at brackets, so join adjacent text first.
+    let mut events = join_adjacent_text(events).into_iter();
+
+    // First collect the definitions, dropping the paragraphs that hold them.
+    let mut abbreviations = HashMap::new();
+    let mut kept = Vec::with_capacity(events.len());
+    while let Some(event) = events.next() {
+        if event != Event::Start(Tag::Paragraph) {
+            kept.push(event);
+            continue;
+        }
+        let paragraph: Vec<_> = events
+            .by_ref()
+            .take_while(|event| *event != Event::End(TagEnd::Paragraph))
+            .collect();
+        let definitions: Option<Vec<_>> = paragraph
+            .iter()
+            .filter(|event| **event != Event::SoftBreak)
+            .map(|event| match event {
+                Event::Text(text) => DEFINITION
+                    .captures(text)
+                    .map(|caps| (caps[1].to_string(), caps[2].trim().to_string())),
+                _ => None,
+            })
+            .collect();
+        match definitions {
+            Some(definitions) => abbreviations.extend(definitions),
+            None => {
+                kept.push(Event::Start(Tag::Paragraph));
+                kept.extend(paragraph);
+                kept.push(Event::End(TagEnd::Paragraph));
+            }
+        }
+    }
+    if abbreviations.is_empty() {
+        return kept;
+    }
+
+    // Then wrap the abbreviations in the remaining text, longest first.
+    let mut names: Vec<_> = abbreviations.keys().map(String::as_str).collect();
+    names.sort_by_key(|name| std::cmp::Reverse(name.len()));
+    let names: Vec<_> = names.into_iter().map(regex::escape).collect();
+    let pattern = Regex::new(&names.join("|")).unwrap();
+    let is_word = |ch: char| ch.is_alphanumeric() || ch == '_';
+
+    let mut in_code_block = false;
+    let mut link_depth = 0;
+    let mut events = Vec::with_capacity(kept.len());
+    for event in kept {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(TagEnd::CodeBlock) => in_code_block = false,
+            Event::Start(Tag::Link { .. } | Tag::Image { .. }) => link_depth += 1,
+            Event::End(TagEnd::Link | TagEnd::Image) => link_depth -= 1,
+            Event::Text(ref text) if !in_code_block && link_depth == 0 => {
+                let mut last = 0;
+                for found in pattern.find_iter(text) {
+                    let before = text[..found.start()].chars().next_back();
+                    let after = text[found.end()..].chars().next();
+                    if before.is_some_and(is_word) || after.is_some_and(is_word) {
+                        continue;
+                    }
+                    if found.start() > last {
+                        events.push(Event::Text(text[last..found.start()].to_string().into()));
+                    }
+                    events.push(Event::InlineHtml(
+                        format!(
+                            "<abbr title=\"{}\">",
+                            special_escape(&abbreviations[found.as_str()])
+                        )
+                        .into(),
+                    ));
+                    events.push(Event::Text(found.as_str().to_string().into()));
+                    events.push(Event::InlineHtml("</abbr>".into()));
+                    last = found.end();
+                }
+                if last > 0 {
+                    if last < text.len() {
+                        events.push(Event::Text(text[last..].to_string().into()));
+                    }
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        events.push(event);
+    }
+    events
+}
+
+/// Drops code blocks that only contain whitespace, or fills them with the
+/// `placeholder` text, depending on `mode`.
+fn handle_empty_code_blocks<'a>(
+    events: Vec<Event<'a>>,
+    mode: EmptyCodeBlocks,
+    placeholder: &str,
+) -> Vec<Event<'a>> {
+    let mut result = Vec::with_capacity(events.len());
+    let mut events = events.into_iter();
+    while let Some(event) = events.next() {
+        let Event::Start(Tag::CodeBlock(_)) = event else {
+            result.push(event);
+            continue;
+        };
+        let content: Vec<_> = events
+            .by_ref()
+            .take_while(|event| *event != Event::End(TagEnd::CodeBlock))
+            .collect();
+        let empty = content
+            .iter()
+            .all(|event| matches!(event, Event::Text(text) if text.trim().is_empty()));
+        match mode {
+            EmptyCodeBlocks::Drop if empty => continue,
+            EmptyCodeBlocks::Placeholder if empty => {
+                result.push(event);
+                result.push(Event::Text(format!("{}\n", placeholder).into()));
+            }
+            _ => {
+                result.push(event);
+                result.extend(content);
+            }
+        }
+        result.push(Event::End(TagEnd::CodeBlock));
+    }
+    result
+}
+
+/// Renders thematic breaks as `<hr>` elements with the given `class`.
+fn add_thematic_break_class<'a>(events: Vec<Event<'a>>, class: &str) -> Vec<Event<'a>> {
+    let html = format!("<hr class=\"{}\" />\n", special_escape(class));
+    events
+        .into_iter()
+        .map(|event| match event {
+            Event::Rule => Event::Html(html.clone().into()),
+            event => event,
+        })
+        .collect()
+}
+
+/// Wraps fenced code blocks in a `<div class="code-block">` together with a
+/// copy button, which themes can wire up to copy the code.
+///
+/// Code blocks in one of the `excluded` languages don't get a button.
+fn add_code_copy_buttons<'a>(events: Vec<Event<'a>>, excluded: &[String]) -> Vec<Event<'a>> {
+    let mut in_fenced_block = false;
+    let mut result = Vec::with_capacity(events.len());
+    for event in events {
+        match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref info))) => {
+                in_fenced_block = true;
+                let lang = info.split([' ', ',']).next().unwrap();
+                let html = if excluded.iter().any(|excluded| excluded == lang) {
+                    "<div class=\"code-block\">"
+                } else {
+                    "<div class=\"code-block\">\
+                     <button class=\"copy-button\" type=\"button\" aria-label=\"Copy\"></button>"
+                };
+                result.push(Event::Html(html.into()));
+                result.push(event);
+            }
+            Event::End(TagEnd::CodeBlock) if in_fenced_block => {
+                in_fenced_block = false;
+                result.push(event);
+                result.push(Event::Html("</div>\n".into()));
+            }
+            event => result.push(event),
+        }
+    }
+    result
+}
+
+/// Renders task list checkboxes without the `disabled` attribute, numbered in
+/// the order they appear on the page with a `data-task-index` attribute.
+fn render_interactive_tasklists(events: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    let mut index = 0;
+    events
+        .into_iter()
+        .map(|event| match event {
+            Event::TaskListMarker(checked) => {
+                let checked = if checked { r#" checked="""# } else { "" };
+                let html =
+                    format!("<input type=\"checkbox\" data-task-index=\"{index}\"{checked}/>\n");
+                index += 1;
+                Event::InlineHtml(html.into())
+            }
+            event => event,
+        })
+        .collect()
+}
+
+/// Turns block quotes whose first line starts with `[!details]` into
+/// `<details>` elements, using the rest of that line as the `<summary>`.
+fn render_details_blockquotes(events: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    const MARKER: &str = "[!details]";
+
+    // Whether each currently open block quote was replaced, to pick the matching end.
+    let mut replaced = Vec::new();
+    let mut result = Vec::with_capacity(events.len());
+    let mut events = events.into_iter().peekable();
+    while let Some(event) = events.next() {
+        match event {
+            Event::Start(Tag::BlockQuote) => {
+                // Buffer the first line of the block quote to look for the marker.
+                let mut line = Vec::new();
+                if events
+                    .next_if(|event| matches!(event, Event::Start(Tag::Paragraph)))
+                    .is_some()
+                {
+                    while let Some(event) = events.next_if(|event| {
+                        !matches!(
+                            event,
+                            Event::SoftBreak | Event::HardBreak | Event::End(TagEnd::Paragraph)
+                        )
+                    }) {
+                        line.push(event);
+                    }
+                } else {
+                    replaced.push(false);
+                    result.push(Event::Start(Tag::BlockQuote));
+                    continue;
+                }
+
+                // The parser splits text at brackets, so join the leading text.
+                let leading = line
+                    .iter()
+                    .take_while(|event| matches!(event, Event::Text(_)))
+                    .count();
+                let text: String = line[..leading]
+                    .iter()
+                    .map(|event| match event {
+                        Event::Text(text) => text.as_ref(),
+                        _ => unreachable!(),
+                    })
+                    .collect();
+                let Some(summary) = text.strip_prefix(MARKER) else {
+                    replaced.push(false);
+                    result.push(Event::Start(Tag::BlockQuote));
+                    result.push(Event::Start(Tag::Paragraph));
+                    result.extend(line);
+                    continue;
+                };
+
+                replaced.push(true);
+                result.push(Event::Html("<details>\n<summary>".into()));
+                let summary = summary.trim_start();
+                if !summary.is_empty() {
+                    result.push(Event::Text(summary.to_string().into()));
+                }
+                result.extend(line.drain(leading..));
+                result.push(Event::Html("</summary>\n".into()));
+                // Drop the first paragraph if it only held the summary,
+                // otherwise the rest of it becomes a paragraph of its own.
+                match events.next() {
+                    Some(Event::End(TagEnd::Paragraph)) | None => {}
+                    Some(_) => result.push(Event::Start(Tag::Paragraph)),
+                }
+            }
+            Event::End(TagEnd::BlockQuote) => {
+                if replaced.pop().unwrap_or(false) {
+                    result.push(Event::Html("</details>\n".into()));
+                } else {
+                    result.push(event);
+                }
+            }
+            event => result.push(event),
+        }
+    }
+    result
+}
+
+/// Renders autolinks like `<ftp://example.com>` whose scheme isn't one of
+/// the `schemes` as text, as they were written.
+fn filter_autolink_schemes<'a>(events: Vec<Event<'a>>, schemes: &[String]) -> Vec<Event<'a>> {
+    let is_allowed = |link_type: LinkType, dest_url: &str| {
+        let scheme = match link_type {
+            LinkType::Email => "mailto",
+            _ => dest_url.split_once(':').map_or("", |(scheme, _)| scheme),
+        };
+        schemes.iter().any(|s| s.eq_ignore_ascii_case(scheme))
+    };
+
+    let mut events = events.into_iter();
+    let mut filtered = Vec::new();
+    while let Some(event) = events.next() {
+        match event {
+            Event::Start(Tag::Link {
+                link_type: link_type @ (LinkType::Autolink | LinkType::Email),
+                ref dest_url,
+                ..
+            }) if !is_allowed(link_type, dest_url) => {
+                let mut text = String::from("<");
+                for event in events.by_ref() {
+                    match event {
+                        Event::End(TagEnd::Link) => break,
+                        Event::Text(t) | Event::Code(t) => text.push_str(&t),
+                        _ => {}
+                    }
+                }
+                text.push('>');
+                filtered.push(Event::Text(text.into()));
+            }
+            event => filtered.push(event),
+        }
+    }
+    filtered
+}
+
+/// Replaces external links with raw HTML anchors carrying the attributes
+/// configured by `external-links-new-tab` and `external-link-rel`, and links
+/// followed by a `{hreflang=fr}` marker with anchors carrying the `hreflang`
+/// if `hreflang-links` is set.
+///
+/// Other links within the book are left untouched.
+fn add_link_attributes<'a>(mut events: Vec<Event<'a>>, options: &Markdown) -> Vec<Event<'a>> {
+    static HREFLANG: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"^\{hreflang=([A-Za-z]{2,3}(?:-[A-Za-z0-9]{1,8})*)\}").unwrap());
+
+    // The languages of the links, by the index of their start, with the
+    // markers after them removed.
+    let mut hreflangs = HashMap::new();
+    if options.hreflang_links {
+        let mut starts = Vec::new();
+        for i in 0..events.len() {
+            match &events[i] {
+                Event::Start(Tag::Link { .. }) => starts.push(i),
+                Event::End(TagEnd::Link) => {
+                    let start = starts.pop();
+                    let Some(Event::Text(text)) = events.get(i + 1) else {
+                        continue;
+                    };
+                    let Some(caps) = HREFLANG.captures(text) else {
+                        continue;
+                    };
+                    let rest = text[caps[0].len()..].to_string();
+                    hreflangs.extend(start.map(|start| (start, caps[1].to_string())));
+                    events[i + 1] = Event::Text(rest.into());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut rel: Vec<&str> = Vec::new();
+    if options.external_links_new_tab {
+        rel.extend(["noopener", "noreferrer"]);
+    }
+    for value in &options.external_link_rel {
+        if !rel.contains(&value.as_str()) {
+            rel.push(value);
+        }
+    }
+    let rel = special_escape(&rel.join(" "));
+
+    // Whether each currently open link was replaced, to pick the matching end.
+    let mut replaced = Vec::new();
+    events
+        .into_iter()
+        .enumerate()
+        .map(|(i, event)| match event {
+            Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                title,
+                ..
+            }) if hreflangs.contains_key(&i)
+                || (link_type != LinkType::Email
+                    && is_external_link(&dest_url)
+                    && (options.external_links_new_tab || !rel.is_empty())) =>
+            {
+                replaced.push(true);
+                let mut html = format!("<a href=\"{}\"", special_escape(&dest_url));
+                if !title.is_empty() {
+                    write!(html, " title=\"{}\"", special_escape(&title)).unwrap();
+                }
+                if let Some(hreflang) = hreflangs.get(&i) {
+                    write!(html, " hreflang=\"{hreflang}\"").unwrap();
+                }
+                if link_type != LinkType::Email && is_external_link(&dest_url) {
+                    if options.external_links_new_tab {
+                        html.push_str(r#" target="_blank""#);
+                    }
+                    if !rel.is_empty() {
+                        write!(html, " rel=\"{rel}\"").unwrap();
+                    }
+                }
+                html.push('>');
+                Event::InlineHtml(html.into())
+            }
+            Event::Start(Tag::Link { .. }) => {
+                replaced.push(false);
+                event
+            }
+            Event::End(TagEnd::Link) if replaced.pop() == Some(true) => {
+                Event::InlineHtml("</a>".into())
+            }
+            event => event,
+        })
+        .collect()
+}
+
+/// Turns every level one heading after the first into a level two heading.
+///
+/// If `title_is_h1` is set, the page title counts as the first one, so all
+/// level one headings are turned into level two headings.
+fn demote_extra_h1(events: Vec<Event<'_>>, title_is_h1: bool) -> Vec<Event<'_>> {
+    let mut seen_h1 = title_is_h1;
+    let mut demoting = false;
+    events
+        .into_iter()
+        .map(|event| match event {
+            Event::Start(Tag::Heading {
+                level: HeadingLevel::H1,
+                id,
+                classes,
+                attrs,
+            }) => {
+                demoting = seen_h1;
+                seen_h1 = true;
+                let level = if demoting {
+                    HeadingLevel::H2
+                } else {
+                    HeadingLevel::H1
+                };
+                Event::Start(Tag::Heading {
+                    level,
+                    id,
+                    classes,
+                    attrs,
+                })
+            }
+            Event::End(TagEnd::Heading(HeadingLevel::H1)) if demoting => {
+                Event::End(TagEnd::Heading(HeadingLevel::H2))
+            }
+            event => event,
+        })
+        .collect()
+}
+
+/// Turns paragraphs that only consist of an image with a title into a
+/// `<figure>`, using the title as its `<figcaption>`.
+///
+/// Images that are part of a larger paragraph are left untouched.
+fn wrap_captioned_figures(events: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    let mut wrapped = Vec::with_capacity(events.len());
+    let mut i = 0;
+    while i < events.len() {
+        if let (Event::Start(Tag::Paragraph), Some(Event::Start(Tag::Image { title, .. }))) =
+            (&events[i], events.get(i + 1))
+        {
+            let image_end = events[i + 1..]
+                .iter()
+                .position(|event| matches!(event, Event::End(TagEnd::Image)))
+                .map(|offset| i + 1 + offset);
+            if let Some(image_end) = image_end {
+                if !title.is_empty()
+                    && matches!(
+                        events.get(image_end + 1),
+                        Some(Event::End(TagEnd::Paragraph))
+                    )
+                {
+                    let caption = format!(
+                        "<figcaption>{}</figcaption></figure>\n",
+                        special_escape(title)
+                    );
+                    wrapped.push(Event::Html("<figure>".into()));
+                    wrapped.extend(events[i + 1..=image_end].iter().cloned());
+                    wrapped.push(Event::Html(caption.into()));
+                    i = image_end + 2;
+                    continue;
+                }
+            }
+        }
+        wrapped.push(events[i].clone());
+        i += 1;
+    }
+    wrapped
+}
+
+/// Wraps runs of two or more consecutive paragraphs that only consist of an
+/// image in a `<div class="gallery">`.
+fn wrap_image_galleries(events: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    let mut wrapped = Vec::with_capacity(events.len());
+    let mut i = 0;
+    while i < events.len() {
+        let mut end = i;
+        let mut images = 0;
+        while let Some(len) = image_paragraph_len(&events[end..]) {
+            end += len;
+            images += 1;
+        }
+        if images >= 2 {
+            wrapped.push(Event::Html("<div class=\"gallery\">\n".into()));
+            wrapped.extend(events[i..end].iter().cloned());
+            wrapped.push(Event::Html("</div>\n".into()));
+            i = end;
+        } else {
+            wrapped.push(events[i].clone());
+            i += 1;
+        }
+    }
+    wrapped
+}
+
+/// The number of events of the paragraph at the start of `events`, if it
+/// only consists of a single image.
+fn image_paragraph_len(events: &[Event<'_>]) -> Option<usize> {
+    if !matches!(
+        events,
+        [
+            Event::Start(Tag::Paragraph),
+            Event::Start(Tag::Image { .. }),
+            ..
+        ]
+    ) {
+        return None;
+    }
+    let image_end = events
+        .iter()
+        .position(|event| matches!(event, Event::End(TagEnd::Image)))?;
+    matches!(
+        events.get(image_end + 1),
+        Some(Event::End(TagEnd::Paragraph))
+    )
+    .then_some(image_end + 2)
+}
+
+/// Moves every footnote definition next to its first reference as a
+/// `<span class="sidenote">`, so that themes can display them in the margin.
+///
+/// The definitions are removed from their original position, which means
+/// no footnote list is emitted at the bottom of the page.
+fn render_sidenotes(events: Vec<Event<'_>>, style: FootnoteStyle) -> Vec<Event<'_>> {
+    let mut definitions = HashMap::new();
+    let mut body = Vec::with_capacity(events.len());
+    let mut events = events.into_iter();
+    while let Some(event) = events.next() {
+        let Event::Start(Tag::FootnoteDefinition(name)) = event else {
+            body.push(event);
+            continue;
+        };
+        // Paragraphs are unwrapped since a `<span>` can only hold phrasing content.
+        let content = events
+            .by_ref()
+            .take_while(|event| !matches!(event, Event::End(TagEnd::FootnoteDefinition)))
+            .filter(|event| {
+                !matches!(
+                    event,
+                    Event::Start(Tag::Paragraph) | Event::End(TagEnd::Paragraph)
+                )
+            });
+        let mut html = String::new();
+        html::push_html(&mut html, content);
+        definitions.insert(name, html);
+    }
+
+    let mut numbers = HashMap::new();
+    body.into_iter()
+        .map(|event| {
+            let Event::FootnoteReference(name) = event else {
+                return event;
+            };
+            let len = numbers.len() + 1;
+            let is_first = !numbers.contains_key(&name);
+            let number = *numbers.entry(name.clone()).or_insert(len);
+            let id = special_escape(&name);
+            let mut html = footnote_reference_html(&format!("sn-{id}"), number, style);
+            if let Some(content) = definitions.get(&name).filter(|_| is_first) {
+                write!(
+                    html,
+                    r#"<span class="sidenote" id="sn-{id}"><sup class="footnote-definition-label">{number}</sup> {}</span>"#,
+                    content.trim_end()
+                )
+                .unwrap();
+            }
+            Event::InlineHtml(html.into())
+        })
+        .collect()
+}
+
+/// Renders the references to footnotes in the given `style`.
+///
+/// Footnotes are numbered in the order they first appear, either by a
+/// reference or by their definition, just like `pulldown_cmark` does. Since
+/// `pulldown_cmark` no longer sees the references, the definitions are
+/// rendered here as well to keep their numbers in sync.
+fn render_footnote_references(events: Vec<Event<'_>>, style: FootnoteStyle) -> Vec<Event<'_>> {
+    let mut numbers = HashMap::new();
+    events
+        .into_iter()
+        .map(|event| match event {
+            Event::Start(Tag::FootnoteDefinition(name)) => {
+                let len = numbers.len() + 1;
+                let number = *numbers.entry(name.clone()).or_insert(len);
+                Event::Html(
+                    format!(
+                        "<div class=\"footnote-definition\" id=\"{}\">\
+                         <sup class=\"footnote-definition-label\">{number}</sup>",
+                        special_escape(&name)
+                    )
+                    .into(),
+                )
+            }
+            Event::End(TagEnd::FootnoteDefinition) => Event::Html("</div>\n".into()),
+            Event::FootnoteReference(name) => {
+                let len = numbers.len() + 1;
+                let number = *numbers.entry(name.clone()).or_insert(len);
+                let html = footnote_reference_html(&special_escape(&name), number, style);
+                Event::InlineHtml(html.into())
+            }
+            event => event,
+        })
+        .collect()
+}
+
+/// Rewrites inline footnotes like `^[the note]` into references to footnotes
+/// whose definitions are appended to the `text`, so that they are rendered
+/// like any other footnote.
+///
+/// The footnotes are named `inline-1`, `inline-2`, and so on, skipping the
+/// names that the page already uses.
+fn expand_inline_footnotes(text: &str) -> Cow<'_, str> {
+    if !text.contains("^[") {
+        return Cow::Borrowed(text);
+    }
+    let code: Vec<Range<usize>> = new_cmark_parser(text, false)
+        .into_offset_iter()
+        .filter(|(event, _)| {
+            matches!(
+                event,
+                Event::Start(Tag::CodeBlock(_))
+                    | Event::Code(_)
+                    | Event::Html(_)
+                    | Event::InlineHtml(_)
+            )
+        })
+        .map(|(_, range)| range)
+        .collect();
+
+    let mut output = String::with_capacity(text.len());
+    let mut definitions = String::new();
+    let mut number = 0;
+    let mut rest_start = 0;
+    let mut search_start = 0;
+    while let Some(offset) = text[search_start..].find("^[") {
+        let start = search_start + offset;
+        search_start = start + 2;
+        if text[..start].ends_with('\\') || code.iter().any(|range| range.contains(&start)) {
+            continue;
+        }
+        let Some(len) = inline_footnote_len(&text[start + 2..]) else {
+            continue;
+        };
+        let note = &text[start + 2..start + 2 + len];
+        if note.trim().is_empty() {
+            continue;
+        }
+        let name = loop {
+            number += 1;
+            let name = format!("inline-{number}");
+            if !text.contains(&format!("[^{name}]")) {
+                break name;
+            }
+        };
+        output.push_str(&text[rest_start..start]);
+        write!(output, "[^{name}]").unwrap();
+        write!(
+            definitions,
+            "\n\n[^{name}]: {}",
+            note.trim().replace('\n', " ")
+        )
+        .unwrap();
+        rest_start = start + 2 + len + 1;
+        search_start = rest_start;
+    }
+    if definitions.is_empty() {
+        return Cow::Borrowed(text);
+    }
+    output.push_str(text[rest_start..].trim_end());
+    output.push_str(&definitions);
+    output.push('\n');
+    Cow::Owned(output)
+}
+
+/// The length of the note of an inline footnote, up to the `]` matching the
+/// `[` before the start of `text`. Inline footnotes can't span paragraphs.
+fn inline_footnote_len(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut chars = text.char_indices();
+    while let Some((i, ch)) = chars.next() {
+        match ch {
+            '\\' => {
+                chars.next();
+            }
+            '[' => depth += 1,
+            ']' if depth == 0 => return Some(i),
+            ']' => depth -= 1,
+            '\n' if text[i + 1..]
+                .trim_start_matches([' ', '\t'])
+                .starts_with('\n') =>
+            {
+                return None
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Drops references to a footnote that directly follow a reference to the
+/// same footnote, so that only a single reference is rendered.
+///
+/// Footnotes are numbered by their first reference, so the numbers of the
+/// other footnotes don't change.
+fn collapse_footnote_references(events: Vec<Event<'_>>) -> Vec<Event<'_>> {
+    let mut collapsed: Vec<Event<'_>> = Vec::with_capacity(events.len());
+    for event in events {
+        if let (Some(Event::FootnoteReference(previous)), Event::FootnoteReference(name)) =
+            (collapsed.last(), &event)
+        {
+            if previous == name {
+                continue;
+            }
+        }
+        collapsed.push(event);
+    }
+    collapsed
+}
+
+/// Lists the footnotes of every section at the end of that section, before
+/// the next `<h2>`, instead of where they are defined.
+///
+/// Footnotes are numbered per section in the order they are referenced, and
+/// their IDs are prefixed with `s{section}-`, so a footnote referenced in
+/// several sections is listed in each of them. Footnotes that are never
+/// referenced are listed in the section they are defined in.
+fn render_footnote_sections(events: Vec<Event<'_>>, style: FootnoteStyle) -> Vec<Event<'_>> {
+    fn is_section_start(event: &Event<'_>) -> bool {
+        matches!(
+            event,
+            Event::Start(Tag::Heading {
+                level: HeadingLevel::H2,
+                ..
+            })
+        )
+    }
+
+    // The events of each section without the footnote definitions, and the
+    // footnotes defined in each section.
+    let mut definitions = HashMap::new();
+    let mut referenced = HashSet::new();
+    let mut sections = vec![(Vec::new(), Vec::new())];
+    let mut events = events.into_iter();
+    while let Some(event) = events.next() {
+        if is_section_start(&event) {
+            sections.push((Vec::new(), Vec::new()));
+        }
+        if let Event::FootnoteReference(name) = &event {
+            referenced.insert(name.clone());
+        }
+        let (body, defined) = sections.last_mut().unwrap();
+        let Event::Start(Tag::FootnoteDefinition(name)) = event else {
+            body.push(event);
+            continue;
+        };
+        let content: Vec<_> = events
+            .by_ref()
+            .take_while(|event| !matches!(event, Event::End(TagEnd::FootnoteDefinition)))
+            .collect();
+        definitions.insert(name.clone(), content);
+        defined.push(name);
+    }
+
+    let mut out = Vec::new();
+    for (section, (body, defined)) in sections.into_iter().enumerate() {
+        let mut numbers = HashMap::new();
+        let mut listed = Vec::new();
+        for event in body {
+            let Event::FootnoteReference(name) = event else {
+                out.push(event);
+                continue;
+            };
+            let len = numbers.len() + 1;
+            let number = *numbers.entry(name.clone()).or_insert_with(|| {
+                listed.push(name.clone());
+                len
+            });
+            let id = format!("s{section}-{}", special_escape(&name));
+            out.push(Event::InlineHtml(
+                footnote_reference_html(&id, number, style).into(),
+            ));
+        }
+        listed.extend(
+            defined
+                .into_iter()
+                .filter(|name| !referenced.contains(name)),
+        );
+        if listed.is_empty() {
+            continue;
+        }
+
+        out.push(Event::Html("<div class=\"footnotes\">\n".into()));
+        for (number, name) in listed.into_iter().enumerate() {
+            out.push(Event::Html(
+                format!(
+                    "<div class=\"footnote-definition\" id=\"s{section}-{}\">\
+                     <sup class=\"footnote-definition-label\">{}</sup>",
+                    special_escape(&name),
+                    number + 1
+                )
+                .into(),
+            ));
+            out.extend(definitions.get(&name).into_iter().flatten().cloned());
+            out.push(Event::Html("</div>\n".into()));
+        }
+        out.push(Event::Html("</div>\n".into()));
+    }
+    out
+}
+
+/// Footnotes collected from the pages of the whole book, to be listed as
+/// endnotes at the end of the print page.
+///
+/// Footnotes are numbered across all pages in the order they are first
+/// referenced, and their IDs are prefixed with the ID of their page. Footnotes
+/// that are never referenced follow the referenced ones of their page.
+#[derive(Debug, Default)]
+pub(crate) struct Endnotes {
+    /// The numbers of the footnotes referenced on the current page.
+    numbers: HashMap<String, usize>,
+    /// The footnotes referenced on the current page, in order.
+    referenced: Vec<String>,
+    /// The rendered definitions of the footnotes of the current page, in
+    /// order.
+    definitions: Vec<(String, String)>,
+    /// The ID and the rendered definition of each footnote of the finished
+    /// pages, in order.
+    notes: Vec<(String, String)>,
+}
+
+impl Endnotes {
+    /// The number of the footnote `name` of the current page.
+    fn number(&mut self, name: &str) -> usize {
+        if let Some(&number) = self.numbers.get(name) {
+            return number;
+        }
+        self.referenced.push(name.to_string());
+        let number = self.notes.len() + self.referenced.len();
+        self.numbers.insert(name.to_string(), number);
+        number
+    }
+
+    /// Moves the footnotes of the current page to the endnotes, prefixing
+    /// their IDs with `page_id`.
+    pub(crate) fn finish_page(&mut self, page_id: &str) {
+        self.numbers.clear();
+        let mut names = std::mem::take(&mut self.referenced);
+        let mut definitions = std::mem::take(&mut self.definitions);
+        let unreferenced: Vec<_> = definitions
+            .iter()
+            .map(|(name, _)| name.clone())
+            .filter(|name| !names.contains(name))
+            .collect();
+        names.extend(unreferenced);
+        for name in names {
+            let definition = definitions
+                .iter()
+                .position(|(defined, _)| *defined == name)
+                .map(|i| definitions.remove(i).1)
+                .unwrap_or_default();
+            let id = print_element_id(page_id, &special_escape(&name));
+            self.notes.push((id, definition));
+        }
+    }
+
+    /// Renders the list of endnotes, which is empty if no page has
+    /// footnotes.
+    pub(crate) fn to_html(&self) -> String {
+        if self.notes.is_empty() {
+            return String::new();
+        }
+        let mut html = String::from("<div class=\"footnotes endnotes\">\n");
+        for (number, (id, definition)) in self.notes.iter().enumerate() {
+            write!(
+                html,
+                "<div class=\"footnote-definition\" id=\"{}\">\
+                 <sup class=\"footnote-definition-label\">{}</sup>\n{}</div>\n",
+                id,
+                number + 1,
+                definition
+            )
+            .unwrap();
+        }
+        html.push_str("</div>\n");
+        html
+    }
+}
+
+/// Moves the footnote definitions to the book-level `endnotes`, and renders
+/// the references with their numbers across the book in the given `style`.
+fn collect_endnotes<'a>(
+    events: Vec<Event<'a>>,
+    endnotes: &mut Endnotes,
+    style: FootnoteStyle,
+) -> Vec<Event<'a>> {
+    let mut body = Vec::with_capacity(events.len());
+    let mut events = events.into_iter();
+    while let Some(event) = events.next() {
+        match event {
+            Event::Start(Tag::FootnoteDefinition(name)) => {
+                let content = events
+                    .by_ref()
+                    .take_while(|event| !matches!(event, Event::End(TagEnd::FootnoteDefinition)));
+                let mut html = String::new();
+                html::push_html(&mut html, content);
+                endnotes.definitions.push((name.to_string(), html));
+            }
+            Event::FootnoteReference(name) => {
+                let number = endnotes.number(&name);
+                let html = footnote_reference_html(&special_escape(&name), number, style);
+                body.push(Event::InlineHtml(html.into()));
+            }
+            event => body.push(event),
+        }
+    }
+    body
+}
+
+/// The markup of a reference to the footnote with the (escaped) `id`.
+fn footnote_reference_html(id: &str, number: usize, style: FootnoteStyle) -> String {
+    match style {
+        FootnoteStyle::Superscript => {
+            format!(r##"<sup class="footnote-reference"><a href="#{id}">{number}</a></sup>"##)
+        }
+        FootnoteStyle::Bracketed => {
+            format!(r##"<a class="footnote-reference" href="#{id}">[{number}]</a>"##)
+        }
+    }
+}
+
+/// Counts the words of the markdown `text`, ignoring the markup.
+///
+/// Since Chinese and Japanese are written without spaces, each of their
+/// characters counts as a word. The contents of code blocks are only counted
+/// if `include_code_blocks` is set.
+pub fn count_words(text: &str, include_code_blocks: bool) -> usize {
+    let mut plain = String::with_capacity(text.len());
+    let mut in_code_block = false;
+    for event in new_cmark_parser(text, false) {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(TagEnd::CodeBlock) => {
+                in_code_block = false;
+                plain.push(' ');
+            }
+            Event::Text(text) if !in_code_block || include_code_blocks => plain.push_str(&text),
+            Event::Code(code) => plain.push_str(&code),
+            Event::SoftBreak | Event::HardBreak | Event::End(_) => plain.push(' '),
+            _ => {}
+        }
+    }
+
+    fn is_cjk(ch: char) -> bool {
+        matches!(ch,
+            '\u{3040}'..='\u{30ff}' // Hiragana and Katakana
+            | '\u{3400}'..='\u{4dbf}' // CJK Unified Ideographs Extension A
+            | '\u{4e00}'..='\u{9fff}' // CJK Unified Ideographs
+            | '\u{f900}'..='\u{faff}' // CJK Compatibility Ideographs
+            | '\u{20000}'..='\u{2fa1f}' // Supplementary Ideographic Plane
+        )
+    }
+
+    plain
+        .split_whitespace()
+        .map(|word| {
+            let cjk = word.chars().filter(|&ch| is_cjk(ch)).count();
+            // Any other characters between the CJK ones form words of their own.
+            let others = word
+                .split(is_cjk)
+                .filter(|part| part.chars().any(char::is_alphanumeric))
+                .count();
+            cjk + others
+        })
+        .sum()
+}
+
+/// Estimates the minutes needed to read the markdown `text`, rounded up.
+///
+/// Code blocks are not counted, see [`count_words`] for how words are counted.
+pub fn estimate_reading_time(text: &str, words_per_minute: usize) -> usize {
+    count_words(text, false).div_ceil(words_per_minute.max(1))
+}
+
+/// How [`render_teaser`] renders a teaser.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TeaserOptions {
+    /// The `[output.html.markdown]` options to render the teaser with.
+    pub markdown: Markdown,
+    /// Replace straight quotes, dashes, and ellipses.
+    pub smart_punctuation: bool,
+    /// The markdown appended to a teaser that leaves out part of the text,
+    /// like a link to the whole page.
+    pub read_more: String,
+}
+
+impl Default for TeaserOptions {
+    fn default() -> TeaserOptions {
+        TeaserOptions {
+            markdown: Markdown::default(),
+            smart_punctuation: false,
+            read_more: r#"<p class="read-more">Read more…</p>"#.to_string(),
+        }
+    }
+}
+
+/// Renders the beginning of the markdown `text` as a teaser, like for a
+/// listing of blog posts, followed by the `read_more` marker.
+///
+/// The teaser ends with the first top-level block, like a paragraph, list or
+/// code block, that brings its words to `word_limit`, so it is never cut
+/// inside of a block. Words are counted like [`count_words`] does, without
+/// code blocks. Text with no more words than that is rendered in full,
+/// without the marker.
+///
+/// The link reference definitions and the footnote definitions that the
+/// teaser refers to are kept, even if they come later in the text.
+pub fn render_teaser(text: &str, word_limit: usize, options: &TeaserOptions) -> String {
+    let render = |text: &str| {
+        render_markdown_with_options(text, options.smart_punctuation, &options.markdown)
+    };
+    if count_words(text, false) <= word_limit {
+        return render(text);
+    }
+
+    let parser = new_cmark_parser(text, false);
+    let mut definitions: Vec<Range<usize>> = parser
+        .reference_definitions()
+        .iter()
+        .map(|(_, definition)| definition.span.clone())
+        .collect();
+    let mut depth = 0;
+    let mut words = 0;
+    let mut cut = None;
+    let mut truncated = false;
+    let mut footnotes = HashSet::new();
+    for (event, range) in parser.into_offset_iter() {
+        match event {
+            Event::Start(tag) => {
+                if depth == 0 {
+                    match (tag, cut) {
+                        (Tag::FootnoteDefinition(label), Some(_)) => {
+                            if footnotes.contains(label.as_ref()) {
+                                definitions.push(range);
+                            }
+                        }
+                        (Tag::FootnoteDefinition(_), None) => {}
+                        (_, None) => {
+                            words += count_words(&text[range.clone()], false);
+                            if words >= word_limit {
+                                cut = Some(range.end);
+                            }
+                        }
+                        (_, Some(_)) => truncated = true,
+                    }
+                }
+                depth += 1;
+            }
+            Event::End(_) => depth -= 1,
+            Event::FootnoteReference(label) if cut.map_or(true, |cut| range.start < cut) => {
+                footnotes.insert(label.to_string());
+            }
+            _ if depth == 0 && cut.is_some() => truncated = true,
+            _ => {}
+        }
+    }
+
+    let Some(cut) = cut.filter(|_| truncated) else {
+        return render(text);
+    };
+    let mut teaser = text[..cut].to_string();
+    teaser.push_str("\n\n");
+    teaser.push_str(&options.read_more);
+    teaser.push('\n');
+    definitions.retain(|range| range.start >= cut);
+    definitions.sort_by_key(|range| range.start);
+    for range in definitions {
+        teaser.push_str("\n\n");
+        teaser.push_str(&text[range]);
+    }
+    render(&teaser)
+}
+
+/// Returns the plain text of the first paragraph of the markdown `text`, to
+/// describe the page in a `<meta name="description">` or `og:description`.
+///
+/// Headings, code blocks, footnotes, and paragraphs holding nothing but
+/// images are skipped. Longer text is shortened to at most `max_chars`
+/// characters, including a trailing `…`, without cutting a word in half.
+pub fn page_description(text: &str, max_chars: usize) -> Option<String> {
+    let mut plain = String::new();
+    let mut in_paragraph = false;
+    let mut in_footnote = false;
+    let mut image_depth = 0;
+    for event in new_cmark_parser(text, false) {
+        match event {
+            Event::Start(Tag::FootnoteDefinition(_)) => in_footnote = true,
+            Event::End(TagEnd::FootnoteDefinition) => in_footnote = false,
+            Event::Start(Tag::Paragraph) if !in_footnote => {
+                in_paragraph = true;
+                plain.clear();
+            }
+            Event::End(TagEnd::Paragraph) if in_paragraph => {
+                in_paragraph = false;
+                let words: Vec<_> = plain.split_whitespace().collect();
+                if !words.is_empty() {
+                    return Some(shorten_at_word(&words.join(" "), max_chars));
+                }
+            }
+            Event::Start(Tag::Image { .. }) => image_depth += 1,
+            Event::End(TagEnd::Image) => image_depth -= 1,
+            Event::Text(text) | Event::Code(text) if in_paragraph && image_depth == 0 => {
+                plain.push_str(&text)
+            }
+            Event::SoftBreak | Event::HardBreak if in_paragraph => plain.push(' '),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Shortens `text`, made of words separated by single spaces, to at most
+/// `max_chars` characters including a trailing `…`.
+fn shorten_at_word(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_owned();
+    }
+    // Leave room for the ellipsis.
+    let end = text
+        .char_indices()
+        .nth(max_chars.saturating_sub(1))
+        .map_or(text.len(), |(index, _)| index);
+    let mut shortened = &text[..end];
+    if !text[end..].starts_with(' ') {
+        // Drop the word that would be cut, unless it's the only one.
+        if let Some(space) = shortened.rfind(' ') {
+            shortened = &shortened[..space];
+        }
+    }
+    format!("{}…", shortened.trim_end())
+}
+
+/// Returns the sources of all images in the markdown `text` that lack
+/// alternative text, so that they can be reported for accessibility.
+///
+/// Markdown images (`![](img.png)`) always need an alt text. Raw HTML images
+/// only need an `alt` attribute: an explicitly empty `alt=""` marks the image
+/// as decorative, so it is not reported.
+pub fn images_without_alt_text(text: &str) -> Vec<String> {
+    static IMG: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)<img\b[^>]*>").unwrap());
+    static SRC: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\bsrc\s*=\s*"([^"]*)""#).unwrap());
+    static ALT: Lazy<Regex> = Lazy::new(|| Regex::new(r"\balt\s*=").unwrap());
+
+    let mut missing = Vec::new();
+    let mut image = None;
+    let mut alt = String::new();
+    for event in new_cmark_parser(text, false) {
+        match event {
+            Event::Start(Tag::Image { dest_url, .. }) => {
+                image = Some(dest_url);
+                alt.clear();
+            }
+            Event::End(TagEnd::Image) => {
+                if let Some(dest_url) = image.take() {
+                    if alt.trim().is_empty() {
+                        missing.push(dest_url.to_string());
+                    }
+                }
+            }
+            Event::Text(text) | Event::Code(text) if image.is_some() => alt.push_str(&text),
+            Event::Html(html) | Event::InlineHtml(html) => {
+                for img in IMG.find_iter(&html) {
+                    if !ALT.is_match(img.as_str()) {
+                        let src = SRC
+                            .captures(img.as_str())
+                            .map(|caps| caps[1].to_string())
+                            .unwrap_or_default();
+                        missing.push(src);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    missing
+}
+
+/// Returns the sources of all images in the markdown `text` with a relative
+/// path that doesn't point to an existing file in `dir`, the directory of
+/// the chapter.
+///
+/// Both Markdown images and raw HTML images are checked. Remote images and
+/// root-absolute paths are skipped since they can't be resolved locally.
+pub fn missing_images(text: &str, dir: &Path) -> Vec<String> {
+    static IMG_SRC: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r#"(?s)<img\b[^>]*?\bsrc\s*=\s*"([^"]*)""#).unwrap());
+
+    let mut sources = Vec::new();
+    for event in new_cmark_parser(text, false) {
+        match event {
+            Event::Start(Tag::Image { dest_url, .. }) => sources.push(dest_url.to_string()),
+            Event::Html(html) | Event::InlineHtml(html) => {
+                sources.extend(IMG_SRC.captures_iter(&html).map(|caps| caps[1].to_string()));
+            }
+            _ => {}
+        }
+    }
+
+    sources
+        .into_iter()
+        .filter(|src| {
+            if src.is_empty() || src.starts_with('/') || is_external_link(src) {
+                return false;
+            }
+            let path = src.split(['?', '#']).next().unwrap().replace("%20", " ");
+            !path.is_empty() && !dir.join(path).is_file()
+        })
+        .collect()
+}
+
+/// Returns the destinations of all links in the markdown `text` that point
+/// outside of the book, each together with the `path` of the page, so that
+/// they can be checked by an external link checker.
+///
+/// Both markdown links and raw HTML `<a href>` elements are included. Links
+/// with any scheme count as external, including `mailto:` links, as well as
+/// protocol-relative links like `//example.com/`.
+pub fn external_links(text: &str, path: &Path) -> Vec<(PathBuf, String)> {
+    static A_HREF: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r#"(?s)<a\b[^>]*?\bhref\s*=\s*"([^"]*)""#).unwrap());
+
+    let mut links = Vec::new();
+    for event in new_cmark_parser(text, false) {
+        match event {
+            Event::Start(Tag::Link { dest_url, .. }) => links.push(dest_url.to_string()),
+            Event::Html(html) | Event::InlineHtml(html) => {
+                links.extend(A_HREF.captures_iter(&html).map(|caps| caps[1].to_string()));
+            }
+            _ => {}
+        }
+    }
+    links
+        .into_iter()
+        .filter(|link| is_external_link(link))
+        .map(|link| (path.to_path_buf(), link))
+        .collect()
+}
+
+/// Returns the heading IDs that occur more than once in the markdown `text`,
+/// together with the number of headings using them, in order of their first
+/// occurrence.
+///
+/// IDs are derived from the heading text like [`unique_id_from_content`] does, unless
+/// the heading sets one explicitly with `{#id}`. Rendering disambiguates
+/// duplicates by appending a counter, so this can be used to report them.
+pub fn check_unique_heading_ids(text: &str) -> Vec<(String, usize)> {
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    for id in heading_ids(text) {
+        match counts.iter_mut().find(|(existing, _)| *existing == id) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((id, 1)),
+        }
+    }
+    counts.retain(|(_, count)| *count > 1);
+    counts
+}
+
+/// A heading of a markdown document, as found by [`headings`].
+struct Heading {
+    level: HeadingLevel,
+    /// The ID, before duplicates are disambiguated.
+    id: String,
+    /// The text content, without any markup.
+    text: String,
+    /// Where the heading starts in the markdown.
+    start: usize,
+}
+
+/// The headings in the markdown `text`, in order.
+fn headings(text: &str) -> Vec<Heading> {
+    let mut headings = Vec::new();
+    let mut heading: Option<(Heading, Option<String>, Vec<Event<'_>>)> = None;
+    for (event, range) in new_cmark_parser(text, false).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Heading { level, id, .. }) => {
+                let current = Heading {
+                    level,
+                    id: String::new(),
+                    text: String::new(),
+                    start: range.start,
+                };
+                heading = Some((current, id.map(|id| id.to_string()), Vec::new()));
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                let Some((mut current, id, content)) = heading.take() else {
+                    continue;
+                };
+                current.id = id.unwrap_or_else(|| {
+                    let mut html = String::new();
+                    html::push_html(&mut html, content.into_iter());
+                    id_from_content_with_style(&html, IdStyle::default())
+                });
+                current.text = current.text.trim().to_owned();
+                headings.push(current);
+            }
+            // Footnote references don't contribute to the ID.
+            Event::FootnoteReference(_) => {}
+            event => {
+                if let Some((current, _, content)) = &mut heading {
+                    if let Event::Text(text) | Event::Code(text) = &event {
+                        current.text.push_str(text);
+                    }
+                    content.push(event);
+                }
+            }
+        }
+    }
+    headings
+}
+
+/// The IDs of the headings in the markdown `text`, in order, before
+/// duplicates are disambiguated.
+fn heading_ids(text: &str) -> Vec<String> {
+    headings(text)
+        .into_iter()
+        .map(|heading| heading.id)
+        .collect()
+}
+
+/// Takes the section of the markdown `text` that starts with the first
+/// heading whose text or ID is `heading`, up to the next heading of the same
+/// or a higher level. Subsections are part of the section.
+///
+/// Returns `None` if there is no such heading.
+pub(crate) fn take_markdown_section<'a>(text: &'a str, heading: &str) -> Option<&'a str> {
+    let range = markdown_section(text, |h| h.text == heading || h.id == heading)?;
+    Some(text[range].trim_end())
+}
+
+/// The range of the section of the markdown `text` under the first heading
+/// that mentions `version`, like `## [1.2.0] - 2024-01-15` or `# v1.2.0`
+/// do for `1.2.0`, up to the next heading of the same or a higher level.
+pub(crate) fn find_version_section(text: &str, version: &str) -> Option<Range<usize>> {
+    markdown_section(text, |h| {
+        h.text
+            .split(|c: char| !(c.is_alphanumeric() || matches!(c, '.' | '-' | '+')))
+            .any(|word| word == version || word.strip_prefix('v') == Some(version))
+    })
+}
+
+/// The range of the section of the markdown `text` under the first heading
+/// matching `predicate`, up to the next heading of the same or a higher
+/// level.
+fn markdown_section(text: &str, predicate: impl Fn(&Heading) -> bool) -> Option<Range<usize>> {
+    let headings = headings(text);
+    let index = headings.iter().position(predicate)?;
+    let level = headings[index].level;
+    let end = headings[index + 1..]
+        .iter()
+        .find(|h| h.level <= level)
+        .map_or(text.len(), |h| h.start);
+    Some(headings[index].start..end)
+}
+
+/// How the rendering of a page changed, as returned by [`diff_renderings`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RenderingDiff {
+    /// Whether the rendered HTML differs.
+    pub changed: bool,
+    /// The heading IDs that only one of the renderings has, so links to
+    /// them need to be checked again.
+    pub changed_ids: BTreeSet<String>,
+}
+
+/// Renders the `old` and `new` markdown of a page with [`render_markdown`]
+/// and compares the results, for example to find out which pages need to be
+/// written again during an incremental build.
+///
+/// Duplicate heading IDs are disambiguated with a counter like rendering
+/// does, so that removing the first of two "Setup" headings changes the ID
+/// `setup-1`.
+pub fn diff_renderings(old: &str, new: &str, smart_punctuation: bool) -> RenderingDiff {
+    fn unique_heading_ids(text: &str) -> BTreeSet<String> {
+        let mut id_counter = HashMap::new();
+        heading_ids(text)
+            .into_iter()
+            .map(|id| {
+                let count = id_counter.entry(id.clone()).or_insert(0);
+                *count += 1;
+                match *count {
+                    1 => id,
+                    count => format!("{id}-{}", count - 1),
+                }
+            })
+            .collect()
+    }
+
+    if old == new {
+        return RenderingDiff::default();
+    }
+    let old_ids = unique_heading_ids(old);
+    let new_ids = unique_heading_ids(new);
+    RenderingDiff {
+        changed: render_markdown(old, smart_punctuation) != render_markdown(new, smart_punctuation),
+        changed_ids: old_ids.symmetric_difference(&new_ids).cloned().collect(),
+    }
+}
+
+/// Normalizes the whitespace of markdown `text`, for formatting it or before
+/// rendering it.
+///
+/// Runs of three or more blank lines are collapsed into a single blank line,
+/// and trailing whitespace is removed from every line. The lines of code
+/// blocks, including their fences, are kept exactly as they are.
+///
+/// Note that this also removes line breaks written as two trailing spaces.
+/// Write them as a trailing backslash instead to keep them.
+pub fn normalize_markdown(text: &str) -> String {
+    let code_blocks: Vec<Range<usize>> = new_cmark_parser(text, false)
+        .into_offset_iter()
+        .filter(|(event, _)| matches!(event, Event::Start(Tag::CodeBlock(_))))
+        .map(|(_, range)| range)
+        .collect();
+
+    let mut normalized = String::with_capacity(text.len());
+    let mut blank_lines = 0;
+    let mut start = 0;
+    for line in text.split_inclusive('\n') {
+        let end = start + line.len();
+        let in_code_block = code_blocks
+            .iter()
+            .any(|block| block.start < end && start < block.end);
+        start = end;
+        if in_code_block {
+            push_blank_lines(&mut normalized, blank_lines);
+            blank_lines = 0;
+            normalized.push_str(line);
+        } else if line.trim().is_empty() {
+            blank_lines += 1;
+        } else {
+            push_blank_lines(&mut normalized, blank_lines);
+            blank_lines = 0;
+            normalized.push_str(line.trim_end());
+            if line.ends_with('\n') {
+                normalized.push('\n');
+            }
+        }
+    }
+    push_blank_lines(&mut normalized, blank_lines);
+    normalized
+}
+
+/// Pushes a run of `count` blank lines, collapsing runs of three or more
+/// into one.
+fn push_blank_lines(s: &mut String, count: usize) {
+    let count = if count >= 3 { 1 } else { count };
+    s.extend(std::iter::repeat('\n').take(count));
+}
+
+/// Computes a fingerprint of the inputs of rendering a page: its `markdown`,
+/// after the preprocessors expanded includes and other directives, and the
+/// options it is rendered with.
+///
+/// Build tools can cache the rendered HTML of a page under this fingerprint,
+/// since identical inputs have the same fingerprint across runs and mdBook
+/// releases, while changing the markdown or any option changes it.
+pub fn content_fingerprint(markdown: &str, smart_punctuation: bool, options: &Markdown) -> u64 {
+    let options = serde_json::to_string(options).expect("markdown options can be serialized");
+    let mut input = Vec::with_capacity(markdown.len() + options.len() + 2);
+    input.extend_from_slice(markdown.as_bytes());
+    // This byte doesn't occur in UTF-8, so it separates the markdown from the
+    // options unambiguously.
+    input.push(0xff);
+    input.push(u8::from(smart_punctuation));
+    input.extend_from_slice(options.as_bytes());
+    fnv1a_hash(&input)
+}
+
+/// The FNV-1a hash of `bytes`, which, unlike the hasher of the standard
+/// library, is stable across releases.
+pub(crate) fn fnv1a_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Metadata given at the top of a page, as parsed by [`split_front_matter`].
+pub type FrontMatter = toml::value::Table;
+
+/// Splits the front matter off the beginning of the markdown `text`, returning
+/// it together with the remaining markdown body.
+///
+/// Front matter is either TOML fenced by `+++` lines, or YAML fenced by `---`
+/// lines. Only the subset of YAML that configuration files commonly use is
+/// understood, the same as for the `yaml:` property of includes: nested
+/// mappings and sequences, `[a, b]` lists, comments, and quoted or plain
+/// scalars.
+///
+/// If there is no closing fence, or the block can't be parsed, the text is
+/// returned unchanged, so that a page starting with a `---` thematic break
+/// still renders it as a horizontal rule.
+pub fn split_front_matter(text: &str) -> (Option<FrontMatter>, &str) {
+    let text_without_bom = text.strip_prefix('\u{feff}').unwrap_or(text);
+    for fence in ["---", "+++"] {
+        let Some(rest) = strip_fence_line(text_without_bom, fence) else {
+            continue;
+        };
+        let mut offset = 0;
+        for line in rest.split_inclusive('\n') {
+            if line.trim_end() == fence {
+                let block = &rest[..offset];
+                let front_matter = if fence == "+++" {
+                    toml::from_str(block).ok()
+                } else {
+                    parse_yaml_front_matter(block)
+                };
+                return match front_matter {
+                    Some(front_matter) => (Some(front_matter), &rest[offset + line.len()..]),
+                    None => (None, text),
+                };
+            }
+            offset += line.len();
+        }
+    }
+    (None, text)
+}
+
+/// Returns the text after the first line if that line consists of `fence`.
+fn strip_fence_line<'a>(text: &'a str, fence: &str) -> Option<&'a str> {
+    let rest = text.strip_prefix(fence)?;
+    rest.strip_prefix("\r\n")
+        .or_else(|| rest.strip_prefix('\n'))
+}
+
+/// Parses YAML front matter into a table.
+///
+/// Returns `None` if the YAML can't be parsed, or isn't a mapping of keys
+/// without whitespace, since the block is then more likely markdown between
+/// two thematic breaks. Keys without a value are left out, since TOML has no
+/// null value.
+fn parse_yaml_front_matter(yaml: &str) -> Option<FrontMatter> {
+    let serde_json::Value::Object(map) = data::parse_yaml(yaml).ok()? else {
+        return None;
+    };
+    if map.is_empty() || map.keys().any(|key| key.contains(char::is_whitespace)) {
+        return None;
+    }
+    match json_to_toml(serde_json::Value::Object(map)) {
+        Some(toml::Value::Table(table)) => Some(table),
+        _ => None,
+    }
+}
+
+/// Converts a JSON value into the equivalent TOML value, leaving out `null`
+/// values.
+fn json_to_toml(value: serde_json::Value) -> Option<toml::Value> {
+    use serde_json::Value;
+
+    Some(match value {
+        Value::Null => return None,
+        Value::Bool(b) => toml::Value::Boolean(b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => toml::Value::Integer(i),
+            None => toml::Value::Float(n.as_f64()?),
+        },
+        Value::String(s) => toml::Value::String(s),
+        Value::Array(items) => {
+            toml::Value::Array(items.into_iter().filter_map(json_to_toml).collect())
+        }
+        Value::Object(map) => toml::Value::Table(
+            map.into_iter()
+                .filter_map(|(key, value)| Some((key, json_to_toml(value)?)))
+                .collect(),
+        ),
+    })
+}
+
+/// Wraps tables in a `.table-wrapper` class to apply overflow-x rules to.
+///
+/// The class can be changed with `table-wrapper-class`, and `table-scroll-class`
+/// adds an inner wrapper, for example to style scroll shadows.
+fn wrap_tables<'a>(event: Event<'a>, options: &Markdown) -> (Option<Event<'a>>, Option<Event<'a>>) {
+    match event {
+        Event::Start(Tag::Table(_)) => {
+            let mut html = format!(
+                r#"<div class="{}">"#,
+                special_escape(&options.table_wrapper_class)
+            );
+            if let Some(scroll_class) = &options.table_scroll_class {
+                write!(html, r#"<div class="{}">"#, special_escape(scroll_class)).unwrap();
+            }
+            (Some(Event::Html(html.into())), Some(event))
+        }
+        Event::End(TagEnd::Table) => {
+            let html = match options.table_scroll_class {
+                Some(_) => "</div></div>",
+                None => "</div>",
+            };
+            (Some(event), Some(Event::Html(html.into())))
+        }
+        _ => (Some(event), None),
+    }
+}
+
+/// Replaces HTML comments whose content is one of the given `directives`
+/// (like `<!-- toc -->`) with an empty `<div data-mdbook-directive="toc">`.
+/// Any other comment is left untouched.
+fn convert_comment_directives<'a>(event: Event<'a>, directives: &[String]) -> Event<'a> {
+    static COMMENT: Lazy<Regex> = Lazy::new(|| Regex::new(r"<!--\s*([\w-]+)\s*-->").unwrap());
+
+    fn convert<'a>(html: CowStr<'a>, directives: &[String]) -> CowStr<'a> {
+        match COMMENT.replace_all(&html, |caps: &regex::Captures<'_>| {
+            if directives.iter().any(|d| d == &caps[1]) {
+                format!(r#"<div data-mdbook-directive="{}"></div>"#, &caps[1])
+            } else {
+                caps[0].to_string()
+            }
+        }) {
+            Cow::Borrowed(_) => html,
+            Cow::Owned(converted) => converted.into(),
+        }
+    }
+
+    if directives.is_empty() {
+        return event;
+    }
+    match event {
+        Event::Html(html) => Event::Html(convert(html, directives)),
+        Event::InlineHtml(html) => Event::InlineHtml(convert(html, directives)),
+        _ => event,
+    }
+}
+
+fn clean_codeblock_headers(event: Event<'_>) -> Event<'_> {
+    match event {
+        Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref info))) => {
+            let info: String = info
+                .chars()
+                .map(|x| match x {
+                    ' ' | '\t' => ',',
+                    _ => x,
+                })
+                .filter(|ch| !ch.is_whitespace())
+                .collect();
+
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(CowStr::from(info))))
+        }
+        _ => event,
+    }
+}
+
+/// Names the language of the fenced code blocks without one that
+/// [`guess_code_language`] recognizes with at least `min_confidence`.
+fn detect_code_languages(events: Vec<Event<'_>>, min_confidence: f64) -> Vec<Event<'_>> {
+    let mut result = Vec::with_capacity(events.len());
+    // The index of the start of the open code block without a language.
+    let mut open = None;
+    let mut code = String::new();
+    for event in events {
+        match &event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) if info.is_empty() => {
+                open = Some(result.len());
+                code.clear();
+            }
+            Event::Text(text) if open.is_some() => code.push_str(text),
+            Event::End(TagEnd::CodeBlock) => {
+                if let Some(start) = open.take() {
+                    if let Some(lang) = guess_code_language(&code, min_confidence) {
+                        result[start] =
+                            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(lang.into())));
+                    }
+                }
+            }
+            _ => {}
+        }
+        result.push(event);
+    }
+    result
+}
+
+/// Guesses the language of `code` from the lines matching the keyword
+/// patterns of a language.
+///
+/// Returns `None` unless at least two lines match, and at least
+/// `min_confidence` of the matches are of the same language.
+fn guess_code_language(code: &str, min_confidence: f64) -> Option<&'static str> {
+    static SIGNALS: Lazy<Vec<(&str, Regex)>> = Lazy::new(|| {
+        [
+            ("rust", r"^\s*(pub(\(\w+\))?\s+)?(async\s+)?fn\s+\w+\s*[<(]"),
+            ("rust", r"^\s*let\s+(mut\s+)?\w+\s*(:[^=]+)?=.*;\s*$"),
+            (
+                "rust",
+                r"^\s*(pub(\(\w+\))?\s+)?(struct|enum|trait|impl|mod)\b",
+            ),
+            ("rust", r"^\s*use\s+\w+::"),
+            ("rust", r"\b\w+!\("),
+            ("rust", r"^\s*#!?\[\w+"),
+            ("python", r"^\s*def\s+\w+\s*\(.*\)\s*(->.*)?:\s*$"),
+            ("python", r"^\s*(import\s+\w+\s*$|from\s+[\w.]+\s+import\b)"),
+            ("python", r"^\s*class\s+\w+(\(.*\))?:\s*$"),
+            ("python", r"^\s*(elif|except|with)\b.*:\s*$"),
+            ("python", r"^\s*print\("),
+            ("javascript", r"^\s*(const|var)\s+\w+\s*="),
+            ("javascript", r"^\s*(export\s+)?(async\s+)?function\b"),
+            ("javascript", r"\bconsole\.\w+\("),
+            ("javascript", r"^\s*(import|export)\b.*\bfrom\s+['\x22]"),
+            ("javascript", r"\b(document|window)\.\w+"),
+            ("bash", r"^#!/(usr/)?bin/(env\s+)?(ba)?sh"),
+            (
+                "bash",
+                r"^\s*(export\s+\w+=|echo\s|cd\s|sudo\s|mkdir\s|curl\s)",
+            ),
+            ("toml", r"^\[{1,2}[\w.\-\x22]+\]{1,2}\s*$"),
+            ("toml", r"^[\w\-]+\s*=\s*\x22"),
+            ("json", r"^\s*\x22[^\x22]+\x22\s*:"),
+        ]
+        .into_iter()
+        .map(|(lang, pattern)| (lang, Regex::new(pattern).unwrap()))
+        .collect()
+    });
+
+    let mut scores: Vec<(&str, usize)> = Vec::new();
+    for line in code.lines() {
+        for (lang, signal) in SIGNALS.iter() {
+            if signal.is_match(line) {
+                match scores.iter_mut().find(|(l, _)| l == lang) {
+                    Some((_, score)) => *score += 1,
+                    None => scores.push((lang, 1)),
+                }
+            }
+        }
+    }
+    let total: usize = scores.iter().map(|(_, score)| score).sum();
+    let &(lang, best) = scores.iter().max_by_key(|(_, score)| *score)?;
+    (best >= 2 && best as f64 >= min_confidence * total as f64).then_some(lang)
+}
+
+/// Renders fenced code blocks with a language with `prefix` instead of
+/// `language-` in front of the language class.
+fn prefix_code_block_languages<'a>(events: Vec<Event<'a>>, prefix: &str) -> Vec<Event<'a>> {
+    let mut replaced = false;
+    events
+        .into_iter()
+        .map(|event| match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref info))) if !info.is_empty() => {
+                replaced = true;
+                // Like `pulldown_cmark`, only the first token names the language.
+                let lang = info.split(' ').next().unwrap();
+                Event::Html(
+                    format!(
+                        "<pre><code class=\"{}{}\">",
+                        special_escape(prefix),
+                        special_escape(lang)
+                    )
+                    .into(),
+                )
+            }
+            Event::End(TagEnd::CodeBlock) if replaced => {
+                replaced = false;
+                Event::Html("</code></pre>\n".into())
+            }
+            event => event,
+        })
+        .collect()
+}
+
+/// Prints a "backtrace" of some `Error`.
+pub fn log_backtrace(e: &Error) {
+    error!("Error: {}", e);
+
+    for cause in e.chain().skip(1) {
+        error!("\tCaused By: {}", cause);
+    }
+}
+
+pub(crate) fn bracket_escape(mut s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    let needs_escape: &[char] = &['<', '>'];
+    while let Some(next) = s.find(needs_escape) {
+        escaped.push_str(&s[..next]);
+        let ch = s[next..].chars().next().unwrap();
+        match ch {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            _ => unreachable!(),
+        }
+        // Advance past the whole character, which isn't always a single byte.
+        s = &s[next + ch.len_utf8()..];
+    }
+    escaped.push_str(s);
+    escaped
+}
+
+pub(crate) fn special_escape(mut s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    let needs_escape: &[char] = &['<', '>', '\'', '"', '\\', '&'];
+    while let Some(next) = s.find(needs_escape) {
+        escaped.push_str(&s[..next]);
+        let ch = s[next..].chars().next().unwrap();
+        match ch {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '\'' => escaped.push_str("&#39;"),
+            '\\' => escaped.push_str("&#92;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => unreachable!(),
+        }
+        // Advance past the whole character, which isn't always a single byte.
+        s = &s[next + ch.len_utf8()..];
+    }
+    escaped.push_str(s);
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bracket_escape, special_escape};
+
+    mod render_markdown {
+        use super::super::{
+            collapse_footnote_references, expand_inline_footnotes, render_markdown,
+            render_markdown_with_options, render_markdown_with_path,
+            render_markdown_with_path_and_redirects, render_markdown_with_transform,
+        };
+        use crate::config::{
+            EmptyCodeBlocks, FootnoteStyle, InternalLinkStyle, Markdown, StrikethroughElement,
+        };
+        use pulldown_cmark::{Event, Tag, TagEnd};
+        use std::borrow::Cow;
+        use std::collections::HashMap;
+        use std::path::Path;
+
+        #[test]
+        fn preserves_external_links() {
+            assert_eq!(
+                render_markdown("[example](https://www.rust-lang.org/)", false),
+                "<p><a href=\"https://www.rust-lang.org/\">example</a></p>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_adjust_markdown_links() {
+            assert_eq!(
+                render_markdown("[example](example.md)", false),
+                "<p><a href=\"example.html\">example</a></p>\n"
+            );
+            assert_eq!(
+                render_markdown("[example_anchor](example.md#anchor)", false),
+                "<p><a href=\"example.html#anchor\">example_anchor</a></p>\n"
+            );
+
+            // this anchor contains 'md' inside of it
+            assert_eq!(
+                render_markdown("[phantom data](foo.html#phantomdata)", false),
+                "<p><a href=\"foo.html#phantomdata\">phantom data</a></p>\n"
+            );
+        }
+
+        #[test]
+        fn it_leaves_protocol_relative_links_untouched() {
+            assert_eq!(
+                render_markdown("[cdn](//cdn.example.com/lib.js)", false),
+                "<p><a href=\"//cdn.example.com/lib.js\">cdn</a></p>\n"
+            );
+            assert_eq!(
+                render_markdown("[root](/root/path.md)", false),
+                "<p><a href=\"/root/path.html\">root</a></p>\n"
+            );
+            assert_eq!(
+                render_markdown("[web](https://host/path.md)", false),
+                "<p><a href=\"https://host/path.md\">web</a></p>\n"
+            );
+
+            let path = Some(Path::new("first/page.md"));
+            assert_eq!(
+                render_markdown_with_path("[cdn](//cdn.example.com/lib.js)", false, path),
+                "<p><a href=\"//cdn.example.com/lib.js\">cdn</a></p>\n"
+            );
+            assert_eq!(
+                render_markdown_with_path("![img](//cdn.example.com/img.png)", false, path),
+                "<p><img src=\"//cdn.example.com/img.png\" alt=\"img\" /></p>\n"
+            );
+            assert_eq!(
+                render_markdown_with_path(r#"<a href="//cdn.example.com/">raw</a>"#, false, path),
+                "<p><a href=\"//cdn.example.com/\">raw</a></p>\n"
+            );
+            assert_eq!(
+                render_markdown_with_path("[web](https://host/path)", false, path),
+                "<p><a href=\"https://host/path\">web</a></p>\n"
+            );
+            assert_eq!(
+                render_markdown_with_path("[other](other.md)", false, path),
+                "<p><a href=\"#first/other\">other</a></p>\n"
+            );
+        }
+
+        #[test]
+        fn it_prefixes_fragment_links_on_print_page() {
+            let path = Some(Path::new("first/page.md"));
+            assert_eq!(
+                render_markdown_with_path("[local](#local)", false, path),
+                "<p><a href=\"#first/page:local\">local</a></p>\n"
+            );
+            assert_eq!(
+                render_markdown_with_path(r##"<a href="#local">raw</a>"##, false, path),
+                "<p><a href=\"#first/page:local\">raw</a></p>\n"
+            );
+            assert_eq!(
+                render_markdown_with_path(
+                    "<div>\n<a class=\"x\" href=\"#local\" name=\"anchor\">raw</a>\n</div>",
+                    false,
+                    path
+                ),
+                "<div>\n<a class=\"x\" href=\"#first/page:local\" name=\"first/page:anchor\">raw</a>\n</div>"
+            );
+            assert_eq!(
+                render_markdown_with_path(r##"<a href="#local">raw</a>"##, false, None),
+                "<p><a href=\"#local\">raw</a></p>\n"
+            );
+        }
+
+        #[test]
+        fn it_gives_distinct_print_ids_to_nested_pages() {
+            let path = Some(Path::new("index.md"));
+            assert_eq!(
+                render_markdown_with_path(
+                    "[1](a/b.md) [2](a-b.md) [3](a/b.md#c) [4](a-b.md#c) \
+                     [5](a--b.md) [6](intro.md#setup) [7](intro-setup.md)",
+                    false,
+                    path
+                ),
+                "<p><a href=\"#a/b\">1</a> <a href=\"#a-b\">2</a> \
+                 <a href=\"#a/b:c\">3</a> <a href=\"#a-b:c\">4</a> \
+                 <a href=\"#a--b\">5</a> <a href=\"#intro:setup\">6</a> \
+                 <a href=\"#intro-setup\">7</a></p>\n"
+            );
+        }
+
+        #[test]
+        fn it_leaves_contact_scheme_links_untouched() {
+            for dest in [
+                "mailto:someone@example.com",
+                "MAILTO:someone@example.com",
+                "tel:+1-555-0100",
+                "Tel:555/0100.html",
+                "sms:+15550100?body=hello",
+            ] {
+                for path in [None, Some(Path::new("first/page.md"))] {
+                    assert_eq!(
+                        render_markdown_with_path(&format!("[contact]({dest})"), false, path),
+                        format!("<p><a href=\"{dest}\">contact</a></p>\n")
+                    );
+                    assert_eq!(
+                        render_markdown_with_path(&format!("<{dest}>"), false, path),
+                        format!("<p><a href=\"{dest}\">{dest}</a></p>\n")
+                    );
+                    assert_eq!(
+                        render_markdown_with_path(
+                            &format!(r#"<a href="{dest}">contact</a>"#),
+                            false,
+                            path
+                        ),
+                        format!("<p><a href=\"{dest}\">contact</a></p>\n")
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn it_links_to_index_pages() {
+            for link in [
+                "chapter/",
+                "chapter/README.md",
+                "chapter/readme.md",
+                "chapter/index.md",
+            ] {
+                assert_eq!(
+                    render_markdown(&format!("[Chapter]({link})"), false),
+                    "<p><a href=\"chapter/index.html\">Chapter</a></p>\n"
+                );
+            }
+            assert_eq!(
+                render_markdown("[Chapter](chapter/README.md#intro)", false),
+                "<p><a href=\"chapter/index.html#intro\">Chapter</a></p>\n"
+            );
+            assert_eq!(
+                render_markdown("[Top](../README.md)", false),
+                "<p><a href=\"../index.html\">Top</a></p>\n"
+            );
+            assert_eq!(
+                render_markdown("[Other](chapter/other.md)", false),
+                "<p><a href=\"chapter/other.html\">Other</a></p>\n"
+            );
+            assert_eq!(
+                render_markdown("[Site](https://example.com/)", false),
+                "<p><a href=\"https://example.com/\">Site</a></p>\n"
+            );
+
+            let options = Markdown {
+                index_page: "home".to_string(),
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options("[Chapter](chapter/README.md)", false, &options),
+                "<p><a href=\"chapter/home.html\">Chapter</a></p>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_link_with_trailing_slashes() {
+            let options = Markdown {
+                internal_link_style: InternalLinkStyle::TrailingSlash,
+                ..Markdown::default()
+            };
+            for (link, expected) in [
+                ("example.md#a", "example/#a"),
+                ("chapter/other.html", "chapter/other/"),
+                ("chapter/README.md#intro", "chapter/#intro"),
+                ("chapter/", "chapter/"),
+                ("../index.md", "../"),
+                ("README.md", "./"),
+                ("myindex.md", "myindex/"),
+                ("#a", "#a"),
+                (
+                    "https://example.com/page.html",
+                    "https://example.com/page.html",
+                ),
+            ] {
+                assert_eq!(
+                    render_markdown_with_options(&format!("[Link]({link})"), false, &options),
+                    format!("<p><a href=\"{expected}\">Link</a></p>\n")
+                );
+            }
+            assert_eq!(
+                render_markdown_with_options("<a href=\"example.md#a\">Link</a>", false, &options),
+                "<p><a href=\"example/#a\">Link</a></p>\n"
+            );
+            assert_eq!(
+                render_markdown("[Link](example.md#a)", false),
+                "<p><a href=\"example.html#a\">Link</a></p>\n"
+            );
+
+            // The print page links to its own sections either way.
+            assert_eq!(
+                render_markdown_with_path_and_redirects(
+                    "[Link](example.md#a)",
+                    false,
+                    Some(Path::new("chapter.md")),
+                    &HashMap::new(),
+                    &options,
+                    None,
+                ),
+                "<p><a href=\"#example:a\">Link</a></p>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_wrap_tables() {
+            let src = r#"
+| Original        | Punycode        | Punycode + Encoding |
+|-----------------|-----------------|---------------------|
+| føø             | f-5gaa          | f_5gaa              |
+"#;
+            let out = r#"
+<div class="table-wrapper"><table><thead><tr><th>Original</th><th>Punycode</th><th>Punycode + Encoding</th></tr></thead><tbody>
+<tr><td>føø</td><td>f-5gaa</td><td>f_5gaa</td></tr>
+</tbody></table>
+</div>
+"#.trim();
+            assert_eq!(render_markdown(src, false), out);
+        }
+
+        #[test]
+        fn it_can_wrap_tables_with_custom_classes() {
+            let src = "| a |\n|---|\n| b |\n";
+            let options = Markdown {
+                table_wrapper_class: "overflow-auto".to_string(),
+                table_scroll_class: Some("table-scroll".to_string()),
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(src, false, &options),
+                "<div class=\"overflow-auto\"><div class=\"table-scroll\"><table><thead><tr><th>a</th></tr></thead><tbody>\n\
+                 <tr><td>b</td></tr>\n\
+                 </tbody></table>\n\
+                 </div></div>"
+            );
+        }
+
+        #[test]
+        fn it_can_render_footnotes_as_sidenotes() {
+            let input = r#"
+Some text[^note] and more[^other], then the first again[^note].
+
+[^note]: The *note*.
+
+[^other]: Another note.
+"#;
+            let expected = r##"<p>Some text<sup class="footnote-reference"><a href="#sn-note">1</a></sup><span class="sidenote" id="sn-note"><sup class="footnote-definition-label">1</sup> The <em>note</em>.</span> and more<sup class="footnote-reference"><a href="#sn-other">2</a></sup><span class="sidenote" id="sn-other"><sup class="footnote-definition-label">2</sup> Another note.</span>, then the first again<sup class="footnote-reference"><a href="#sn-note">1</a></sup>.</p>
+"##;
+            let options = Markdown {
+                sidenotes: true,
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                expected
+            );
+        }
+
+        #[test]
+        fn it_can_render_footnote_references_in_brackets() {
+            let input = "Text[^b] and more[^a].\n\n[^a]: First.\n[^b]: Second.\n";
+            let superscript = render_markdown_with_options(input, false, &Markdown::default());
+            assert_eq!(
+                superscript,
+                "<p>Text<sup class=\"footnote-reference\"><a href=\"#b\">1</a></sup> and more\
+                 <sup class=\"footnote-reference\"><a href=\"#a\">2</a></sup>.</p>\n\
+                 <div class=\"footnote-definition\" id=\"a\"><sup class=\"footnote-definition-label\">2</sup>\n\
+                 <p>First.</p>\n</div>\n\
+                 <div class=\"footnote-definition\" id=\"b\"><sup class=\"footnote-definition-label\">1</sup>\n\
+                 <p>Second.</p>\n</div>\n"
+            );
+
+            let options = Markdown {
+                footnote_style: FootnoteStyle::Bracketed,
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                superscript
+                    .replace(
+                        "<sup class=\"footnote-reference\"><a href=\"#b\">1</a></sup>",
+                        "<a class=\"footnote-reference\" href=\"#b\">[1]</a>"
+                    )
+                    .replace(
+                        "<sup class=\"footnote-reference\"><a href=\"#a\">2</a></sup>",
+                        "<a class=\"footnote-reference\" href=\"#a\">[2]</a>"
+                    )
+            );
+        }
+
+        #[test]
+        fn it_can_collapse_repeated_footnote_references() {
+            // The parser reads `[^a][^a]` as a link, so build the events directly.
+            let events = vec![
+                Event::Text("Text".into()),
+                Event::FootnoteReference("a".into()),
+                Event::FootnoteReference("a".into()),
+                Event::FootnoteReference("b".into()),
+                Event::FootnoteReference("a".into()),
+            ];
+            assert_eq!(
+                collapse_footnote_references(events),
+                vec![
+                    Event::Text("Text".into()),
+                    Event::FootnoteReference("a".into()),
+                    Event::FootnoteReference("b".into()),
+                    Event::FootnoteReference("a".into()),
+                ]
+            );
+
+            let options = Markdown {
+                collapse_footnote_references: true,
+                ..Markdown::default()
+            };
+            let input = "Text[^a] and[^a].\n\n[^a]: First.\n";
+            let rendered = render_markdown_with_options(input, false, &options);
+            assert_eq!(
+                rendered,
+                render_markdown_with_options(input, false, &Markdown::default())
+            );
+            assert_eq!(rendered.matches("class=\"footnote-reference\"").count(), 2);
+        }
+
+        #[test]
+        fn it_can_render_inline_footnotes() {
+            let options = Markdown {
+                inline_footnotes: true,
+                ..Markdown::default()
+            };
+            let input = "Text.^[A *short* note.]\n";
+            let expected = "Text.[^inline-1]\n\n[^inline-1]: A *short* note.\n";
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                render_markdown_with_options(expected, false, &Markdown::default())
+            );
+
+            // Names already used on the page are skipped.
+            let input = "One^[See [the guide](guide.md).] and two^[Second.] and \
+                         three[^inline-1].\n\n[^inline-1]: Third.\n";
+            assert_eq!(
+                expand_inline_footnotes(input),
+                "One[^inline-2] and two[^inline-3] and three[^inline-1].\n\n\
+                 [^inline-1]: Third.\n\n\
+                 [^inline-2]: See [the guide](guide.md).\n\n\
+                 [^inline-3]: Second.\n"
+            );
+            let rendered = render_markdown_with_options(input, false, &options);
+            assert_eq!(rendered.matches("class=\"footnote-definition\"").count(), 3);
+
+            let input = "`^[code]` and \\^[escaped] and ^[unclosed\n";
+            assert_eq!(expand_inline_footnotes(input), input);
+            assert!(matches!(
+                expand_inline_footnotes("No footnotes."),
+                Cow::Borrowed(_)
+            ));
+        }
+
+        #[test]
+        fn it_can_list_footnotes_per_section() {
+            let input = "Intro[^a].\n\n\
+                         ## First\n\nText[^b] and[^a].\n\n\
+                         ## Second\n\nMore[^a].\n\n\
+                         [^a]: Shared.\n[^b]: Only first.\n[^c]: Unused.\n";
+            let options = Markdown {
+                footnote_sections: true,
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                "<p>Intro<sup class=\"footnote-reference\"><a href=\"#s0-a\">1</a></sup>.</p>\n\
+                 <div class=\"footnotes\">\n\
+                 <div class=\"footnote-definition\" id=\"s0-a\"><sup class=\"footnote-definition-label\">1</sup>\n\
+                 <p>Shared.</p>\n</div>\n</div>\n\
+                 <h2>First</h2>\n\
+                 <p>Text<sup class=\"footnote-reference\"><a href=\"#s1-b\">1</a></sup> and\
+                 <sup class=\"footnote-reference\"><a href=\"#s1-a\">2</a></sup>.</p>\n\
+                 <div class=\"footnotes\">\n\
+                 <div class=\"footnote-definition\" id=\"s1-b\"><sup class=\"footnote-definition-label\">1</sup>\n\
+                 <p>Only first.</p>\n</div>\n\
+                 <div class=\"footnote-definition\" id=\"s1-a\"><sup class=\"footnote-definition-label\">2</sup>\n\
+                 <p>Shared.</p>\n</div>\n</div>\n\
+                 <h2>Second</h2>\n\
+                 <p>More<sup class=\"footnote-reference\"><a href=\"#s2-a\">1</a></sup>.</p>\n\
+                 <div class=\"footnotes\">\n\
+                 <div class=\"footnote-definition\" id=\"s2-a\"><sup class=\"footnote-definition-label\">1</sup>\n\
+                 <p>Shared.</p>\n</div>\n\
+                 <div class=\"footnote-definition\" id=\"s2-c\"><sup class=\"footnote-definition-label\">2</sup>\n\
+                 <p>Unused.</p>\n</div>\n</div>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_render_subscript_and_superscript() {
+            let options = Markdown {
+                subscript_superscript: true,
+                ..Markdown::default()
+            };
+            let input = "H~2~O, x^2^, a ~b~ c, and ~~strike~~";
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                "<p>H<sub>2</sub>O, x<sup>2</sup>, a <sub>b</sub> c, and <del>strike</del></p>\n"
+            );
+
+            let input = "~unbalanced, 2^10, ^[note]^, `x^2^`, and ~~~x~~~";
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                render_markdown_with_options(input, false, &Markdown::default())
+            );
+        }
+
+        #[test]
+        fn it_runs_a_custom_transform() {
+            assert_eq!(
+                render_markdown_with_transform(
+                    "Hello *world* and [a link](page.md).",
+                    false,
+                    &Markdown::default(),
+                    |event| match event {
+                        Event::Text(text) => vec![Event::Text(text.to_uppercase().into())],
+                        event => vec![event],
+                    }
+                ),
+                "<p>HELLO <em>WORLD</em> AND <a href=\"page.html\">A LINK</a>.</p>\n"
+            );
+
+            // Events can be replaced or dropped, and footnotes are rewritten
+            // afterwards.
+            let options = Markdown {
+                footnote_style: FootnoteStyle::Bracketed,
+                ..Markdown::default()
+            };
+            let rendered = render_markdown_with_transform(
+                "*Note*[^a]\n\n[^a]: A.\n\n[^b]: B.\n",
+                false,
+                &options,
+                |event| match event {
+                    Event::Start(Tag::Emphasis) | Event::End(TagEnd::Emphasis) => vec![],
+                    Event::FootnoteReference(_) => {
+                        vec![
+                            Event::Text(" ".into()),
+                            Event::FootnoteReference("b".into()),
+                        ]
+                    }
+                    event => vec![event],
+                },
+            );
+            assert_eq!(
+                rendered,
+                "<p>Note <a class=\"footnote-reference\" href=\"#b\">[1]</a></p>\n\
+                 <div class=\"footnote-definition\" id=\"a\">\
+                 <sup class=\"footnote-definition-label\">2</sup>\n<p>A.</p>\n</div>\n\
+                 <div class=\"footnote-definition\" id=\"b\">\
+                 <sup class=\"footnote-definition-label\">1</sup>\n<p>B.</p>\n</div>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_sanitize_raw_html() {
+            let options = Markdown {
+                sanitize_html: true,
+                ..Markdown::default()
+            };
+            let input = "Some <strong class=\"x\" onclick=\"steal()\">bold</strong> text\
+                         <script>alert(1)</script>.\n\n\
+                         <a href=\"javascript:alert(1)\" title=\"t\">link</a> \
+                         [md](javascript:alert(2)) <a href=\"page.md\">ok</a>\n\n\
+                         <div style=\"color: red\"\n     onmouseover=\"alert(3)\">\n\
+                         <blink>Hi</blink>\n<script>\nalert(4)\n</script>\n</div>\n";
+            let expected = "<p>Some <strong class=\"x\">bold</strong> text.</p>\n\
+                            <p><a title=\"t\">link</a> <a href=\"#\">md</a> \
+                            <a href=\"page.html\">ok</a></p>\n\
+                            <div>\nHi\n\n</div>\n";
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                expected
+            );
+
+            // Without the option, raw HTML is kept as it is.
+            let html = render_markdown_with_options(input, false, &Markdown::default());
+            assert!(html.contains("<script>alert(1)</script>"));
+
+            // Only the configured tags and attributes are kept.
+            let options = Markdown {
+                sanitize_html: true,
+                sanitize_html_tags: vec!["em".to_string(), "script".to_string()],
+                sanitize_html_attributes: vec!["style".to_string()],
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(
+                    "<em style=\"a\" class=\"b\">x</em> <strong>y</strong> <script>z</script>",
+                    false,
+                    &options
+                ),
+                "<p><em style=\"a\">x</em> y </p>\n"
+            );
+
+            // Comments are removed, including ones that browsers end early.
+            let options = Markdown {
+                sanitize_html: true,
+                ..Markdown::default()
+            };
+            for (input, expected) in [
+                ("a <!-- b --> c", "<p>a  c</p>\n"),
+                (
+                    "a <!--><script>alert(1)</script>--> c",
+                    "<p>a --&gt; c</p>\n",
+                ),
+                (
+                    "a <!-- --!><img src=x onerror=alert(2)> --> c",
+                    "<p>a <img src=x> --> c</p>\n",
+                ),
+                ("<!-- a --!><script>alert(3)</script>\n", "\n"),
+            ] {
+                assert_eq!(
+                    render_markdown_with_options(input, false, &options),
+                    expected,
+                    "{input}"
+                );
+            }
+        }
+
+        #[test]
+        fn it_can_limit_the_width_of_images() {
+            let options = Markdown {
+                max_image_width: Some("100%".to_string()),
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options("![Cat](cat.png \"A cat\")", false, &options),
+                "<p><img src=\"cat.png\" alt=\"Cat\" title=\"A cat\" \
+                 style=\"max-width:100%;height:auto\" /></p>\n"
+            );
+            assert_eq!(
+                render_markdown_with_options("<img src=\"cat.png\">", false, &options),
+                "<img src=\"cat.png\" style=\"max-width:100%;height:auto\">"
+            );
+            // Images with a size or style of their own, and code, are left alone.
+            for input in [
+                "<img src=\"cat.png\" style=\"max-width:50%\">",
+                "<img src=\"cat.png\" width=\"20\" />",
+                "<img\n  src=\"cat.png\" HEIGHT=\"20\">",
+                "`<img src=\"cat.png\">`",
+            ] {
+                assert_eq!(
+                    render_markdown_with_options(input, false, &options),
+                    render_markdown(input, false)
+                );
+            }
+            assert_eq!(
+                render_markdown("![Cat](cat.png)", false),
+                "<p><img src=\"cat.png\" alt=\"Cat\" /></p>\n"
+            );
+            let options = Markdown {
+                max_image_width: Some("40rem".to_string()),
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options("![](a.png)", false, &options),
+                "<p><img src=\"a.png\" alt=\"\" style=\"max-width:40rem;height:auto\" /></p>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_detect_code_languages() {
+            let options = Markdown {
+                detect_code_languages: true,
+                ..Markdown::default()
+            };
+            let rust = "```\nfn main() {\n    let x = 5;\n    println!(\"{x}\");\n}\n```\n";
+            assert_eq!(
+                render_markdown_with_options(rust, false, &options),
+                "<pre><code class=\"language-rust\">fn main() {\n    let x = 5;\n    \
+                 println!(\"{x}\");\n}\n</code></pre>\n"
+            );
+            assert_eq!(
+                render_markdown_with_options(rust, false, &Markdown::default()),
+                "<pre><code>fn main() {\n    let x = 5;\n    \
+                 println!(\"{x}\");\n}\n</code></pre>\n"
+            );
+
+            let python = "```\nimport os\n\ndef main():\n    print(os.getcwd())\n```\n";
+            assert!(render_markdown_with_options(python, false, &options)
+                .starts_with("<pre><code class=\"language-python\">"));
+
+            // Languages that are named, blocks with too few keywords, and
+            // blocks with keywords of several languages are left alone.
+            for input in [
+                "```text\nfn main() {\n    let x = 5;\n}\n```\n",
+                "```\nlet x = 5;\n```\n",
+                "```\nlet x = 5;\nconsole.log(x);\nconst y = 6;\nprintln!(y);\n```\n",
+                "```\nx = 1\ny = 2\n```\n",
+            ] {
+                assert_eq!(
+                    render_markdown_with_options(input, false, &options),
+                    render_markdown_with_options(input, false, &Markdown::default())
+                );
+            }
+
+            // A lower confidence guesses the language with most keywords.
+            let options = Markdown {
+                detect_code_languages: true,
+                detect_code_languages_confidence: 0.5,
+                ..Markdown::default()
+            };
+            let mixed = "```\nfn main() {}\nlet x = 5;\nconsole.log(x);\n```\n";
+            assert!(render_markdown_with_options(mixed, false, &options)
+                .starts_with("<pre><code class=\"language-rust\">"));
+        }
+
+        #[test]
+        fn it_can_choose_the_strikethrough_element() {
+            let input = "~~Old~~ price, ~~`code`~~";
+            assert_eq!(
+                render_markdown_with_options(input, false, &Markdown::default()),
+                "<p><del>Old</del> price, <del><code>code</code></del></p>\n"
+            );
+            let options = Markdown {
+                strikethrough_element: StrikethroughElement::S,
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                "<p><s>Old</s> price, <s><code>code</code></s></p>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_restrict_autolink_schemes() {
+            let input = "<https://example.com> <http://example.com> <ftp://example.com> \
+                         <someone@example.com>";
+            assert_eq!(
+                render_markdown_with_options(input, false, &Markdown::default()),
+                "<p><a href=\"https://example.com\">https://example.com</a> \
+                 <a href=\"http://example.com\">http://example.com</a> \
+                 <a href=\"ftp://example.com\">ftp://example.com</a> \
+                 <a href=\"mailto:someone@example.com\">someone@example.com</a></p>\n"
+            );
+
+            let options = Markdown {
+                autolink_schemes: Some(vec!["HTTPS".to_owned()]),
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                "<p><a href=\"https://example.com\">https://example.com</a> \
+                 &lt;http://example.com&gt; &lt;ftp://example.com&gt; \
+                 &lt;someone@example.com&gt;</p>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_link_issue_references() {
+            let options = Markdown {
+                issue_link_template: Some("https://github.com/org/repo/issues/{n}".to_owned()),
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options("Fixed in #123, see (#45).", false, &options),
+                "<p>Fixed in <a href=\"https://github.com/org/repo/issues/123\">#123</a>, \
+                 see (<a href=\"https://github.com/org/repo/issues/45\">#45</a>).</p>\n"
+            );
+
+            let input = "word#123 #12a `#123` [#7](other.md)\n\n## Issue #8\n\n```\n#9\n```\n";
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                render_markdown_with_options(input, false, &Markdown::default())
+            );
+        }
+
+        #[test]
+        fn it_can_render_time_elements() {
+            let input = "Released on 2024-01-15, not on 2024-13-40 or 2023-02-29.\n\n\
+                         Version 12024-01-150 and 2024-01-15-rc1, `2024-01-15` and (2024-02-29).\n\n\
+                         ```\n2024-01-15\n```\n";
+            let options = Markdown {
+                time_elements: true,
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                "<p>Released on <time datetime=\"2024-01-15\">2024-01-15</time>, \
+                 not on 2024-13-40 or 2023-02-29.</p>\n\
+                 <p>Version 12024-01-150 and 2024-01-15-rc1, <code>2024-01-15</code> and \
+                 (<time datetime=\"2024-02-29\">2024-02-29</time>).</p>\n\
+                 <pre><code>2024-01-15\n</code></pre>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_give_paragraphs_ids() {
+            let input = "A note. {#note}\n\nAnother *note*.\n{#Note}\n\n\
+                         No id here.\n\n`code {#code}`\n\n> Quoted {#content}\n";
+            let options = Markdown {
+                paragraph_ids: true,
+                reserved_ids: vec!["content".to_string()],
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                "<p id=\"note\">A note.</p>\n\
+                 <p id=\"note-1\">Another <em>note</em>.</p>\n\
+                 <p>No id here.</p>\n\
+                 <p><code>code {#code}</code></p>\n\
+                 <blockquote>\n<p id=\"content-1\">Quoted</p>\n</blockquote>\n"
+            );
+            assert_eq!(
+                render_markdown_with_options("A note. {#note}", false, &Markdown::default()),
+                "<p>A note. {#note}</p>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_number_paragraphs() {
+            let input = "First.\n\nSecond. {#named}\n\n## Reference\n\nThird.\n\n\
+                         ### Details\n\n> Fourth.\n\n## Other\n\nFifth.\n";
+            let options = Markdown {
+                paragraph_ids: true,
+                sequential_paragraph_ids: true,
+                ..Markdown::default()
+            };
+            let html = render_markdown_with_options(input, false, &options);
+            assert_eq!(
+                html,
+                "<p id=\"p-1\">First.</p>\n\
+                 <p id=\"named\">Second.</p>\n\
+                 <h2>Reference</h2>\n\
+                 <p id=\"p-3\">Third.</p>\n\
+                 <h3>Details</h3>\n\
+                 <blockquote>\n<p id=\"p-4\">Fourth.</p>\n</blockquote>\n\
+                 <h2>Other</h2>\n\
+                 <p id=\"p-5\">Fifth.</p>\n"
+            );
+            // The IDs are the same every time the page is rendered.
+            assert_eq!(render_markdown_with_options(input, false, &options), html);
+
+            let options = Markdown {
+                sequential_paragraph_ids: true,
+                sequential_paragraph_ids_section: Some("reference".to_string()),
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                "<p>First.</p>\n\
+                 <p>Second. {#named}</p>\n\
+                 <h2>Reference</h2>\n\
+                 <p id=\"p-1\">Third.</p>\n\
+                 <h3>Details</h3>\n\
+                 <blockquote>\n<p id=\"p-2\">Fourth.</p>\n</blockquote>\n\
+                 <h2>Other</h2>\n\
+                 <p>Fifth.</p>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_demote_extra_h1() {
+            let input = "# First\n\n## Sub\n\n# Second {#second}\n";
+            let options = Markdown {
+                single_h1: true,
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                "<h1>First</h1>\n<h2>Sub</h2>\n<h2 id=\"second\">Second</h2>\n"
+            );
+
+            let options = Markdown {
+                single_h1: true,
+                page_title_is_h1: true,
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                "<h2>First</h2>\n<h2>Sub</h2>\n<h2 id=\"second\">Second</h2>\n"
+            );
+
+            assert_eq!(
+                render_markdown_with_options(input, false, &Markdown::default()),
+                "<h1>First</h1>\n<h2>Sub</h2>\n<h1 id=\"second\">Second</h1>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_wrap_captioned_images_in_figures() {
+            let options = Markdown {
+                captioned_figures: true,
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(
+                    r#"![A cat](cat.png "A <sleeping> cat")"#,
+                    false,
+                    &options
+                ),
+                "<figure><img src=\"cat.png\" alt=\"A cat\" title=\"A &lt;sleeping&gt; cat\" />\
+                 <figcaption>A &lt;sleeping&gt; cat</figcaption></figure>\n"
+            );
+            assert_eq!(
+                render_markdown_with_options(
+                    r#"See ![A cat](cat.png "A cat") here."#,
+                    false,
+                    &options
+                ),
+                "<p>See <img src=\"cat.png\" alt=\"A cat\" title=\"A cat\" /> here.</p>\n"
+            );
+            assert_eq!(
+                render_markdown_with_options("![A cat](cat.png)", false, &options),
+                "<p><img src=\"cat.png\" alt=\"A cat\" /></p>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_open_external_links_in_new_tab() {
+            let options = Markdown {
+                external_links_new_tab: true,
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(
+                    r#"[Rust](https://www.rust-lang.org/ "The *Rust* site") and [mdBook](//example.com/)"#,
+                    false,
+                    &options
+                ),
+                "<p><a href=\"https://www.rust-lang.org/\" title=\"The *Rust* site\" \
+                 target=\"_blank\" rel=\"noopener noreferrer\">Rust</a> and \
+                 <a href=\"//example.com/\" target=\"_blank\" rel=\"noopener noreferrer\">mdBook</a></p>\n"
+            );
+            assert_eq!(
+                render_markdown_with_options(
+                    "[chapter](chapter.md) and [*section*](#section)",
+                    false,
+                    &options
+                ),
+                "<p><a href=\"chapter.html\">chapter</a> and <a href=\"#section\"><em>section</em></a></p>\n"
+            );
+            assert_eq!(
+                render_markdown_with_options(
+                    "[Rust](https://www.rust-lang.org/)",
+                    false,
+                    &Markdown::default()
+                ),
+                "<p><a href=\"https://www.rust-lang.org/\">Rust</a></p>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_set_the_hreflang_of_links() {
+            let options = Markdown {
+                hreflang_links: true,
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(
+                    "[Lisez](fr/page.md){hreflang=fr} or [read](page.md).",
+                    false,
+                    &options
+                ),
+                "<p><a href=\"fr/page.html\" hreflang=\"fr\">Lisez</a> or \
+                 <a href=\"page.html\">read</a>.</p>\n"
+            );
+            assert_eq!(
+                render_markdown_with_options(
+                    "[*Leia*](https://example.com/pt \"T\"){hreflang=pt-BR}{x}",
+                    false,
+                    &Markdown {
+                        external_links_new_tab: true,
+                        ..options.clone()
+                    }
+                ),
+                "<p><a href=\"https://example.com/pt\" title=\"T\" hreflang=\"pt-BR\" \
+                 target=\"_blank\" rel=\"noopener noreferrer\"><em>Leia</em></a>{x}</p>\n"
+            );
+            // Markers that aren't language tags, or follow a space, are text.
+            for input in ["[a](b.md){hreflang=\"fr\"}", "[a](b.md) {hreflang=fr}"] {
+                assert_eq!(
+                    render_markdown_with_options(input, false, &options),
+                    render_markdown(input, false)
+                );
+            }
+            assert_eq!(
+                render_markdown("[Lisez](fr/page.md){hreflang=fr}", false),
+                "<p><a href=\"fr/page.html\">Lisez</a>{hreflang=fr}</p>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_set_the_rel_of_external_links() {
+            let input = "[Rust](https://www.rust-lang.org/) and [chapter](chapter.md)";
+            let options = Markdown {
+                external_link_rel: vec!["nofollow".to_string(), "ugc".to_string()],
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                "<p><a href=\"https://www.rust-lang.org/\" rel=\"nofollow ugc\">Rust</a> and \
+                 <a href=\"chapter.html\">chapter</a></p>\n"
+            );
+            let options = Markdown {
+                external_links_new_tab: true,
+                external_link_rel: vec!["noopener".to_string(), "nofollow".to_string()],
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                "<p><a href=\"https://www.rust-lang.org/\" target=\"_blank\" \
+                 rel=\"noopener noreferrer nofollow\">Rust</a> and \
+                 <a href=\"chapter.html\">chapter</a></p>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_change_the_code_class_prefix() {
+            let input = "```rust,no_run\nfn main() {}\n```\n\n```\nplain\n```\n";
+            let options = Markdown {
+                code_class_prefix: "lang-".to_string(),
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                "<pre><code class=\"lang-rust,no_run\">fn main() {}\n</code></pre>\n\
+                 <pre><code>plain\n</code></pre>\n"
+            );
+            assert_eq!(
+                render_markdown_with_options("```rust\nfn main() {}\n```", false, &options),
+                "<pre><code class=\"lang-rust\">fn main() {}\n</code></pre>\n"
+            );
+            assert_eq!(
+                render_markdown_with_options(input, false, &Markdown::default()),
+                "<pre><code class=\"language-rust,no_run\">fn main() {}\n</code></pre>\n\
+                 <pre><code>plain\n</code></pre>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_render_interactive_tasklists() {
+            let input = "- [x] Apples\n- [ ] Carrots\n\ntext\n\n- [ ] Bread\n";
+            assert_eq!(
+                render_markdown_with_options(input, false, &Markdown::default()),
+                "<ul>\n<li><input disabled=\"\" type=\"checkbox\" checked=\"\"/>\nApples</li>\n\
+                 <li><input disabled=\"\" type=\"checkbox\"/>\nCarrots</li>\n</ul>\n\
+                 <p>text</p>\n\
+                 <ul>\n<li><input disabled=\"\" type=\"checkbox\"/>\nBread</li>\n</ul>\n"
+            );
+
+            let options = Markdown {
+                interactive_tasklists: true,
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                "<ul>\n<li><input type=\"checkbox\" data-task-index=\"0\" checked=\"\"/>\nApples</li>\n\
+                 <li><input type=\"checkbox\" data-task-index=\"1\"/>\nCarrots</li>\n</ul>\n\
+                 <p>text</p>\n\
+                 <ul>\n<li><input type=\"checkbox\" data-task-index=\"2\"/>\nBread</li>\n</ul>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_render_details_blockquotes() {
+            let input = "> [!details] Show *more*\n>\n> Some **bold** text.\n>\n> - item\n";
+            assert_eq!(
+                render_markdown_with_options(input, false, &Markdown::default()),
+                "<blockquote>\n<p>[!details] Show <em>more</em></p>\n\
+                 <p>Some <strong>bold</strong> text.</p>\n\
+                 <ul>\n<li>item</li>\n</ul>\n</blockquote>\n"
+            );
+
+            let options = Markdown {
+                details_blockquotes: true,
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                "<details>\n<summary>Show <em>more</em></summary>\n\
+                 <p>Some <strong>bold</strong> text.</p>\n\
+                 <ul>\n<li>item</li>\n</ul>\n</details>\n"
+            );
+
+            let input = "> [!details] Summary\n> Continued `here`.\n\n> A quote\n";
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                "<details>\n<summary>Summary</summary>\n\
+                 <p>Continued <code>here</code>.</p>\n</details>\n\
+                 <blockquote>\n<p>A quote</p>\n</blockquote>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_render_abbreviations() {
+            let input = "The HTML spec.\n\n*[HTML]: HyperText Markup Language\n";
+            assert_eq!(
+                render_markdown_with_options(input, false, &Markdown::default()),
+                "<p>The HTML spec.</p>\n<p>*[HTML]: HyperText Markup Language</p>\n"
+            );
+
+            let options = Markdown {
+                abbreviations: true,
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                "<p>The <abbr title=\"HyperText Markup Language\">HTML</abbr> spec.</p>\n"
+            );
+
+            let input = "*[HTML]: HyperText \"Markup\" Language\n*[CSS]: Cascading Style Sheets\n\n\
+                         XHTML, HTML5 and HTML_ aren't HTML, but `HTML` and [HTML](x.html) aren't wrapped.\n\n\
+                         ```\nHTML\n```\n\n\
+                         HTML/CSS\n";
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                "<p>XHTML, HTML5 and HTML_ aren't \
+                 <abbr title=\"HyperText &quot;Markup&quot; Language\">HTML</abbr>, \
+                 but <code>HTML</code> and <a href=\"x.html\">HTML</a> aren't wrapped.</p>\n\
+                 <pre><code>HTML\n</code></pre>\n\
+                 <p><abbr title=\"HyperText &quot;Markup&quot; Language\">HTML</abbr>/\
+                 <abbr title=\"Cascading Style Sheets\">CSS</abbr></p>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_wrap_image_galleries() {
+            let input = "![a](a.png)\n\n![b](b.png)\n\n![c](c.png \"C\")\n\n\
+                         Some text.\n\n![d](d.png)\n\n![e](e.png) and text\n";
+            let options = Markdown {
+                image_galleries: true,
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                "<div class=\"gallery\">\n\
+                 <p><img src=\"a.png\" alt=\"a\" /></p>\n\
+                 <p><img src=\"b.png\" alt=\"b\" /></p>\n\
+                 <p><img src=\"c.png\" alt=\"c\" title=\"C\" /></p>\n\
+                 </div>\n\
+                 <p>Some text.</p>\n\
+                 <p><img src=\"d.png\" alt=\"d\" /></p>\n\
+                 <p><img src=\"e.png\" alt=\"e\" /> and text</p>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_render_quotes() {
+            let input = "He said \"it's 'fine' now\" and left.\n\n\
+                         \"Don't *quote* `\"code\"`\"\n\n\
+                         ```\n\"code\"\n```\n";
+            let german = Markdown {
+                quotes: Some(["„", "“", "‚", "‘"].map(String::from)),
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(input, true, &german),
+                "<p>He said „it’s ‚fine‘ now“ and left.</p>\n\
+                 <p>„Don’t <em>quote</em> <code>\"code\"</code>“</p>\n\
+                 <pre><code>\"code\"\n</code></pre>\n"
+            );
+
+            let french = Markdown {
+                quotes: Some(["«\u{a0}", "\u{a0}»", "‹\u{a0}", "\u{a0}›"].map(String::from)),
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options("\"Un 'deux' \"trois\"\" -- fin", true, &french),
+                "<p>«\u{a0}Un ‹\u{a0}deux\u{a0}› ‹\u{a0}trois\u{a0}›\u{a0}» -- fin</p>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_render_highlights() {
+            let input = "A ==highlighted phrase== and ==another==, `==code==`, \
+                         a == b, ==[link](x.html)==\n\n```\n==block==\n```\n";
+            assert_eq!(
+                render_markdown_with_options(input, false, &Markdown::default()),
+                "<p>A ==highlighted phrase== and ==another==, <code>==code==</code>, \
+                 a == b, ==<a href=\"x.html\">link</a>==</p>\n\
+                 <pre><code>==block==\n</code></pre>\n"
+            );
+
+            let options = Markdown {
+                highlights: true,
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                "<p>A <mark>highlighted phrase</mark> and <mark>another</mark>, \
+                 <code>==code==</code>, a == b, ==<a href=\"x.html\">link</a>==</p>\n\
+                 <pre><code>==block==\n</code></pre>\n"
+            );
+            assert_eq!(
+                render_markdown_with_options("Only ==one marker.", false, &options),
+                "<p>Only ==one marker.</p>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_set_the_class_of_thematic_breaks() {
+            let input = "Before\n\n---\n\nAfter\n";
+            assert_eq!(
+                render_markdown_with_options(input, false, &Markdown::default()),
+                "<p>Before</p>\n<hr />\n<p>After</p>\n"
+            );
+            let options = Markdown {
+                thematic_break_class: Some("section-divider".to_owned()),
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                "<p>Before</p>\n<hr class=\"section-divider\" />\n<p>After</p>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_add_code_copy_buttons() {
+            let options = Markdown {
+                code_copy_buttons: true,
+                ..Markdown::default()
+            };
+            let input = "Run `cargo build`.\n\n```sh\ncargo build\n```\n\n    indented\n";
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                "<p>Run <code>cargo build</code>.</p>\n\
+                 <div class=\"code-block\">\
+                 <button class=\"copy-button\" type=\"button\" aria-label=\"Copy\"></button>\n\
+                 <pre><code class=\"language-sh\">cargo build\n</code></pre>\n</div>\n\
+                 <pre><code>indented\n</code></pre>\n"
+            );
+
+            let options = Markdown {
+                code_copy_buttons: true,
+                code_copy_buttons_exclude: vec!["console".to_owned()],
+                ..Markdown::default()
+            };
+            let input = "```rust\nfn main() {}\n```\n\n```console\n$ cargo run\n```\n";
+            assert_eq!(
+                render_markdown_with_options(input, false, &options),
+                "<div class=\"code-block\">\
+                 <button class=\"copy-button\" type=\"button\" aria-label=\"Copy\"></button>\n\
+                 <pre><code class=\"language-rust\">fn main() {}\n</code></pre>\n</div>\n\
+                 <div class=\"code-block\">\n\
+                 <pre><code class=\"language-console\">$ cargo run\n</code></pre>\n</div>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_handle_empty_code_blocks() {
+            let input = "Before\n\n```rust\n```\n\n```\n\n```\n\n```rust\nfn main() {}\n```\n";
+            let modes = [
+                (
+                    EmptyCodeBlocks::Keep,
+                    "<p>Before</p>\n<pre><code class=\"language-rust\"></code></pre>\n\
+                     <pre><code>\n</code></pre>\n\
+                     <pre><code class=\"language-rust\">fn main() {}\n</code></pre>\n",
+                ),
+                (
+                    EmptyCodeBlocks::Drop,
+                    "<p>Before</p>\n\
+                     <pre><code class=\"language-rust\">fn main() {}\n</code></pre>\n",
+                ),
+                (
+                    EmptyCodeBlocks::Placeholder,
+                    "<p>Before</p>\n<pre><code class=\"language-rust\">(empty)\n</code></pre>\n\
+                     <pre><code>(empty)\n</code></pre>\n\
+                     <pre><code class=\"language-rust\">fn main() {}\n</code></pre>\n",
+                ),
+            ];
+            for (empty_code_blocks, should_be) in modes {
+                let options = Markdown {
+                    empty_code_blocks,
+                    ..Markdown::default()
+                };
+                assert_eq!(
+                    render_markdown_with_options(input, false, &options),
+                    should_be
+                );
+            }
+        }
+
+        #[test]
+        fn it_can_render_keyboard_keys() {
+            let options = Markdown {
+                keyboard_keys: true,
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options("Press [[Enter]].", false, &options),
+                "<p>Press <kbd>Enter</kbd>.</p>\n"
+            );
+            assert_eq!(
+                render_markdown_with_options("Copy with [[Ctrl+C]] or [[Cmd+C]]", false, &options),
+                "<p>Copy with <kbd>Ctrl</kbd>+<kbd>C</kbd> or <kbd>Cmd</kbd>+<kbd>C</kbd></p>\n"
+            );
+            assert_eq!(
+                render_markdown_with_options("[[not a key and [[not a key]]", false, &options),
+                "<p>[[not a key and [[not a key]]</p>\n"
+            );
+            assert_eq!(
+                render_markdown_with_options(
+                    "`[[Ctrl+C]]`\n\n```\n[[Ctrl+C]]\n```",
+                    false,
+                    &options
+                ),
+                "<p><code>[[Ctrl+C]]</code></p>\n<pre><code>[[Ctrl+C]]\n</code></pre>\n"
+            );
+            assert_eq!(
+                render_markdown_with_options("Press [[Enter]].", false, &Markdown::default()),
+                "<p>Press [[Enter]].</p>\n"
+            );
+        }
+
+        #[test]
+        fn it_can_convert_comment_directives() {
+            let options = Markdown {
+                comment_directives: vec!["toc".to_string()],
+                ..Markdown::default()
+            };
+            assert_eq!(
+                render_markdown_with_options("<!-- toc -->\n\n# Title", false, &options),
+                "<div data-mdbook-directive=\"toc\"></div>\n<h1>Title</h1>\n"
+            );
+            assert_eq!(
+                render_markdown_with_options("Text <!--toc--> here", false, &options),
+                "<p>Text <div data-mdbook-directive=\"toc\"></div> here</p>\n"
+            );
+            assert_eq!(
+                render_markdown_with_options("<!-- a comment -->\n<!-- note -->", false, &options),
+                "<!-- a comment -->\n<!-- note -->"
+            );
+        }
+
+        #[test]
+        fn it_can_keep_quotes_straight() {
+            assert_eq!(render_markdown("'one'", false), "<p>'one'</p>\n");
+        }
+
+        #[test]
+        fn it_can_make_quotes_curly_except_when_they_are_in_code() {
+            let input = r#"
+'one'
 ```
 'two'
 ```
@@ -642,6 +4693,383 @@ more text with spaces
         }
     }
 
+    mod images_without_alt_text {
+        use super::super::images_without_alt_text;
+
+        #[test]
+        fn it_reports_images_without_alt_text() {
+            let input = r#"
+![A captioned image](captioned.png "Caption")
+
+![](missing.png "Only a title")
+
+<img src="decorative.png" alt="">
+<img src="raw.png">
+"#;
+            assert_eq!(
+                images_without_alt_text(input),
+                vec!["missing.png".to_string(), "raw.png".to_string()]
+            );
+        }
+    }
+
+    mod page_description {
+        use super::super::page_description;
+
+        #[test]
+        fn it_uses_the_first_paragraph() {
+            let input = "# Introduction\n\n\
+                         This book explains *everything* about `mdBook`,\n\
+                         from writing to publishing.\n\n\
+                         Second paragraph.\n";
+            assert_eq!(
+                page_description(input, 160).as_deref(),
+                Some("This book explains everything about mdBook, from writing to publishing.")
+            );
+            assert_eq!(
+                page_description(input, 30).as_deref(),
+                Some("This book explains everything…")
+            );
+            assert_eq!(
+                page_description(input, 26).as_deref(),
+                Some("This book explains…")
+            );
+            assert_eq!(page_description("# Only a heading", 160), None);
+        }
+
+        #[test]
+        fn it_skips_images_and_code_blocks() {
+            let input = "![Cover](cover.png)\n\n\
+                         <img src=\"logo.png\" alt=\"Logo\">\n\n\
+                         ```rust\nfn main() {}\n```\n\n\
+                         Tom &amp; Jerry ![icon](icon.png) explain entities.\n";
+            assert_eq!(
+                page_description(input, 160).as_deref(),
+                Some("Tom & Jerry explain entities.")
+            );
+            assert_eq!(page_description(input, 8).as_deref(), Some("Tom &…"));
+        }
+    }
+
+    mod missing_images {
+        use super::super::missing_images;
+        use std::fs;
+
+        #[test]
+        fn it_reports_missing_relative_images() {
+            let temp = tempfile::tempdir().unwrap();
+            fs::create_dir(temp.path().join("images")).unwrap();
+            fs::write(temp.path().join("images/found.png"), "").unwrap();
+            let input = r#"
+![Found](images/found.png "Title")
+![Missing](images/missing.png)
+![Remote](https://example.com/remote.png)
+![Absolute](/absolute.png)
+
+<img src="images/found.png?v=1" alt="Found">
+<img alt="Missing" src="raw%20missing.png">
+"#;
+            assert_eq!(
+                missing_images(input, temp.path()),
+                vec![
+                    "images/missing.png".to_string(),
+                    "raw%20missing.png".to_string()
+                ]
+            );
+        }
+    }
+
+    mod external_links {
+        use super::super::external_links;
+        use std::path::{Path, PathBuf};
+
+        #[test]
+        fn it_collects_external_links() {
+            let input = r#"
+[Rust](https://www.rust-lang.org/) and [a chapter](other.md#part), <https://docs.rs>,
+[mail](mailto:someone@example.com), [top](#top), [CDN](//cdn.example.com/lib.js).
+
+<p>Raw <a class="x" href="http://example.com/raw">link</a> and
+<a href="../internal.html">internal</a> and <a name="anchor">anchor</a>.</p>
+"#;
+            let path = Path::new("guide/intro.md");
+            let links: Vec<_> = external_links(input, path)
+                .into_iter()
+                .inspect(|(page, _)| assert_eq!(page, &PathBuf::from("guide/intro.md")))
+                .map(|(_, link)| link)
+                .collect();
+            assert_eq!(
+                links,
+                vec![
+                    "https://www.rust-lang.org/",
+                    "https://docs.rs",
+                    "mailto:someone@example.com",
+                    "//cdn.example.com/lib.js",
+                    "http://example.com/raw",
+                ]
+            );
+            assert!(external_links("[local](local.md)", path).is_empty());
+        }
+    }
+
+    mod check_unique_heading_ids {
+        use super::super::check_unique_heading_ids;
+
+        #[test]
+        fn it_reports_headings_with_the_same_title() {
+            let input =
+                "# Intro\n\n## Setup\n\ntext\n\n## Usage\n\n## Setup\n\n### *Setup*[^1]\n\n\
+                         [^1]: A note.\n";
+            assert_eq!(
+                check_unique_heading_ids(input),
+                vec![("setup".to_string(), 3)]
+            );
+            assert_eq!(check_unique_heading_ids("# Intro\n\n## Usage\n"), vec![]);
+        }
+
+        #[test]
+        fn it_reports_explicit_duplicate_ids() {
+            let input = "# Intro {#start}\n\n## Start\n\n## Other {#start}\n\n\
+                         ## Usage {#usage}\n\n## Usage\n";
+            assert_eq!(
+                check_unique_heading_ids(input),
+                vec![("start".to_string(), 3), ("usage".to_string(), 2)]
+            );
+        }
+    }
+
+    mod normalize_markdown {
+        use super::super::normalize_markdown;
+
+        #[test]
+        fn it_collapses_excessive_blank_lines() {
+            let text = "# Title  \n\n\n\n\nText.\t\n  \n\nMore text.\n\n\n\n";
+            assert_eq!(
+                normalize_markdown(text),
+                "# Title\n\nText.\n\n\nMore text.\n\n"
+            );
+        }
+
+        #[test]
+        fn it_keeps_code_blocks_untouched() {
+            let text = "Text.\n\n\n\n```rust  \nfn main() {  \n\n\n\n\n}\n```\n\n\n\n\
+                        ~~~\n  \n\n\n\n~~~\n\n\n\n    indented  \n\n\n\n\n    code\n";
+            assert_eq!(
+                normalize_markdown(text),
+                "Text.\n\n```rust  \nfn main() {  \n\n\n\n\n}\n```\n\n\
+                 ~~~\n  \n\n\n\n~~~\n\n    indented  \n\n\n\n\n    code\n"
+            );
+        }
+
+        #[test]
+        fn it_keeps_normalized_text_as_is() {
+            let text = "# Title\n\nText.\n\n- A\n- B\n";
+            assert_eq!(normalize_markdown(text), text);
+        }
+    }
+
+    mod content_fingerprint {
+        use super::super::content_fingerprint;
+        use crate::config::Markdown;
+
+        #[test]
+        fn it_is_stable_for_identical_input() {
+            let options = Markdown::default();
+            let fingerprint = content_fingerprint("# Intro\n\nText.\n", false, &options);
+            assert_eq!(
+                fingerprint,
+                content_fingerprint("# Intro\n\nText.\n", false, &options.clone())
+            );
+            assert_ne!(
+                fingerprint,
+                content_fingerprint("# Intro\n\nText!\n", false, &options)
+            );
+        }
+
+        #[test]
+        fn it_changes_with_the_options() {
+            let text = "\"Quoted\" -- text.\n";
+            let options = Markdown::default();
+            let fingerprint = content_fingerprint(text, false, &options);
+            assert_ne!(fingerprint, content_fingerprint(text, true, &options));
+
+            let options = Markdown {
+                highlights: true,
+                ..Markdown::default()
+            };
+            assert_ne!(fingerprint, content_fingerprint(text, false, &options));
+        }
+    }
+
+    mod diff_renderings {
+        use super::super::{diff_renderings, RenderingDiff};
+
+        #[test]
+        fn it_finds_no_change_in_identical_input() {
+            let input = "# Intro\n\nSome text.\n\n## Setup\n";
+            assert_eq!(
+                diff_renderings(input, input, false),
+                RenderingDiff::default()
+            );
+            // Markdown that renders the same isn't a change either.
+            let diff = diff_renderings("# Intro\n\n*text*\n", "Intro\n=====\n\n_text_\n", false);
+            assert_eq!(diff, RenderingDiff::default());
+        }
+
+        #[test]
+        fn it_detects_text_changes() {
+            let diff =
+                diff_renderings("# Intro\n\nSome text.\n", "# Intro\n\nOther text.\n", false);
+            assert!(diff.changed);
+            assert!(diff.changed_ids.is_empty());
+        }
+
+        #[test]
+        fn it_reports_changed_heading_ids() {
+            let diff = diff_renderings(
+                "# Intro\n\n## Setup\n\n## Usage {#use}\n",
+                "# Overview\n\n## Setup\n\n## Usage {#usage}\n",
+                false,
+            );
+            assert!(diff.changed);
+            assert_eq!(
+                Vec::from_iter(diff.changed_ids),
+                ["intro", "overview", "usage", "use"]
+            );
+
+            let diff = diff_renderings("## Setup\n\n## Setup\n", "## Setup\n", false);
+            assert_eq!(Vec::from_iter(diff.changed_ids), ["setup-1"]);
+        }
+    }
+
+    mod split_front_matter {
+        use super::super::{render_markdown, split_front_matter};
+        use toml::Value;
+
+        #[test]
+        fn it_splits_yaml_front_matter() {
+            let input = "---\ntitle: \"Front: matter\"\ndraft: false\nweight: 3\n\
+                         tags: [rust, docs]\nauthors:\n  - Ana\n  - Bo\n---\n# Heading\n";
+            let (front_matter, body) = split_front_matter(input);
+            let front_matter = front_matter.unwrap();
+            assert_eq!(front_matter["title"], Value::from("Front: matter"));
+            assert_eq!(front_matter["draft"], Value::from(false));
+            assert_eq!(front_matter["weight"], Value::from(3));
+            assert_eq!(front_matter["tags"], Value::from(vec!["rust", "docs"]));
+            assert_eq!(front_matter["authors"], Value::from(vec!["Ana", "Bo"]));
+            assert_eq!(body, "# Heading\n");
+
+            let input =
+                "---\nratio: 0.5\nempty:\nitem: 'it''s'\nextra:\n  nested: [1, \"two\"]\n---\n";
+            let front_matter = split_front_matter(input).0.unwrap();
+            assert_eq!(front_matter["ratio"], Value::from(0.5));
+            assert_eq!(front_matter.get("empty"), None);
+            assert_eq!(front_matter["item"], Value::from("it's"));
+            assert_eq!(
+                front_matter["extra"]["nested"],
+                Value::Array(vec![Value::from(1), Value::from("two")])
+            );
+        }
+
+        #[test]
+        fn it_splits_toml_front_matter() {
+            let input = "+++\ntitle = \"Intro\"\ntags = [\"rust\"]\n+++\r\nSome text.\n";
+            let (front_matter, body) = split_front_matter(input);
+            let front_matter = front_matter.unwrap();
+            assert_eq!(front_matter["title"], Value::from("Intro"));
+            assert_eq!(front_matter["tags"], Value::from(vec!["rust"]));
+            assert_eq!(body, "Some text.\n");
+        }
+
+        #[test]
+        fn it_keeps_a_leading_thematic_break() {
+            let input = "---\n\nSome text.\n";
+            assert_eq!(split_front_matter(input), (None, input));
+            assert_eq!(render_markdown(input, false), "<hr />\n<p>Some text.</p>\n");
+
+            // Markdown between two thematic breaks isn't front matter.
+            let input = "---\nSome text.\n\n---\n";
+            assert_eq!(split_front_matter(input), (None, input));
+            let input = "---\n# Heading\n---\n";
+            assert_eq!(split_front_matter(input), (None, input));
+            let input = "+++\nnot = toml = here\n+++\n";
+            assert_eq!(split_front_matter(input), (None, input));
+        }
+    }
+
+    mod reading_time {
+        use super::super::{count_words, estimate_reading_time};
+
+        #[test]
+        fn it_counts_english_words() {
+            let input = "# Reading *time*\n\n\
+                         Some words, with `code` and a [link](https://example.com).\n\n\
+                         ```rust\nfn main() {}\n```\n";
+            assert_eq!(count_words(input, false), 9);
+            assert_eq!(count_words(input, true), 11);
+            assert_eq!(estimate_reading_time(input, 200), 1);
+            assert_eq!(estimate_reading_time(input, 4), 3);
+            assert_eq!(estimate_reading_time("", 200), 0);
+        }
+
+        #[test]
+        fn it_counts_cjk_characters_as_words() {
+            let input = "中文標題。これは日本語です。 Rust 2021";
+            assert_eq!(count_words(input, false), 14);
+            assert_eq!(estimate_reading_time(input, 5), 3);
+        }
+    }
+
+    mod teasers {
+        use super::super::{render_markdown, render_teaser, TeaserOptions};
+
+        #[test]
+        fn it_ends_teasers_after_a_block() {
+            let input = "# A post\n\n\
+                         The first paragraph has [a link][link] here.[^note]\n\n\
+                         ```rust\nfn main() {\n    println!(\"no words counted here\");\n}\n```\n\n\
+                         - one item\n- and another item\n\n\
+                         The last paragraph is left out.\n\n\
+                         [^note]: A note.\n\n\
+                         [link]: https://example.com\n";
+            assert_eq!(
+                render_teaser(input, 12, &TeaserOptions::default()),
+                "<h1>A post</h1>\n\
+                 <p>The first paragraph has <a href=\"https://example.com\">a link</a> \
+                 here.<sup class=\"footnote-reference\"><a href=\"#note\">1</a></sup></p>\n\
+                 <pre><code class=\"language-rust\">fn main() {\n    \
+                 println!(\"no words counted here\");\n}\n</code></pre>\n\
+                 <ul>\n<li>one item</li>\n<li>and another item</li>\n</ul>\n\
+                 <p class=\"read-more\">Read more…</p>\n\
+                 <div class=\"footnote-definition\" id=\"note\">\
+                 <sup class=\"footnote-definition-label\">1</sup>\n<p>A note.</p>\n</div>\n"
+            );
+
+            let options = TeaserOptions {
+                read_more: "[Read the whole post](post.md)".to_string(),
+                ..TeaserOptions::default()
+            };
+            assert_eq!(
+                render_teaser("One two.\n\nThree four.\n", 1, &options),
+                "<p>One two.</p>\n<p><a href=\"post.html\">Read the whole post</a></p>\n"
+            );
+        }
+
+        #[test]
+        fn it_renders_short_text_in_full() {
+            let input = "# A post\n\nJust a few words.\n\n```\nand code\n```\n";
+            let options = TeaserOptions::default();
+            assert_eq!(
+                render_teaser(input, 6, &options),
+                render_markdown(input, false)
+            );
+            assert_eq!(
+                render_teaser(input, 100, &options),
+                render_markdown(input, false)
+            );
+        }
+    }
+
     #[allow(deprecated)]
     mod id_from_content {
         use super::super::id_from_content;
@@ -675,7 +5103,12 @@ more text with spaces
     }
 
     mod html_munging {
-        use super::super::{normalize_id, unique_id_from_content};
+        use super::super::{
+            github_slug, id_counter_with_reserved, normalize_id, normalize_id_and_href,
+            normalize_id_with_style, percent_encode_id, unique_id_from_content,
+            unique_id_from_content_with_style, IdStyle,
+        };
+        use crate::config::EmojiIds;
 
         #[test]
         fn it_normalizes_ids() {
@@ -685,7 +5118,7 @@ more text with spaces
             );
             assert_eq!(
                 normalize_id("Method-call 🐙 expressions \u{1f47c}"),
-                "method-call--expressions-"
+                "method-call-expressions"
             );
             assert_eq!(normalize_id("_-_12345"), "_-_12345");
             assert_eq!(normalize_id("12345"), "12345");
@@ -695,6 +5128,23 @@ more text with spaces
             assert_eq!(normalize_id(""), "");
         }
 
+        #[test]
+        fn it_percent_encodes_ids_for_links() {
+            assert_eq!(
+                normalize_id_and_href("中文標題"),
+                (
+                    "中文標題".to_owned(),
+                    "%E4%B8%AD%E6%96%87%E6%A8%99%E9%A1%8C".to_owned()
+                )
+            );
+            assert_eq!(
+                normalize_id_and_href("Über uns"),
+                ("Über-uns".to_owned(), "%C3%9Cber-uns".to_owned())
+            );
+            assert_eq!(percent_encode_id("100%-<ok>"), "100%25-%3Cok%3E");
+            assert_eq!(percent_encode_id("plain-id_1"), "plain-id_1");
+        }
+
         #[test]
         fn it_generates_unique_ids_from_content() {
             // Same id if not given shared state
@@ -717,6 +5167,145 @@ more text with spaces
             assert_eq!(unique_id_from_content("## Über", &mut id_counter), "Über-1");
             assert_eq!(unique_id_from_content("## Über", &mut id_counter), "Über-2");
         }
+
+        #[test]
+        fn it_uses_custom_separator() {
+            let style = IdStyle {
+                separator: '_',
+                ..IdStyle::default()
+            };
+            assert_eq!(
+                normalize_id_with_style("Method call expressions", style),
+                "method_call_expressions"
+            );
+            let mut id_counter = Default::default();
+            assert_eq!(
+                unique_id_from_content_with_style("## Hello World", &mut id_counter, style),
+                "hello_world"
+            );
+            assert_eq!(
+                unique_id_from_content_with_style("## Hello World", &mut id_counter, style),
+                "hello_world_1"
+            );
+            assert_eq!(
+                unique_id_from_content_with_style("## Hello World", &mut id_counter, style),
+                "hello_world_2"
+            );
+        }
+
+        #[test]
+        fn it_can_keep_case() {
+            let style = IdStyle {
+                lowercase: false,
+                ..IdStyle::default()
+            };
+            assert_eq!(
+                normalize_id_with_style("API Reference", style),
+                "API-Reference"
+            );
+            assert_eq!(
+                normalize_id_with_style("API Reference", IdStyle::default()),
+                "api-reference"
+            );
+            assert_eq!(normalize_id_with_style("Über API", style), "Über-API");
+        }
+
+        #[test]
+        fn it_generates_github_slugs() {
+            assert_eq!(github_slug("Q &amp; A"), "q--a");
+            assert_eq!(github_slug("Über <code>API</code>"), "über-api");
+            assert_eq!(github_slug("🚀 Launch"), "-launch");
+            assert_eq!(github_slug("v1.2: Snake_case"), "v12-snake_case");
+        }
+
+        #[test]
+        fn it_strips_emoji_without_stray_separators() {
+            let style = IdStyle::default();
+            assert_eq!(normalize_id_with_style("🚀 Launch", style), "launch");
+            assert_eq!(normalize_id_with_style("Ship 🚀 it", style), "ship-it");
+            assert_eq!(normalize_id_with_style("Launch 🚀", style), "launch");
+            assert_eq!(normalize_id_with_style("🎉🎉 Party 🎉 ", style), "party");
+            assert_eq!(normalize_id_with_style("Rust🦀Crab", style), "rust-crab");
+            assert_eq!(
+                normalize_id_with_style("⚠️ Breaking changes", style),
+                "breaking-changes"
+            );
+            assert_eq!(
+                normalize_id_with_style("👩‍💻 Developers", style),
+                "developers"
+            );
+            assert_eq!(
+                unique_id_from_content("## 🚀 Launch", &mut Default::default()),
+                "launch"
+            );
+        }
+
+        #[test]
+        fn it_can_spell_emoji_as_shortcodes() {
+            let style = IdStyle {
+                emoji: EmojiIds::Shortcode,
+                ..IdStyle::default()
+            };
+            assert_eq!(normalize_id_with_style("🚀 Launch", style), "rocket-launch");
+            assert_eq!(
+                normalize_id_with_style("Ship 🚀 it", style),
+                "ship-rocket-it"
+            );
+            assert_eq!(normalize_id_with_style("Launch 🚀", style), "launch-rocket");
+            assert_eq!(
+                normalize_id_with_style("✅ Done ⚠️", style),
+                "white-check-mark-done-warning"
+            );
+            assert_eq!(normalize_id_with_style("🫠 Melting", style), "melting");
+        }
+
+        #[test]
+        fn it_can_transliterate_latin() {
+            let style = IdStyle {
+                transliterate: true,
+                ..IdStyle::default()
+            };
+            assert_eq!(normalize_id_with_style("Café", style), "cafe");
+            assert_eq!(normalize_id_with_style("Über", style), "uber");
+            assert_eq!(
+                normalize_id_with_style("Straße Ñandú", style),
+                "strasse-nandu"
+            );
+            assert_eq!(
+                normalize_id_with_style("中文標題 CJK title", style),
+                "中文標題-cjk-title"
+            );
+            assert_eq!(normalize_id_with_style("Über", IdStyle::default()), "Über");
+
+            let style = IdStyle {
+                lowercase: false,
+                ..style
+            };
+            assert_eq!(normalize_id_with_style("Œuvre Über", style), "OEuvre-Uber");
+        }
+
+        #[test]
+        fn it_skips_reserved_ids() {
+            let mut id_counter = id_counter_with_reserved(["content"]);
+            assert_eq!(
+                unique_id_from_content("Content", &mut id_counter),
+                "content-1"
+            );
+            assert_eq!(
+                unique_id_from_content("Content", &mut id_counter),
+                "content-2"
+            );
+            assert_eq!(
+                unique_id_from_content("Sidebar", &mut id_counter),
+                "sidebar"
+            );
+
+            let mut id_counter = id_counter_with_reserved(Vec::<String>::new());
+            assert_eq!(
+                unique_id_from_content("Content", &mut id_counter),
+                "content"
+            );
+        }
     }
 
     #[test]
@@ -727,5 +5316,23 @@ more text with spaces
         assert_eq!(bracket_escape("<>"), "&lt;&gt;");
         assert_eq!(bracket_escape("<test>"), "&lt;test&gt;");
         assert_eq!(bracket_escape("a<test>b"), "a&lt;test&gt;b");
+        assert_eq!(bracket_escape("é<🦀>中"), "é&lt;🦀&gt;中");
+    }
+
+    #[test]
+    fn escaped_special() {
+        assert_eq!(special_escape(""), "");
+        assert_eq!(special_escape("<"), "&lt;");
+        assert_eq!(special_escape(">"), "&gt;");
+        assert_eq!(special_escape("<>"), "&lt;&gt;");
+        assert_eq!(special_escape("<test>"), "&lt;test&gt;");
+        assert_eq!(special_escape("a<test>b"), "a&lt;test&gt;b");
+        assert_eq!(special_escape("'"), "&#39;");
+        assert_eq!(special_escape("\\"), "&#92;");
+        assert_eq!(special_escape("&"), "&amp;");
+        assert_eq!(
+            special_escape("𝄞<ü>\"日本\"&🦀'\\é"),
+            "𝄞&lt;ü&gt;&quot;日本&quot;&amp;🦀&#39;&#92;é"
+        );
     }
 }
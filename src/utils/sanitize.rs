@@ -0,0 +1,291 @@
+//! Sanitizing the raw HTML of untrusted markdown.
+
+use once_cell::sync::Lazy;
+use pulldown_cmark::{CowStr, Event, Tag, TagEnd};
+use regex::{Captures, Regex};
+
+/// Elements whose content is removed along with them when they aren't
+/// allowed, since it isn't meant to be shown as text.
+const CONTENT_ELEMENTS: &[&str] = &[
+    "script", "style", "iframe", "object", "embed", "template", "noscript", "textarea",
+];
+
+/// Attributes holding a URL that must not run code, like `javascript:`.
+const URL_ATTRIBUTES: &[&str] = &[
+    "href",
+    "src",
+    "cite",
+    "action",
+    "formaction",
+    "poster",
+    "background",
+    "xlink:href",
+];
+
+/// The URL schemes that are allowed, besides URLs without a scheme.
+const SAFE_SCHEMES: &[&str] = &["http", "https", "mailto", "tel", "ftp"];
+
+/// Removes the HTML elements and attributes that aren't allowed from the
+/// raw HTML events of a page.
+///
+/// `<script>` elements, `on*` event handler attributes and URLs with schemes
+/// like `javascript:` are removed even if they are allowed. The content of
+/// elements that aren't allowed is kept, except for [`CONTENT_ELEMENTS`].
+pub(crate) struct HtmlSanitizer<'o> {
+    tags: &'o [String],
+    attributes: &'o [String],
+    /// The HTML of the current HTML block, which is sanitized as a whole
+    /// since its tags may span several lines.
+    block: Option<String>,
+    /// The element of the inline HTML whose content is being removed.
+    removing: Option<String>,
+}
+
+impl<'o> HtmlSanitizer<'o> {
+    pub(crate) fn new(tags: &'o [String], attributes: &'o [String]) -> Self {
+        HtmlSanitizer {
+            tags,
+            attributes,
+            block: None,
+            removing: None,
+        }
+    }
+
+    /// Sanitizes the next `event` of a page, returning the events to emit
+    /// instead.
+    pub(crate) fn sanitize<'a>(&mut self, event: Event<'a>) -> Vec<Event<'a>> {
+        if let Some(block) = &mut self.block {
+            return match event {
+                Event::Html(html) | Event::InlineHtml(html) | Event::Text(html) => {
+                    block.push_str(&html);
+                    Vec::new()
+                }
+                Event::End(TagEnd::HtmlBlock) => {
+                    let block = self.block.take().unwrap_or_default();
+                    let html = self.sanitize_fragment(&block, &mut None);
+                    vec![Event::Html(html.into()), event]
+                }
+                event => vec![event],
+            };
+        }
+        match event {
+            Event::Start(Tag::HtmlBlock) => {
+                self.block = Some(String::new());
+                vec![event]
+            }
+            Event::InlineHtml(html) => {
+                let mut removing = self.removing.take();
+                let html = self.sanitize_fragment(&html, &mut removing);
+                self.removing = removing;
+                if html.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![Event::InlineHtml(html.into())]
+                }
+            }
+            Event::Text(_) | Event::Code(_) if self.removing.is_some() => Vec::new(),
+            Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => vec![Event::Start(Tag::Link {
+                link_type,
+                dest_url: safe_url(dest_url),
+                title,
+                id,
+            })],
+            Event::Start(Tag::Image {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => vec![Event::Start(Tag::Image {
+                link_type,
+                dest_url: safe_url(dest_url),
+                title,
+                id,
+            })],
+            Event::End(end) => {
+                // An element left open in inline HTML doesn't remove the
+                // content of the blocks after it.
+                if !matches!(
+                    end,
+                    TagEnd::Emphasis
+                        | TagEnd::Strong
+                        | TagEnd::Strikethrough
+                        | TagEnd::Link
+                        | TagEnd::Image
+                ) {
+                    self.removing = None;
+                }
+                vec![Event::End(end)]
+            }
+            event => vec![event],
+        }
+    }
+
+    fn is_allowed(&self, name: &str) -> bool {
+        name != "script" && self.tags.iter().any(|tag| tag.eq_ignore_ascii_case(name))
+    }
+
+    /// Sanitizes a fragment of HTML, where `removing` is the element whose
+    /// content is being removed, if any.
+    fn sanitize_fragment(&self, html: &str, removing: &mut Option<String>) -> String {
+        // Browsers end a comment at `<!-->`, `<!--->` and `--!>` as well,
+        // and at the end of the HTML if it isn't closed.
+        static TOKEN: Lazy<Regex> = Lazy::new(|| {
+            Regex::new(
+                r#"(?s)<!--(?:-?>|.*?--!?>|.*)|<(/)?([a-zA-Z][a-zA-Z0-9:-]*)((?:\s+[^\s=/>]+(?:\s*=\s*(?:"[^"]*"|'[^']*'|[^\s"'>]+))?)*)\s*(/)?>|<"#,
+            )
+            .unwrap()
+        });
+
+        let mut result = String::with_capacity(html.len());
+        let mut last = 0;
+        for caps in TOKEN.captures_iter(html) {
+            let token = caps.get(0).unwrap();
+            if removing.is_none() {
+                result.push_str(&html[last..token.start()]);
+            }
+            last = token.end();
+
+            let Some(name) = caps.get(2) else {
+                // A comment is removed, while a `<` that doesn't start a tag
+                // is escaped so that it can't start one with the next event.
+                if removing.is_none() && token.as_str() == "<" {
+                    result.push_str("&lt;");
+                }
+                continue;
+            };
+            let name = name.as_str().to_ascii_lowercase();
+            let closing = caps.get(1).is_some();
+            if let Some(removed) = removing {
+                if closing && *removed == name {
+                    *removing = None;
+                }
+                continue;
+            }
+            if !self.is_allowed(&name) {
+                let self_closing = caps.get(4).is_some();
+                if !closing && !self_closing && CONTENT_ELEMENTS.contains(&name.as_str()) {
+                    *removing = Some(name);
+                }
+                continue;
+            }
+            if closing {
+                result.push_str(token.as_str());
+                continue;
+            }
+            result.push('<');
+            result.push_str(&caps[2]);
+            result.push_str(&self.sanitize_attributes(&caps[3]));
+            if caps.get(4).is_some() {
+                result.push_str(" /");
+            }
+            result.push('>');
+        }
+        if removing.is_none() {
+            result.push_str(&html[last..]);
+        }
+        result
+    }
+
+    /// Removes the attributes that aren't allowed from the attributes of a
+    /// tag.
+    fn sanitize_attributes(&self, attributes: &str) -> String {
+        static ATTR: Lazy<Regex> = Lazy::new(|| {
+            Regex::new(r#"\s+([^\s=/>]+)(?:\s*=\s*("[^"]*"|'[^']*'|[^\s"'>]+))?"#).unwrap()
+        });
+
+        ATTR.replace_all(attributes, |attr: &Captures<'_>| {
+            let name = attr[1].to_ascii_lowercase();
+            let allowed = !name.starts_with("on")
+                && self
+                    .attributes
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(&name));
+            let value = attr
+                .get(2)
+                .map_or("", |value| value.as_str().trim_matches(['"', '\'']));
+            if allowed && (!URL_ATTRIBUTES.contains(&name.as_str()) || is_safe_url(value)) {
+                attr[0].to_owned()
+            } else {
+                String::new()
+            }
+        })
+        .into_owned()
+    }
+}
+
+/// Replaces a URL that isn't safe by `#`.
+fn safe_url(url: CowStr<'_>) -> CowStr<'_> {
+    if is_safe_url(&url) {
+        url
+    } else {
+        "#".into()
+    }
+}
+
+/// Whether `url` has no scheme, or one of the [`SAFE_SCHEMES`].
+///
+/// Browsers ignore whitespace and control characters in schemes, and decode
+/// character references like `&#106;` in attributes, so they are ignored
+/// and decoded here as well.
+pub(crate) fn is_safe_url(url: &str) -> bool {
+    static REFERENCE: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"(?i)&(?:#x([0-9a-f]+)|#([0-9]+)|(colon|tab|newline));?").unwrap()
+    });
+
+    let url = REFERENCE.replace_all(url, |caps: &Captures<'_>| {
+        let code = match (caps.get(1), caps.get(2)) {
+            (Some(hex), _) => u32::from_str_radix(hex.as_str(), 16).ok(),
+            (_, Some(decimal)) => decimal.as_str().parse().ok(),
+            _ if caps[3].eq_ignore_ascii_case("colon") => Some(u32::from(':')),
+            _ => Some(u32::from(' ')),
+        };
+        code.and_then(char::from_u32).unwrap_or(' ').to_string()
+    });
+    let url: String = url
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect();
+    match url.find([':', '/', '?', '#']) {
+        Some(end) if url[end..].starts_with(':') => {
+            let scheme = url[..end].to_ascii_lowercase();
+            SAFE_SCHEMES.contains(&scheme.as_str())
+        }
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safe_urls() {
+        for url in [
+            "https://example.com",
+            "page.html#x",
+            "/a:b",
+            "#x",
+            "mailto:a@b.c",
+            "",
+        ] {
+            assert!(is_safe_url(url), "{url}");
+        }
+        for url in [
+            "javascript:alert(1)",
+            " JavaScript:alert(1)",
+            "java\tscript:alert(1)",
+            "&#106;avascript:alert(1)",
+            "javascript&colon;alert(1)",
+            "&#x6A;avascript&#58alert(1)",
+            "vbscript:msgbox",
+            "data:text/html,<script>",
+        ] {
+            assert!(!is_safe_url(url), "{url}");
+        }
+    }
+}
@@ -59,6 +59,40 @@ pub fn take_anchored_lines(s: &str, anchor: &str) -> String {
     retained.join("\n")
 }
 
+/// Take the lines of the `n`th (starting at 1) section with the given anchor
+/// from a string, for files that reuse an anchor name.
+/// Lines containing anchor are ignored.
+/// Returns `None` if the anchor occurs less than `n` times.
+pub fn take_nth_anchored_lines(s: &str, anchor: &str, n: usize) -> Option<String> {
+    let mut retained = Vec::<&str>::new();
+    let mut occurrence = 0;
+    let mut anchor_found = false;
+
+    for l in s.lines() {
+        if anchor_found {
+            match ANCHOR_END.captures(l) {
+                Some(cap) => {
+                    if &cap["anchor_name"] == anchor {
+                        break;
+                    }
+                }
+                None => {
+                    if !ANCHOR_START.is_match(l) {
+                        retained.push(l);
+                    }
+                }
+            }
+        } else if let Some(cap) = ANCHOR_START.captures(l) {
+            if &cap["anchor_name"] == anchor {
+                occurrence += 1;
+                anchor_found = occurrence == n;
+            }
+        }
+    }
+
+    anchor_found.then(|| retained.join("\n"))
+}
+
 /// Keep lines contained within the range specified as-is.
 /// For any lines not in the range, include them but use `#` at the beginning. This will hide the
 /// lines from initial display but include them when expanding the code snippet or testing with
@@ -115,12 +149,273 @@ pub fn take_rustdoc_include_anchored_lines(s: &str, anchor: &str) -> String {
     output
 }
 
+/// Take the lines from the first line matching `start_pat` through the first
+/// following line matching `end_pat`, both inclusive.
+/// The line matching `start_pat` is never considered for `end_pat`.
+/// Returns `None` if either pattern does not match.
+pub fn take_regex_region(s: &str, start_pat: &Regex, end_pat: &Regex) -> Option<String> {
+    let lines: Vec<&str> = s.lines().collect();
+    let start = lines.iter().position(|l| start_pat.is_match(l))?;
+    let end = lines[start + 1..]
+        .iter()
+        .position(|l| end_pat.is_match(l))
+        .map(|offset| start + 1 + offset)?;
+    Some(lines[start..=end].join("\n"))
+}
+
+/// Remove trailing spaces and tabs from every line of a string.
+/// Indentation and blank lines are kept as-is.
+pub fn trim_trailing_whitespace(s: &str) -> String {
+    let mut output = s
+        .lines()
+        .map(|l| l.trim_end_matches([' ', '\t']))
+        .collect::<Vec<_>>()
+        .join("\n");
+    if s.ends_with('\n') {
+        output.push('\n');
+    }
+    output
+}
+
+/// Remove the blank lines at the start and end of a string, keeping the
+/// blank lines in between. A string of only blank lines becomes empty.
+pub fn trim_blank_lines(s: &str) -> String {
+    let lines: Vec<&str> = s.lines().collect();
+    let is_blank = |l: &&str| l.trim().is_empty();
+    let Some(start) = lines.iter().position(|l| !is_blank(l)) else {
+        return String::new();
+    };
+    let end = lines.iter().rposition(|l| !is_blank(l)).unwrap_or(start);
+    let mut output = lines[start..=end].join("\n");
+    if s.ends_with('\n') {
+        output.push('\n');
+    }
+    output
+}
+
+/// Replace tabs with spaces up to the next multiple of `tab_width` columns.
+///
+/// With `leading_only`, only the tabs in the indentation of each line are
+/// replaced, so that tabs inside of string literals are kept.
+pub fn expand_tabs(s: &str, tab_width: usize, leading_only: bool) -> String {
+    let mut output = String::with_capacity(s.len());
+    for (i, line) in s.split('\n').enumerate() {
+        if i > 0 {
+            output.push('\n');
+        }
+        let mut column = 0;
+        let mut indentation = true;
+        for ch in line.chars() {
+            indentation &= ch == ' ' || ch == '\t';
+            if ch == '\t' && tab_width > 0 && (indentation || !leading_only) {
+                let spaces = tab_width - column % tab_width;
+                output.extend(std::iter::repeat(' ').take(spaces));
+                column += spaces;
+            } else {
+                output.push(ch);
+                column += 1;
+            }
+        }
+    }
+    output
+}
+
+/// Remove the lines that Rust code blocks hide, like `# use std::fmt;` or a
+/// lone `#`, from a string.
+///
+/// Lines starting with `#!` or `#[` are attributes and kept, just like lines
+/// starting with `##`, which escape a leading `#` that should be shown.
+pub fn strip_hidden_lines(s: &str) -> String {
+    let mut output = s
+        .lines()
+        .filter(|line| {
+            let Some(rest) = line.trim_start().strip_prefix('#') else {
+                return true;
+            };
+            matches!(rest.chars().next(), Some('!' | '[' | '#'))
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    if s.ends_with('\n') {
+        output.push('\n');
+    }
+    output
+}
+
+/// Remove the lines that are comments starting with `prefix`, possibly after
+/// some indentation, from a string. Comments at the end of a line of code
+/// are kept.
+pub fn strip_comment_lines(s: &str, prefix: &str) -> String {
+    let mut output = s
+        .lines()
+        .filter(|line| !line.trim_start().starts_with(prefix))
+        .collect::<Vec<_>>()
+        .join("\n");
+    if s.ends_with('\n') {
+        output.push('\n');
+    }
+    output
+}
+
+/// Like [`take_rustdoc_include_anchored_lines`], but only keeps the lines of
+/// the `n`th (starting at 1) section with the given anchor as-is.
+/// Returns `None` if the anchor occurs less than `n` times.
+pub fn take_rustdoc_include_nth_anchored_lines(s: &str, anchor: &str, n: usize) -> Option<String> {
+    let mut output = String::with_capacity(s.len());
+    let mut occurrence = 0;
+    let mut within_anchored_section = false;
+
+    for l in s.lines() {
+        if within_anchored_section {
+            match ANCHOR_END.captures(l) {
+                Some(cap) => {
+                    if &cap["anchor_name"] == anchor {
+                        within_anchored_section = false;
+                    }
+                }
+                None => {
+                    if !ANCHOR_START.is_match(l) {
+                        output.push_str(l);
+                        output.push('\n');
+                    }
+                }
+            }
+        } else if let Some(cap) = ANCHOR_START.captures(l) {
+            if &cap["anchor_name"] == anchor {
+                occurrence += 1;
+                within_anchored_section = occurrence == n;
+            }
+        } else if !ANCHOR_END.is_match(l) {
+            output.push_str("# ");
+            output.push_str(l);
+            output.push('\n');
+        }
+    }
+
+    output.pop();
+    (n >= 1 && occurrence >= n).then_some(output)
+}
+
 #[cfg(test)]
 mod tests {
     use super::{
-        take_anchored_lines, take_lines, take_rustdoc_include_anchored_lines,
-        take_rustdoc_include_lines,
+        expand_tabs, strip_comment_lines, strip_hidden_lines, take_anchored_lines, take_lines,
+        take_nth_anchored_lines, take_regex_region, take_rustdoc_include_anchored_lines,
+        take_rustdoc_include_lines, take_rustdoc_include_nth_anchored_lines, trim_blank_lines,
+        trim_trailing_whitespace,
     };
+    use regex::Regex;
+
+    #[test]
+    fn take_nth_anchored_lines_test() {
+        let s =
+            "ANCHOR: test\nfirst\nANCHOR_END: test\nLorem\nANCHOR: test\nsecond\nANCHOR_END: test";
+        assert_eq!(
+            take_nth_anchored_lines(s, "test", 1).as_deref(),
+            Some("first")
+        );
+        assert_eq!(
+            take_nth_anchored_lines(s, "test", 2).as_deref(),
+            Some("second")
+        );
+        assert_eq!(take_nth_anchored_lines(s, "test", 3), None);
+        assert_eq!(take_nth_anchored_lines(s, "test", 0), None);
+        assert_eq!(take_nth_anchored_lines(s, "other", 1), None);
+    }
+
+    #[test]
+    fn take_rustdoc_include_nth_anchored_lines_test() {
+        let s =
+            "ANCHOR: test\nfirst\nANCHOR_END: test\nLorem\nANCHOR: test\nsecond\nANCHOR_END: test";
+        assert_eq!(
+            take_rustdoc_include_nth_anchored_lines(s, "test", 2).as_deref(),
+            Some("# first\n# Lorem\nsecond")
+        );
+        assert_eq!(take_rustdoc_include_nth_anchored_lines(s, "test", 3), None);
+    }
+
+    #[test]
+    fn take_regex_region_test() {
+        let s = "use std::fmt;\n\nfn foo() {\n    bar();\n}\n\nfn baz() {\n}";
+        let start = Regex::new(r"^fn foo\b").unwrap();
+        let end = Regex::new(r"^}").unwrap();
+        assert_eq!(
+            take_regex_region(s, &start, &end).as_deref(),
+            Some("fn foo() {\n    bar();\n}")
+        );
+
+        // The start line is not considered for the end pattern.
+        let same = Regex::new(r"^fn ").unwrap();
+        assert_eq!(
+            take_regex_region(s, &same, &same).as_deref(),
+            Some("fn foo() {\n    bar();\n}\n\nfn baz() {")
+        );
+
+        let missing = Regex::new(r"^fn qux\b").unwrap();
+        assert_eq!(take_regex_region(s, &missing, &end), None);
+        assert_eq!(take_regex_region(s, &start, &missing), None);
+    }
+
+    #[test]
+    fn strip_hidden_lines_test() {
+        let s = "# use std::fmt;\n#\n#![allow(unused)]\n#[derive(Debug)]\nstruct Foo;\n\
+                 ##[not_hidden]\n    # let hidden = 1;\n    let shown = 2;\n#hidden\n";
+        assert_eq!(
+            strip_hidden_lines(s),
+            "#![allow(unused)]\n#[derive(Debug)]\nstruct Foo;\n##[not_hidden]\n    let shown = 2;\n"
+        );
+        assert_eq!(strip_hidden_lines("#\n##"), "##");
+        assert_eq!(strip_hidden_lines("no hidden lines"), "no hidden lines");
+    }
+
+    #[test]
+    fn expand_tabs_test() {
+        let s = "fn main() {\n\tlet s = \"a\tb\";\n\t\tx();\n  \ty();\n}\n";
+        assert_eq!(
+            expand_tabs(s, 4, true),
+            "fn main() {\n    let s = \"a\tb\";\n        x();\n    y();\n}\n"
+        );
+        assert_eq!(
+            expand_tabs(s, 4, false),
+            "fn main() {\n    let s = \"a  b\";\n        x();\n    y();\n}\n"
+        );
+        assert_eq!(expand_tabs("\tx", 2, true), "  x");
+    }
+
+    #[test]
+    fn trim_trailing_whitespace_test() {
+        let s = "fn main() {  \n    let x = 5;\t\n\n    \n}\n";
+        assert_eq!(
+            trim_trailing_whitespace(s),
+            "fn main() {\n    let x = 5;\n\n\n}\n"
+        );
+        let s = "  indented\t \nlast  ";
+        assert_eq!(trim_trailing_whitespace(s), "  indented\nlast");
+        assert_eq!(
+            trim_trailing_whitespace(&take_lines("Lorem  \n  ipsum \ndolor", 1..)),
+            "  ipsum\ndolor"
+        );
+    }
+
+    #[test]
+    fn strip_comment_lines_test() {
+        let s = "// Copyright\n//! Crate docs\nfn main() {\n    // hi\n    let s = \"// not a comment\"; // end\n}\n";
+        assert_eq!(
+            strip_comment_lines(s, "//"),
+            "fn main() {\n    let s = \"// not a comment\"; // end\n}\n"
+        );
+        assert_eq!(strip_comment_lines("# a\nx = 1  # b", "#"), "x = 1  # b");
+    }
+
+    #[test]
+    fn trim_blank_lines_test() {
+        let s = "\n  \nfn main() {\n\n    let x = 5;\n}\n\t\n\n";
+        assert_eq!(trim_blank_lines(s), "fn main() {\n\n    let x = 5;\n}\n");
+        assert_eq!(trim_blank_lines("\n    indented\n"), "    indented\n");
+        assert_eq!(trim_blank_lines("no blank lines"), "no blank lines");
+        assert_eq!(trim_blank_lines("\n \n\t\n"), "");
+        assert_eq!(trim_blank_lines(""), "");
+    }
 
     #[test]
     #[allow(clippy::reversed_empty_ranges)] // Intentionally checking that those are correctly handled
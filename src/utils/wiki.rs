@@ -0,0 +1,256 @@
+//! Wiki-style `[[Title]]` links to the pages and headings of a book.
+
+use super::collapse_whitespace;
+use super::fs::path_to_root;
+use once_cell::sync::Lazy;
+use pulldown_cmark::{Event, Tag};
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// The pages and headings of a book that wiki-style `[[Title]]` links can
+/// point to, by their title.
+///
+/// Titles are matched case-insensitively, with runs of whitespace treated
+/// as a single space.
+#[derive(Debug, Default, Clone)]
+pub struct WikiLinks {
+    pages: HashMap<String, Vec<PathBuf>>,
+    headings: HashMap<String, Vec<(PathBuf, String)>>,
+}
+
+/// Why a wiki-style link couldn't be resolved.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum WikiLinkError {
+    /// No page or heading has the title.
+    Missing,
+    /// Several pages, or several headings, have the title. The destinations
+    /// of the links to them are given.
+    Ambiguous(Vec<String>),
+}
+
+impl fmt::Display for WikiLinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WikiLinkError::Missing => write!(f, "no page or heading has this title"),
+            WikiLinkError::Ambiguous(destinations) => write!(
+                f,
+                "several pages or headings have this title: {}",
+                destinations.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for WikiLinkError {}
+
+fn normalize_title(title: &str) -> String {
+    collapse_whitespace(title.trim()).to_lowercase()
+}
+
+/// The destination of a link from the page at `from` to `page`, both
+/// relative to the source directory.
+fn destination(from: &Path, page: &Path, id: Option<&str>) -> String {
+    let mut dest = path_to_root(from);
+    dest.push_str(&page.to_string_lossy().replace('\\', "/"));
+    if let Some(id) = id {
+        dest.push('#');
+        dest.push_str(id);
+    }
+    dest
+}
+
+impl WikiLinks {
+    /// Creates an empty set of link targets.
+    pub fn new() -> WikiLinks {
+        WikiLinks::default()
+    }
+
+    /// Adds a page of the book, like `guide/intro.md`, with its `title`.
+    pub fn add_page(&mut self, title: &str, page: &Path) {
+        self.pages
+            .entry(normalize_title(title))
+            .or_default()
+            .push(page.to_path_buf());
+    }
+
+    /// Adds a heading with the `title` and `id` on a page of the book.
+    pub fn add_heading(&mut self, title: &str, page: &Path, id: &str) {
+        self.headings
+            .entry(normalize_title(title))
+            .or_default()
+            .push((page.to_path_buf(), id.to_string()));
+    }
+
+    /// Returns the destination of a link with `title` from the page `from`,
+    /// like `../guide/intro.md#setup`.
+    ///
+    /// Pages take precedence over headings, so a page whose first heading
+    /// repeats its title is linked to as a whole.
+    pub fn resolve(&self, title: &str, from: &Path) -> Result<String, WikiLinkError> {
+        let title = normalize_title(title);
+        let mut destinations: Vec<String> = match self.pages.get(&title) {
+            Some(pages) => pages
+                .iter()
+                .map(|page| destination(from, page, None))
+                .collect(),
+            None => self
+                .headings
+                .get(&title)
+                .into_iter()
+                .flatten()
+                .map(|(page, id)| destination(from, page, Some(id)))
+                .collect(),
+        };
+        destinations.sort();
+        destinations.dedup();
+        match destinations.len() {
+            0 => Err(WikiLinkError::Missing),
+            1 => Ok(destinations.remove(0)),
+            _ => Err(WikiLinkError::Ambiguous(destinations)),
+        }
+    }
+}
+
+/// Rewrites the wiki-style `[[Title]]` links in the markdown `text` of the
+/// page at `page` into links to the page or heading with the title in
+/// `links`.
+///
+/// Links that can't be resolved are left as they are and returned with the
+/// reason. Code, raw HTML and links are left untouched.
+pub fn link_wiki_references<'a>(
+    text: &'a str,
+    page: &Path,
+    links: &WikiLinks,
+) -> (Cow<'a, str>, Vec<(String, WikiLinkError)>) {
+    static WIKI_LINK: Lazy<Regex> = Lazy::new(|| Regex::new(r"\[\[([^\[\]\n]+)\]\]").unwrap());
+
+    if !text.contains("[[") {
+        return (Cow::Borrowed(text), Vec::new());
+    }
+    let skipped: Vec<Range<usize>> = super::new_cmark_parser(text, false)
+        .into_offset_iter()
+        .filter(|(event, _)| {
+            matches!(
+                event,
+                Event::Start(Tag::CodeBlock(_) | Tag::HtmlBlock | Tag::Link { .. })
+                    | Event::Code(_)
+                    | Event::InlineHtml(_)
+            )
+        })
+        .map(|(_, range)| range)
+        .collect();
+
+    let mut unresolved = Vec::new();
+    let mut result = String::with_capacity(text.len());
+    let mut last = 0;
+    for caps in WIKI_LINK.captures_iter(text) {
+        let whole = caps.get(0).unwrap();
+        let escaped = text[..whole.start()].ends_with('\\');
+        if escaped
+            || skipped
+                .iter()
+                .any(|range| range.start < whole.end() && whole.start() < range.end)
+        {
+            continue;
+        }
+        let title = &caps[1];
+        match links.resolve(title, page) {
+            Ok(dest) => {
+                result.push_str(&text[last..whole.start()]);
+                result.push_str(&format!("[{title}](<{dest}>)"));
+                last = whole.end();
+            }
+            Err(e) => unresolved.push((title.to_string(), e)),
+        }
+    }
+    if last == 0 {
+        return (Cow::Borrowed(text), unresolved);
+    }
+    result.push_str(&text[last..]);
+    (Cow::Owned(result), unresolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn links() -> WikiLinks {
+        let mut links = WikiLinks::new();
+        links.add_page("Getting Started", Path::new("guide/start.md"));
+        links.add_heading(
+            "Getting started",
+            Path::new("guide/start.md"),
+            "getting-started",
+        );
+        links.add_heading(
+            "Installing  the Tools",
+            Path::new("guide/start.md"),
+            "install",
+        );
+        links.add_heading("Examples", Path::new("guide/start.md"), "examples");
+        links.add_heading("Examples", Path::new("reference.md"), "examples");
+        links
+    }
+
+    #[test]
+    fn resolves_titles() {
+        let links = links();
+        let from = Path::new("guide/other.md");
+        assert_eq!(
+            links.resolve("getting   STARTED", from),
+            Ok("../guide/start.md".to_string())
+        );
+        assert_eq!(
+            links.resolve("Installing the tools", Path::new("index.md")),
+            Ok("guide/start.md#install".to_string())
+        );
+        assert_eq!(
+            links.resolve("Examples", from),
+            Err(WikiLinkError::Ambiguous(vec![
+                "../guide/start.md#examples".to_string(),
+                "../reference.md#examples".to_string(),
+            ]))
+        );
+        assert_eq!(links.resolve("Nowhere", from), Err(WikiLinkError::Missing));
+    }
+
+    #[test]
+    fn links_wiki_references() {
+        let text = "See [[Installing the Tools]], [[Examples]] and [[Nowhere]].\n\n\
+                    `[[Examples]]` \\[[Getting Started]]\n\n\
+                    ```\n[[Getting Started]]\n```\n";
+        let (linked, unresolved) = link_wiki_references(text, Path::new("index.md"), &links());
+        assert_eq!(
+            linked,
+            "See [Installing the Tools](<guide/start.md#install>), [[Examples]] and \
+             [[Nowhere]].\n\n\
+             `[[Examples]]` \\[[Getting Started]]\n\n\
+             ```\n[[Getting Started]]\n```\n"
+        );
+        assert_eq!(
+            unresolved,
+            vec![
+                (
+                    "Examples".to_string(),
+                    WikiLinkError::Ambiguous(vec![
+                        "guide/start.md#examples".to_string(),
+                        "reference.md#examples".to_string(),
+                    ])
+                ),
+                ("Nowhere".to_string(), WikiLinkError::Missing),
+            ]
+        );
+
+        // The links are rendered like any other link to a page.
+        let html = crate::utils::render_markdown(&linked, false);
+        assert!(html.starts_with(
+            "<p>See <a href=\"guide/start.html#install\">Installing the Tools</a>, \
+             [[Examples]] and [[Nowhere]].</p>"
+        ));
+    }
+}
@@ -126,14 +126,14 @@ fn check_correct_relative_links_in_print_page() {
     assert_contains_strings(
         first.join("print.html"),
         &[
-            r##"<a href="#first-nested">the first section</a>,"##,
+            r##"<a href="#first/nested">the first section</a>,"##,
             r##"<a href="second/../../std/foo/bar.html">outside</a>"##,
             r##"<img src="second/../images/picture.png" alt="Some image" />"##,
-            r##"<a href="#second-nested-some-section">fragment link</a>"##,
-            r##"<a href="#first-markdown">HTML Link</a>"##,
+            r##"<a href="#second/nested:some-section">fragment link</a>"##,
+            r##"<a href="#first/markdown">HTML Link</a>"##,
             r##"<img src="second/../images/picture.png" alt="raw html">"##,
-            r##"<sup class="footnote-reference"><a href="#first-markdown-1">1</a></sup>"##,
-            r##"<sup class="footnote-reference"><a href="#first-markdown-word">2</a></sup>"##,
+            r##"<sup class="footnote-reference"><a href="#first/markdown:1">1</a></sup>"##,
+            r##"<sup class="footnote-reference"><a href="#first/markdown:word">2</a></sup>"##,
         ],
     );
 }
@@ -401,6 +401,27 @@ fn able_to_include_files_in_chapters() {
     assert_doesnt_contain_strings(&includes, &["{{#include ../SUMMARY.md::}}"]);
 }
 
+#[test]
+fn include_start_line() {
+    let temp = TempFileBuilder::new().prefix("mdbook").tempdir().unwrap();
+    let src = temp.path().join("src");
+    write_file(&src, "SUMMARY.md", b"- [Chapter](chapter.md)\n").unwrap();
+    let lines: Vec<String> = (1..=120).map(|n| format!("let x{n} = {n};")).collect();
+    write_file(&src, "code.rs", lines.join("\n").as_bytes()).unwrap();
+    write_file(
+        &src,
+        "chapter.md",
+        b"# Chapter\n\n{{#include code.rs:100:110 lang=rust,editable start-line}}\n",
+    )
+    .unwrap();
+    MDBook::load(temp.path()).unwrap().build().unwrap();
+
+    assert_contains_strings(
+        temp.path().join("book/chapter.html"),
+        &[r#"<code class="language-rust editable" data-start-line="100">"#],
+    );
+}
+
 /// Ensure cyclic includes are capped so that no exceptions occur
 #[test]
 fn recursive_includes_are_capped() {
@@ -511,6 +532,125 @@ fn no_index_for_print_html() {
     assert_doesnt_contain_strings(index_html, &[r##"noindex"##]);
 }
 
+#[test]
+fn print_page_breaks() {
+    const INLINE: &str = r#"<div style="break-before: page; page-break-before: always;"></div>"#;
+    const CLASS: &str = r#"<div class="page-break"></div>"#;
+
+    let temp = DummyBook::new().build().unwrap();
+    let md = MDBook::load(temp.path()).unwrap();
+    md.build().unwrap();
+    let print_html = temp.path().join("book/print.html");
+    assert_contains_strings(&print_html, &[INLINE]);
+    assert_doesnt_contain_strings(&print_html, &[CLASS]);
+
+    let temp = DummyBook::new().build().unwrap();
+    let mut md = MDBook::load(temp.path()).unwrap();
+    md.config
+        .set("output.html.print.page-break", false)
+        .unwrap();
+    md.build().unwrap();
+    let print_html = temp.path().join("book/print.html");
+    assert_doesnt_contain_strings(&print_html, &[INLINE, CLASS]);
+
+    let temp = DummyBook::new().build().unwrap();
+    let mut md = MDBook::load(temp.path()).unwrap();
+    md.config
+        .set("output.html.print.page-break-style", "class")
+        .unwrap();
+    md.build().unwrap();
+    let print_html = temp.path().join("book/print.html");
+    assert_contains_strings(&print_html, &[CLASS]);
+    assert_doesnt_contain_strings(&print_html, &[INLINE]);
+}
+
+#[test]
+fn print_page_endnotes() {
+    let temp = TempFileBuilder::new().prefix("mdbook").tempdir().unwrap();
+    let src = temp.path().join("src");
+    write_file(
+        &src,
+        "SUMMARY.md",
+        b"[First](first.md)\n- [Second](nested/second.md)\n",
+    )
+    .unwrap();
+    write_file(
+        &src,
+        "first.md",
+        b"# First\n\nOne[^note] and two[^other].\n\n[^note]: First note.\n[^other]: Second note.\n",
+    )
+    .unwrap();
+    write_file(
+        &src.join("nested"),
+        "second.md",
+        b"# Second\n\nThree[^note].\n\n[^note]: Third note.\n",
+    )
+    .unwrap();
+    let config = Config::from_str("output.html.print.endnotes = true").unwrap();
+    MDBook::load_with_config(temp.path(), config)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    let print_html = temp.path().join("book/print.html");
+    assert_contains_strings(
+        &print_html,
+        &[
+            r##"One<sup class="footnote-reference"><a href="#first:note">1</a></sup>"##,
+            r##"two<sup class="footnote-reference"><a href="#first:other">2</a></sup>"##,
+            r##"Three<sup class="footnote-reference"><a href="#nested/second:note">3</a></sup>"##,
+            "<div class=\"footnotes endnotes\">\n\
+             <div class=\"footnote-definition\" id=\"first:note\">\
+             <sup class=\"footnote-definition-label\">1</sup>\n<p>First note.</p>\n</div>\n\
+             <div class=\"footnote-definition\" id=\"first:other\">\
+             <sup class=\"footnote-definition-label\">2</sup>\n<p>Second note.</p>\n</div>\n\
+             <div class=\"footnote-definition\" id=\"nested/second:note\">\
+             <sup class=\"footnote-definition-label\">3</sup>\n<p>Third note.</p>\n</div>\n\
+             </div>",
+        ],
+    );
+    // The chapters themselves keep their own footnotes.
+    let second_html = temp.path().join("book/nested/second.html");
+    assert_contains_strings(
+        &second_html,
+        &[r##"<sup class="footnote-reference"><a href="#note">1</a></sup>"##],
+    );
+    assert_doesnt_contain_strings(&second_html, &["endnotes"]);
+}
+
+#[test]
+fn front_matter_page_class() {
+    let temp = TempFileBuilder::new().prefix("mdbook").tempdir().unwrap();
+    let src = temp.path().join("src");
+    write_file(
+        &src,
+        "SUMMARY.md",
+        b"[Landing](landing.md)\n- [Plain](plain.md)\n",
+    )
+    .unwrap();
+    write_file(
+        &src,
+        "landing.md",
+        b"---\nclass: [landing, wide]\n---\n# Landing\n",
+    )
+    .unwrap();
+    write_file(&src, "plain.md", b"# Plain\n").unwrap();
+    let config = Config::from_str("output.html.markdown.front-matter = true").unwrap();
+    MDBook::load_with_config(temp.path(), config)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    assert_contains_strings(
+        temp.path().join("book/landing.html"),
+        &[r#"<body class="sidebar-visible no-js landing wide">"#],
+    );
+    assert_contains_strings(
+        temp.path().join("book/plain.html"),
+        &[r#"<body class="sidebar-visible no-js">"#],
+    );
+}
+
 #[test]
 fn markdown_options() {
     let temp = DummyBook::new().build().unwrap();